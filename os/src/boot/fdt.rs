@@ -0,0 +1,336 @@
+//! A reader for the flattened device tree the SBI passes in `a1`, just
+//! enough to walk its nodes and a few of their properties. Nothing is
+//! allocated, so it can run before the heap is set up.
+
+const MAGIC: u32 = 0xd00d_feed;
+const HEADER_SIZE: usize = 40;
+
+const BEGIN_NODE: u32 = 1;
+const END_NODE: u32 = 2;
+const PROP: u32 = 3;
+const NOP: u32 = 4;
+const END: u32 = 9;
+
+/// Deeper trees are taken as malformed.
+const MAX_DEPTH: usize = 16;
+
+fn be32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let b = bytes.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn align4(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+/// A string property without its terminating NUL.
+fn string(prop: &[u8]) -> &[u8] {
+    match prop.split_last() {
+        Some((0, rest)) => rest,
+        _ => prop,
+    }
+}
+
+/// `count` big-endian cells from `bytes`, at most two of them.
+fn cells(bytes: &[u8], count: usize) -> usize {
+    (0..count).fold(0, |value, i| {
+        (value << 32) | be32(bytes, 4 * i).unwrap_or(0) as usize
+    })
+}
+
+pub struct Fdt<'a> {
+    structs: &'a [u8],
+    strings: &'a [u8],
+}
+
+/// A node with the properties the kernel looks at. Those it does not have
+/// are empty.
+pub struct Node<'a> {
+    pub name: &'a str,
+    /// 0 for the root
+    pub depth: usize,
+    /// Cells of an address and a size in `reg`, from the parent
+    address_cells: usize,
+    size_cells: usize,
+    reg: &'a [u8],
+    compatible: &'a [u8],
+    device_type: &'a [u8],
+    status: &'a [u8],
+    interrupts: &'a [u8],
+    bootargs: &'a [u8],
+}
+
+impl<'a> Fdt<'a> {
+    /// The blob at physical address `addr`, `None` if there is none.
+    ///
+    /// # Safety
+    ///
+    /// `addr` must be 0 or readable, identity mapped memory that is not
+    /// written while the returned `Fdt` is alive.
+    pub unsafe fn from_addr(addr: usize) -> Option<Self> {
+        if addr == 0 || addr % 8 != 0 {
+            return None;
+        }
+        let header = core::slice::from_raw_parts(addr as *const u8, HEADER_SIZE);
+        if be32(header, 0)? != MAGIC {
+            return None;
+        }
+        let total = be32(header, 4)? as usize;
+        Self::new(core::slice::from_raw_parts(addr as *const u8, total))
+    }
+    pub fn new(blob: &'a [u8]) -> Option<Self> {
+        if be32(blob, 0)? != MAGIC || (be32(blob, 4)? as usize) > blob.len() {
+            return None;
+        }
+        let structs_at = be32(blob, 8)? as usize;
+        let strings_at = be32(blob, 12)? as usize;
+        let strings_size = be32(blob, 32)? as usize;
+        let structs_size = be32(blob, 36)? as usize;
+        Some(Self {
+            structs: blob.get(structs_at..structs_at.checked_add(structs_size)?)?,
+            strings: blob.get(strings_at..strings_at.checked_add(strings_size)?)?,
+        })
+    }
+    fn prop_name(&self, offset: usize) -> Option<&'a [u8]> {
+        let rest = self.strings.get(offset..)?;
+        rest.split(|&b| b == 0).next()
+    }
+    /// Call `f` on every node, parents before their children. `None` if
+    /// the blob turns out malformed, possibly after some nodes were seen.
+    pub fn for_each_node(&self, mut f: impl FnMut(&Node<'a>)) -> Option<()> {
+        let structs = self.structs;
+        // cells for the children of the open node at each depth
+        let mut child_cells = [(2, 1); MAX_DEPTH];
+        let mut open = 0;
+        let mut pending: Option<Node<'a>> = None;
+        let mut offset = 0;
+        loop {
+            let token = be32(structs, offset)?;
+            offset += 4;
+            match token {
+                BEGIN_NODE => {
+                    if let Some(node) = pending.take() {
+                        f(&node);
+                    }
+                    if open == MAX_DEPTH {
+                        return None;
+                    }
+                    let rest = structs.get(offset..)?;
+                    let len = rest.iter().position(|&b| b == 0)?;
+                    let name = core::str::from_utf8(&rest[..len]).ok()?;
+                    offset = align4(offset + len + 1);
+                    let (address_cells, size_cells) = if open == 0 {
+                        (2, 1)
+                    } else {
+                        child_cells[open - 1]
+                    };
+                    child_cells[open] = (2, 1);
+                    pending = Some(Node {
+                        name,
+                        depth: open,
+                        address_cells,
+                        size_cells,
+                        reg: &[],
+                        compatible: &[],
+                        device_type: &[],
+                        status: &[],
+                        interrupts: &[],
+                        bootargs: &[],
+                    });
+                    open += 1;
+                }
+                END_NODE => {
+                    if let Some(node) = pending.take() {
+                        f(&node);
+                    }
+                    open = open.checked_sub(1)?;
+                }
+                PROP => {
+                    let len = be32(structs, offset)? as usize;
+                    let name = self.prop_name(be32(structs, offset + 4)? as usize)?;
+                    let value = structs.get(offset + 8..offset + 8 + len)?;
+                    offset = align4(offset + 8 + len);
+                    let node = pending.as_mut()?;
+                    match name {
+                        b"#address-cells" => child_cells[open - 1].0 = cells(value, 1),
+                        b"#size-cells" => child_cells[open - 1].1 = cells(value, 1),
+                        b"reg" => node.reg = value,
+                        b"compatible" => node.compatible = value,
+                        b"device_type" => node.device_type = value,
+                        b"status" => node.status = value,
+                        b"interrupts" => node.interrupts = value,
+                        b"bootargs" => node.bootargs = value,
+                        _ => {}
+                    }
+                }
+                NOP => {}
+                END => {
+                    if let Some(node) = pending.take() {
+                        f(&node);
+                    }
+                    return Some(());
+                }
+                _ => return None,
+            }
+        }
+    }
+}
+
+impl<'a> Node<'a> {
+    /// `(address, size)` of each range in `reg`.
+    pub fn reg(&self) -> impl Iterator<Item = (usize, usize)> + 'a {
+        let (address_cells, size_cells) = (self.address_cells, self.size_cells);
+        let entry = 4 * (address_cells + size_cells);
+        let reg = if entry == 0 || address_cells > 2 || size_cells > 2 {
+            &[]
+        } else {
+            self.reg
+        };
+        reg.chunks_exact(entry.max(1)).map(move |entry| {
+            (
+                cells(entry, address_cells),
+                cells(&entry[4 * address_cells..], size_cells),
+            )
+        })
+    }
+    pub fn is_compatible(&self, with: &str) -> bool {
+        self.compatible
+            .split(|&b| b == 0)
+            .any(|compatible| compatible == with.as_bytes())
+    }
+    pub fn is_device_type(&self, device_type: &str) -> bool {
+        string(self.device_type) == device_type.as_bytes()
+    }
+    /// Nodes without a `status` are enabled.
+    pub fn is_enabled(&self) -> bool {
+        let status = string(self.status);
+        status.is_empty() || status == b"okay" || status == b"ok"
+    }
+    /// The first cell of `interrupts`.
+    pub fn interrupt(&self) -> Option<usize> {
+        be32(self.interrupts, 0).map(|irq| irq as usize)
+    }
+    /// The kernel command line, in `/chosen`.
+    pub fn bootargs(&self) -> &'a [u8] {
+        string(self.bootargs)
+    }
+}
+
+/// Node names are `name@unit-address`.
+pub fn node_name(name: &str) -> &str {
+    name.split('@').next().unwrap_or(name)
+}
+
+#[allow(unused)]
+pub fn fdt_test() {
+    use alloc::vec::Vec;
+
+    fn push32(blob: &mut Vec<u8>, value: u32) {
+        blob.extend_from_slice(&value.to_be_bytes());
+    }
+    fn begin(blob: &mut Vec<u8>, name: &str) {
+        push32(blob, BEGIN_NODE);
+        blob.extend_from_slice(name.as_bytes());
+        blob.push(0);
+        blob.resize(align4(blob.len()), 0);
+    }
+    fn prop(blob: &mut Vec<u8>, strings: &mut Vec<u8>, name: &str, value: &[u8]) {
+        push32(blob, PROP);
+        push32(blob, value.len() as u32);
+        push32(blob, strings.len() as u32);
+        strings.extend_from_slice(name.as_bytes());
+        strings.push(0);
+        blob.extend_from_slice(value);
+        blob.resize(align4(blob.len()), 0);
+    }
+    fn be_cells(cells: &[u32]) -> Vec<u8> {
+        cells.iter().flat_map(|cell| cell.to_be_bytes()).collect()
+    }
+
+    let (mut structs, mut strings) = (Vec::new(), Vec::new());
+    begin(&mut structs, "");
+    prop(
+        &mut structs,
+        &mut strings,
+        "#address-cells",
+        &be_cells(&[2]),
+    );
+    prop(&mut structs, &mut strings, "#size-cells", &be_cells(&[2]));
+    begin(&mut structs, "memory@80000000");
+    prop(&mut structs, &mut strings, "device_type", b"memory\0");
+    prop(
+        &mut structs,
+        &mut strings,
+        "reg",
+        &be_cells(&[0, 0x8000_0000, 0, 0x800_0000]),
+    );
+    push32(&mut structs, END_NODE);
+    begin(&mut structs, "soc");
+    prop(
+        &mut structs,
+        &mut strings,
+        "#address-cells",
+        &be_cells(&[1]),
+    );
+    prop(&mut structs, &mut strings, "#size-cells", &be_cells(&[1]));
+    begin(&mut structs, "uart@10000000");
+    prop(&mut structs, &mut strings, "compatible", b"ns16550a\0");
+    prop(&mut structs, &mut strings, "interrupts", &be_cells(&[10]));
+    prop(
+        &mut structs,
+        &mut strings,
+        "reg",
+        &be_cells(&[0x1000_0000, 0x100]),
+    );
+    prop(&mut structs, &mut strings, "status", b"disabled\0");
+    push32(&mut structs, END_NODE);
+    push32(&mut structs, END_NODE);
+    push32(&mut structs, END_NODE);
+    push32(&mut structs, END);
+
+    let mut blob = Vec::new();
+    let structs_at = HEADER_SIZE as u32;
+    let strings_at = structs_at + structs.len() as u32;
+    let total = strings_at + strings.len() as u32;
+    for &field in [MAGIC, total, structs_at, strings_at, 0, 17, 16, 0].iter() {
+        push32(&mut blob, field);
+    }
+    push32(&mut blob, strings.len() as u32);
+    push32(&mut blob, structs.len() as u32);
+    blob.extend_from_slice(&structs);
+    blob.extend_from_slice(&strings);
+
+    let fdt = Fdt::new(&blob).unwrap();
+    let mut seen = Vec::new();
+    fdt.for_each_node(|node| {
+        let reg: Vec<_> = node.reg().collect();
+        seen.push((node.name, node.depth, reg, node.is_enabled()));
+        if node_name(node.name) == "memory" {
+            assert!(node.is_device_type("memory"));
+        }
+        if node_name(node.name) == "uart" {
+            assert!(node.is_compatible("ns16550a") && !node.is_compatible("ns16550"));
+            assert_eq!(node.interrupt(), Some(10));
+        }
+    })
+    .unwrap();
+    assert_eq!(
+        seen,
+        [
+            ("", 0, Vec::new(), true),
+            (
+                "memory@80000000",
+                1,
+                [(0x8000_0000, 0x800_0000)].to_vec(),
+                true
+            ),
+            ("soc", 1, Vec::new(), true),
+            ("uart@10000000", 2, [(0x1000_0000, 0x100)].to_vec(), false),
+        ]
+    );
+    // cut short, or not a device tree at all
+    assert!(Fdt::new(&blob[..blob.len() - 1]).is_none());
+    blob[0] = 0;
+    assert!(Fdt::new(&blob).is_none());
+    debug!("fdt_test passed!");
+}
@@ -0,0 +1,178 @@
+//! What the kernel knows about the machine it boots on: memory, devices
+//! and harts, read from the device tree the SBI passes along. Whatever the
+//! tree does not say, or all of it if there is no tree, comes from the
+//! defaults of the board the kernel was built for.
+
+pub mod fdt;
+
+use crate::config::{CPU_NUM, FAST_MEMORY};
+use fdt::{node_name, Fdt};
+use spin::Once;
+
+const MAX_MEMORY: usize = 4;
+const MAX_UARTS: usize = 8;
+const MAX_BOOTARGS: usize = 256;
+
+#[derive(Debug)]
+pub struct Machine {
+    /// `[start, end)` of each main memory region
+    memory: [(usize, usize); MAX_MEMORY],
+    memory_len: usize,
+    /// Base and interrupt of each UART
+    uarts: [(usize, usize); MAX_UARTS],
+    uarts_len: usize,
+    pub plic: usize,
+    /// At most `CPU_NUM`
+    pub harts: usize,
+    /// The kernel command line, cut at `MAX_BOOTARGS` bytes
+    bootargs: [u8; MAX_BOOTARGS],
+    bootargs_len: usize,
+}
+
+impl Machine {
+    #[cfg(feature = "board_qemu")]
+    fn board_default() -> Self {
+        let mut machine = Self::empty(0xc00_0000);
+        machine.add_memory(0x8000_0000, 0x8080_0000);
+        machine.add_uart(0x1000_0200, 12);
+        machine
+    }
+    #[cfg(feature = "board_lrv")]
+    fn board_default() -> Self {
+        let mut machine = Self::empty(0xc00_0000);
+        machine.add_memory(0x1_0000_0000, 0x1_0080_0000);
+        machine.add_uart(0x6000_1000, 4);
+        machine
+    }
+    fn empty(plic: usize) -> Self {
+        Self {
+            memory: [(0, 0); MAX_MEMORY],
+            memory_len: 0,
+            uarts: [(0, 0); MAX_UARTS],
+            uarts_len: 0,
+            plic,
+            harts: CPU_NUM,
+            bootargs: [0; MAX_BOOTARGS],
+            bootargs_len: 0,
+        }
+    }
+    fn add_memory(&mut self, start: usize, end: usize) {
+        if self.memory_len < MAX_MEMORY && start < end {
+            self.memory[self.memory_len] = (start, end);
+            self.memory_len += 1;
+        }
+    }
+    fn add_uart(&mut self, base: usize, irq: usize) {
+        if self.uarts_len < MAX_UARTS {
+            self.uarts[self.uarts_len] = (base, irq);
+            self.uarts_len += 1;
+        }
+    }
+    fn set_bootargs(&mut self, bootargs: &[u8]) {
+        let len = bootargs.len().min(MAX_BOOTARGS);
+        self.bootargs[..len].copy_from_slice(&bootargs[..len]);
+        self.bootargs_len = len;
+    }
+    /// The board defaults, overridden by what `fdt` describes.
+    fn from_fdt(fdt: &Fdt) -> Option<Self> {
+        let mut found = Self::empty(0);
+        let mut harts = 0;
+        fdt.for_each_node(|node| {
+            if !node.is_enabled() {
+                return;
+            }
+            if node.is_device_type("memory") {
+                for (start, size) in node.reg() {
+                    found.add_memory(start, start.saturating_add(size));
+                }
+            } else if node.is_device_type("cpu") {
+                harts += 1;
+            } else if node.is_compatible("riscv,plic0") || node.is_compatible("sifive,plic-1.0.0") {
+                if let Some((base, _)) = node.reg().next() {
+                    found.plic = base;
+                }
+            } else if node.depth == 1 && node_name(node.name) == "chosen" {
+                found.set_bootargs(node.bootargs());
+            } else if matches!(node_name(node.name), "uart" | "serial") {
+                if let (Some((base, _)), Some(irq)) = (node.reg().next(), node.interrupt()) {
+                    found.add_uart(base, irq);
+                }
+            }
+        })?;
+        let mut machine = Self::board_default();
+        if found.memory_len > 0 {
+            machine.memory = found.memory;
+            machine.memory_len = found.memory_len;
+        }
+        if found.uarts_len > 0 {
+            machine.uarts = found.uarts;
+            machine.uarts_len = found.uarts_len;
+        }
+        if found.plic != 0 {
+            machine.plic = found.plic;
+        }
+        if harts > 0 {
+            machine.harts = harts.min(CPU_NUM);
+        }
+        machine.bootargs = found.bootargs;
+        machine.bootargs_len = found.bootargs_len;
+        Some(machine)
+    }
+    pub fn memory(&self) -> &[(usize, usize)] {
+        &self.memory[..self.memory_len]
+    }
+    /// `[start, end)` of the main memory left for frames: regions below
+    /// `kernel_end` hold the firmware and the kernel, and fast memory is
+    /// handed out on its own.
+    pub fn free_memory(&self, kernel_end: usize) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.memory()
+            .iter()
+            .filter(move |&&(_, end)| end > kernel_end)
+            .filter(|&&(start, end)| {
+                !FAST_MEMORY
+                    .iter()
+                    .any(|&(fast_start, fast_end)| fast_start < end && start < fast_end)
+            })
+            .map(move |&(start, end)| (start.max(kernel_end), end))
+    }
+    /// `value` of the first `key=value` among the space separated boot
+    /// arguments.
+    pub fn bootarg(&self, key: &str) -> Option<&str> {
+        let bootargs = core::str::from_utf8(&self.bootargs[..self.bootargs_len]).ok()?;
+        bootargs.split_whitespace().find_map(|arg| {
+            let mut parts = arg.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(name), Some(value)) if name == key => Some(value),
+                _ => None,
+            }
+        })
+    }
+    /// Base of the UART raising `irq`.
+    pub fn uart(&self, irq: usize) -> Option<usize> {
+        self.uarts[..self.uarts_len]
+            .iter()
+            .find(|&&(_, uart_irq)| uart_irq == irq)
+            .map(|&(base, _)| base)
+    }
+}
+
+static MACHINE: Once<Machine> = Once::new();
+
+/// Read the device tree at `dtb`, 0 if the SBI passed none. Called once by
+/// the boot hart, after the bss is cleared and before memory is set up.
+pub fn init(dtb: usize) {
+    let machine = MACHINE.call_once(|| {
+        match unsafe { Fdt::from_addr(dtb) }.and_then(|fdt| Machine::from_fdt(&fdt)) {
+            Some(machine) => machine,
+            None => {
+                warn!("no device tree at {:#x}, using the board defaults", dtb);
+                Machine::board_default()
+            }
+        }
+    });
+    debug!("{:x?}", machine);
+}
+
+pub fn machine() -> &'static Machine {
+    MACHINE.get().expect("boot::init has not run")
+}
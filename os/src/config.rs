@@ -1,17 +1,26 @@
 pub const USER_STACK_SIZE: usize = 0x4000;
+pub const USER_STACK_LIMIT: usize = 0x10_0000;
+pub const USER_HEAP_SIZE: usize = 0x10_0000;
 pub const KERNEL_STACK_SIZE: usize = 0x4000;
 pub const KERNEL_HEAP_SIZE: usize = 0x20_0000;
 
+/// `[start, end)` of the physical memory regions faster than main memory,
+/// handed out for `Placement::Fast`.
 #[cfg(feature = "board_qemu")]
-pub const MEMORY_END: usize = 0x80800000;
+pub const FAST_MEMORY: &[(usize, usize)] = &[];
 
+/// The on-chip SRAM, next to the DDR the kernel runs from.
 #[cfg(feature = "board_lrv")]
-pub const MEMORY_END: usize = 0x100800000;
+pub const FAST_MEMORY: &[(usize, usize)] = &[(0x2000_0000, 0x2004_0000)];
 
 pub const PAGE_SIZE: usize = 0x1000;
 pub const PAGE_SIZE_BITS: usize = 0xc;
 
 pub const TRAMPOLINE: usize = usize::MAX - PAGE_SIZE + 1;
+/// `[KERNEL_VMM_START, KERNEL_VMM_END)` of the kernel space is left to
+/// `vmalloc`, far below the kernel stacks under the trampoline.
+pub const KERNEL_VMM_START: usize = 0xffff_ffc0_0000_0000;
+pub const KERNEL_VMM_END: usize = KERNEL_VMM_START + 0x4000_0000;
 pub const TRAP_CONTEXT: usize = TRAMPOLINE - PAGE_SIZE;
 pub const USER_TRAP_BUFFER: usize = TRAP_CONTEXT - PAGE_SIZE;
 
@@ -21,4 +30,14 @@ pub const CLOCK_FREQ: usize = 12500000;
 #[cfg(feature = "board_lrv")]
 pub const CLOCK_FREQ: usize = 10_000_000;
 
+/// The most harts the kernel runs on, see `boot::Machine::harts` for how
+/// many it has.
 pub const CPU_NUM: usize = 4;
+
+/// Policy of the ready queues, unless the `sched=` boot argument names
+/// another: "fifo", "rr", "priority" or "stride".
+#[cfg(not(feature = "sched_stride"))]
+pub const SCHED_POLICY: &str = "priority";
+
+#[cfg(feature = "sched_stride")]
+pub const SCHED_POLICY: &str = "stride";
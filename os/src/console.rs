@@ -34,6 +34,12 @@ macro_rules! colorize {
     };
 }
 
+/// Write straight to the SBI console without taking `STDERR`, for the panic
+/// path where the lock may be held by the hart that panicked.
+pub fn print_unlocked(args: fmt::Arguments) {
+    Stderr.write_fmt(args).unwrap();
+}
+
 /// Use colorize! to print with color
 pub fn print_colorized(args: fmt::Arguments, foreground_color: u8, background_color: u8) {
     STDERR
@@ -56,6 +62,13 @@ macro_rules! println_colorized {
     }
 }
 
+#[macro_export]
+macro_rules! println_unlocked {
+    ($fmt: literal $(, $($arg: tt)+)?) => {
+        $crate::console::print_unlocked(format_args!(concat!($fmt, "\r\n") $(, $($arg)+)?))
+    }
+}
+
 #[macro_export]
 macro_rules! println_hart {
     ($fmt: literal, $hart_id: expr $(, $($arg: tt)+)?) => {
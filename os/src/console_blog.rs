@@ -3,22 +3,102 @@
 use crate::uart;
 use alloc::{collections::VecDeque, sync::Arc};
 use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, Ordering};
 use lazy_static::*;
 use spin::Mutex;
 
 pub const DEFAULT_OUT_BUFFER_SIZE: usize = 10_000;
 pub const DEFAULT_IN_BUFFER_SIZE: usize = 1_000;
+/// Empty polls after which an ESC that nothing followed counts as a key.
+pub const ESCAPE_TIMEOUT_POLLS: usize = 64;
+/// Longest escape sequence the decoder buffers before giving up on it.
+const MAX_ESCAPE_SEQUENCE: usize = 8;
 
 lazy_static! {
     pub static ref IN_BUFFER: Arc<Mutex<VecDeque<u8>>> =
         Arc::new(Mutex::new(VecDeque::with_capacity(DEFAULT_IN_BUFFER_SIZE)));
     pub static ref OUT_BUFFER: Arc<Mutex<VecDeque<u8>>> =
         Arc::new(Mutex::new(VecDeque::with_capacity(DEFAULT_OUT_BUFFER_SIZE)));
+    static ref KEY_DECODER: Mutex<KeyDecoder> = Mutex::new(KeyDecoder::new());
+}
+
+/// Whether `pop_key` decodes escape sequences or hands out raw bytes.
+static ESCAPE_DECODING: AtomicBool = AtomicBool::new(false);
+
+/// Set once no more input will arrive, see `signal_stdin_eof`.
+static STDIN_EOF: AtomicBool = AtomicBool::new(false);
+
+/// Throttles `push_stdout`, see `set_output_rate`.
+static OUTPUT_LIMITER: Mutex<RateLimiter> = Mutex::new(RateLimiter::new());
+
+/// Token bucket holding up to `burst` bytes, refilled by `rate` bytes every
+/// timer tick. A zero `rate` turns it off.
+pub struct RateLimiter {
+    rate: usize,
+    burst: usize,
+    tokens: usize,
+    dropped: usize,
+}
+
+impl RateLimiter {
+    pub const fn new() -> Self {
+        Self {
+            rate: 0,
+            burst: 0,
+            tokens: 0,
+            dropped: 0,
+        }
+    }
+    pub fn configure(&mut self, rate: usize, burst: usize) {
+        self.rate = rate;
+        self.burst = burst.max(rate);
+        self.tokens = self.burst;
+    }
+    /// Take a token for one byte, counting the byte as dropped if there is
+    /// none left.
+    pub fn allow(&mut self) -> bool {
+        if self.rate == 0 {
+            true
+        } else if self.tokens > 0 {
+            self.tokens -= 1;
+            true
+        } else {
+            self.dropped += 1;
+            false
+        }
+    }
+    pub fn refill(&mut self) {
+        self.tokens = (self.tokens + self.rate).min(self.burst);
+    }
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+}
+
+/// Let at most `bytes_per_tick` bytes, after an initial `burst`, through
+/// `push_stdout` per timer tick and drop the rest. Zero disables the limit.
+#[allow(dead_code)]
+pub fn set_output_rate(bytes_per_tick: usize, burst: usize) {
+    OUTPUT_LIMITER.lock().configure(bytes_per_tick, burst);
+}
+
+/// Called on every kernel timer tick.
+pub fn refill_output_rate() {
+    OUTPUT_LIMITER.lock().refill();
+}
+
+/// Bytes `push_stdout` dropped because of the rate limit.
+#[allow(dead_code)]
+pub fn dropped_output_bytes() -> usize {
+    OUTPUT_LIMITER.lock().dropped()
 }
 
 #[cfg(any(feature = "board_qemu", feature = "board_lrv"))]
 #[allow(dead_code)]
 pub fn push_stdout(c: u8) {
+    if !OUTPUT_LIMITER.lock().allow() {
+        return;
+    }
     let uart = uart::UART.lock();
     if !uart.is_transmitter_holding_register_empty_interrupt_enabled() {
         uart.write_byte(c);
@@ -34,6 +114,9 @@ pub fn push_stdout(c: u8) {
 #[cfg(feature = "board_lrv_uartlite")]
 #[allow(dead_code)]
 pub fn push_stdout(c: u8) {
+    if !OUTPUT_LIMITER.lock().allow() {
+        return;
+    }
     let uart = uart::UART.lock();
     if uart.is_tx_fifo_empty() && OUT_BUFFER.lock().is_empty() {
         uart.write_byte(c);
@@ -45,6 +128,40 @@ pub fn push_stdout(c: u8) {
     }
 }
 
+/// Line status register bit set when the transmitter can take another byte.
+#[cfg(any(feature = "board_qemu", feature = "board_lrv"))]
+const LSR_THRE: u8 = 1 << 5;
+
+/// Write out everything in `OUT_BUFFER`, polling the transmitter instead of
+/// waiting for the THRE interrupt. Gives up if either lock is already held.
+#[cfg(any(feature = "board_qemu", feature = "board_lrv"))]
+pub fn flush() {
+    let uart = match uart::UART.try_lock() {
+        Some(uart) => uart,
+        None => return,
+    };
+    if let Some(mut out_buffer) = OUT_BUFFER.try_lock() {
+        while let Some(ch) = out_buffer.pop_front() {
+            while uart.read_lsr() & LSR_THRE == 0 {}
+            uart.write_byte(ch);
+        }
+    }
+}
+
+#[cfg(feature = "board_lrv_uartlite")]
+pub fn flush() {
+    let uart = match uart::UART.try_lock() {
+        Some(uart) => uart,
+        None => return,
+    };
+    if let Some(mut out_buffer) = OUT_BUFFER.try_lock() {
+        while let Some(ch) = out_buffer.pop_front() {
+            while uart.is_tx_fifo_full() {}
+            uart.write_byte(ch);
+        }
+    }
+}
+
 #[allow(dead_code)]
 pub fn pop_stdout() -> u8 {
     let mut out_buffer = OUT_BUFFER.lock();
@@ -59,21 +176,283 @@ pub fn push_stdin(c: u8) {
     }
 }
 
+/// Returns 0 both when no byte is ready and at EOF, use `poll_stdin` to
+/// tell them apart.
 pub fn pop_stdin() -> u8 {
+    try_pop_stdin().unwrap_or(0)
+}
+
+/// Outcome of a non-blocking stdin read.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StdinPoll {
+    Byte(u8),
+    /// Nothing buffered yet, more may come
+    Empty,
+    /// Nothing buffered and no more will come
+    Eof,
+}
+
+/// Read one byte without blocking. Bytes received before EOF was signalled
+/// are still handed out first.
+pub fn poll_stdin() -> StdinPoll {
+    match try_pop_stdin() {
+        Some(ch) => StdinPoll::Byte(ch),
+        None if stdin_eof() => StdinPoll::Eof,
+        None => StdinPoll::Empty,
+    }
+}
+
+/// Mark the end of console input, e.g. when the input script has ended.
+/// Reads report EOF until `clear_stdin_eof`.
+#[allow(dead_code)]
+pub fn signal_stdin_eof() {
+    STDIN_EOF.store(true, Ordering::Release);
+}
+
+#[allow(dead_code)]
+pub fn clear_stdin_eof() {
+    STDIN_EOF.store(false, Ordering::Release);
+}
+
+pub fn stdin_eof() -> bool {
+    STDIN_EOF.load(Ordering::Acquire)
+}
+
+/// Read up to and including a newline into `buf`, spinning while no input
+/// is ready. Returns the number of bytes read, which is 0 only at EOF.
+#[allow(dead_code)]
+pub fn read_line(buf: &mut [u8]) -> usize {
+    let mut len = 0;
+    while len < buf.len() {
+        match poll_stdin() {
+            StdinPoll::Byte(ch) => {
+                buf[len] = ch;
+                len += 1;
+                if ch == b'\n' {
+                    break;
+                }
+            }
+            StdinPoll::Empty => core::hint::spin_loop(),
+            StdinPoll::Eof => break,
+        }
+    }
+    len
+}
+
+fn try_pop_stdin() -> Option<u8> {
+    IN_BUFFER.lock().pop_front()
+}
+
+/// Bytes that can be read right now without waiting. The Rx interrupt
+/// keeps `IN_BUFFER` up to date, so nothing is left in the UART FIFO to add.
+#[allow(dead_code)]
+pub fn stdin_available() -> usize {
+    IN_BUFFER.lock().len()
+}
+
+/// Move every byte `read_byte` yields into `IN_BUFFER`, in order. The UART
+/// Rx interrupt calls this with its FIFO, so input is buffered whether or
+/// not anyone reads. Bytes that do not fit are dropped. Returns the number
+/// of bytes taken from `read_byte`.
+pub fn receive_stdin(mut read_byte: impl FnMut() -> Option<u8>) -> usize {
     let mut in_buffer = IN_BUFFER.lock();
-    if let Some(ch) = in_buffer.pop_front() {
-        ch
-    } else {
-        #[cfg(any(feature = "board_qemu", feature = "board_lrv"))]
-        {
-            // Drain UART Rx FIFO
-            let uart = uart::UART.lock();
-            while let Some(ch_read) = uart.read_byte() {
-                in_buffer.push_back(ch_read);
+    let mut received = 0;
+    while let Some(ch) = read_byte() {
+        if in_buffer.len() < DEFAULT_IN_BUFFER_SIZE {
+            in_buffer.push_back(ch);
+        }
+        received += 1;
+    }
+    received
+}
+
+/// A key press read from the console.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[allow(dead_code)]
+pub enum Key {
+    Char(u8),
+    Escape,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    Insert,
+    Delete,
+    PageUp,
+    PageDown,
+    /// Function keys F1 to F12
+    F(u8),
+}
+
+/// Turns console input into keys, buffering a partial escape sequence
+/// until it is complete.
+pub struct KeyDecoder {
+    sequence: VecDeque<u8>,
+    keys: VecDeque<Key>,
+    idle_polls: usize,
+}
+
+#[allow(dead_code)]
+impl KeyDecoder {
+    pub fn new() -> Self {
+        Self {
+            sequence: VecDeque::with_capacity(MAX_ESCAPE_SEQUENCE),
+            keys: VecDeque::new(),
+            idle_polls: 0,
+        }
+    }
+
+    /// Next decoded key, if any.
+    pub fn pop(&mut self) -> Option<Key> {
+        self.keys.pop_front()
+    }
+
+    pub fn feed(&mut self, byte: u8) {
+        const ESC: u8 = 0x1b;
+        self.idle_polls = 0;
+        match (self.sequence.len(), byte) {
+            (0, ESC) => self.sequence.push_back(byte),
+            (0, _) => self.keys.push_back(Key::Char(byte)),
+            (1, b'[') | (1, b'O') => self.sequence.push_back(byte),
+            (1, _) => {
+                // not a sequence after all, e.g. Alt+key
+                self.sequence.clear();
+                self.keys.push_back(Key::Escape);
+                self.feed(byte);
+            }
+            _ => {
+                self.sequence.push_back(byte);
+                // SS3 sequences are one byte long, CSI ones end in 0x40..=0x7e
+                let complete = self.sequence[1] == b'O' || (0x40..=0x7e).contains(&byte);
+                if complete {
+                    // unknown sequences are dropped
+                    if let Some(key) = self.decode() {
+                        self.keys.push_back(key);
+                    }
+                    self.sequence.clear();
+                } else if self.sequence.len() == MAX_ESCAPE_SEQUENCE {
+                    self.sequence.clear();
+                }
             }
         }
-        in_buffer.pop_front().unwrap_or(0)
     }
+
+    /// Called when no input is available. An ESC left alone for
+    /// `ESCAPE_TIMEOUT_POLLS` polls is delivered as `Key::Escape`.
+    pub fn idle(&mut self) {
+        if self.sequence.is_empty() {
+            return;
+        }
+        self.idle_polls += 1;
+        if self.idle_polls >= ESCAPE_TIMEOUT_POLLS {
+            self.keys.push_back(Key::Escape);
+            // whatever followed the ESC is plain input
+            let rest: VecDeque<u8> = self.sequence.drain(..).skip(1).collect();
+            for byte in rest {
+                self.keys.push_back(Key::Char(byte));
+            }
+            self.idle_polls = 0;
+        }
+    }
+
+    /// Decode the complete sequence `ESC [ params final` or `ESC O final`.
+    fn decode(&self) -> Option<Key> {
+        let final_byte = *self.sequence.back()?;
+        let key = match final_byte {
+            b'A' => Key::Up,
+            b'B' => Key::Down,
+            b'C' => Key::Right,
+            b'D' => Key::Left,
+            b'H' => Key::Home,
+            b'F' => Key::End,
+            b'P' => Key::F(1),
+            b'Q' => Key::F(2),
+            b'R' => Key::F(3),
+            b'S' => Key::F(4),
+            b'~' => {
+                let mut param = 0usize;
+                for &byte in self.sequence.iter().skip(2).take(self.sequence.len() - 3) {
+                    if !byte.is_ascii_digit() {
+                        return None;
+                    }
+                    param = param * 10 + (byte - b'0') as usize;
+                }
+                match param {
+                    1 | 7 => Key::Home,
+                    2 => Key::Insert,
+                    3 => Key::Delete,
+                    4 | 8 => Key::End,
+                    5 => Key::PageUp,
+                    6 => Key::PageDown,
+                    15 => Key::F(5),
+                    17..=21 => Key::F(param as u8 - 11),
+                    23 | 24 => Key::F(param as u8 - 12),
+                    _ => return None,
+                }
+            }
+            _ => return None,
+        };
+        Some(key)
+    }
+}
+
+/// Decode escape sequences in `pop_key` from now on, or stop doing so.
+#[allow(dead_code)]
+pub fn set_escape_decoding(enabled: bool) {
+    ESCAPE_DECODING.store(enabled, Ordering::Relaxed);
+}
+
+/// Like `pop_stdin`, but returns whole keys. Gives `Key::Char(0)` when no
+/// complete key is available yet.
+#[allow(dead_code)]
+pub fn pop_key() -> Key {
+    if !ESCAPE_DECODING.load(Ordering::Relaxed) {
+        return Key::Char(pop_stdin());
+    }
+    let mut decoder = KEY_DECODER.lock();
+    if let Some(key) = decoder.pop() {
+        return key;
+    }
+    while let Some(byte) = try_pop_stdin() {
+        decoder.feed(byte);
+        if let Some(key) = decoder.pop() {
+            return key;
+        }
+    }
+    decoder.idle();
+    decoder.pop().unwrap_or(Key::Char(0))
+}
+
+#[allow(unused)]
+pub fn key_decoder_test() {
+    let mut decoder = KeyDecoder::new();
+    let mut feed = |bytes: &[u8]| {
+        for &byte in bytes {
+            decoder.feed(byte);
+        }
+        let mut keys = alloc::vec::Vec::new();
+        while let Some(key) = decoder.pop() {
+            keys.push(key);
+        }
+        keys
+    };
+    assert_eq!(feed(b"\x1b[A"), [Key::Up]);
+    assert_eq!(feed(b"x"), [Key::Char(b'x')]);
+    // partial sequences wait for the rest
+    assert_eq!(feed(b"\x1b["), []);
+    assert_eq!(feed(b"3~"), [Key::Delete]);
+    assert_eq!(feed(b"\x1bOP\x1b[24~"), [Key::F(1), Key::F(12)]);
+    assert_eq!(feed(b"\x1bx"), [Key::Escape, Key::Char(b'x')]);
+    // a lone ESC is a key once enough empty polls have passed
+    assert_eq!(feed(b"\x1b"), []);
+    for _ in 0..ESCAPE_TIMEOUT_POLLS {
+        decoder.idle();
+    }
+    assert_eq!(decoder.pop(), Some(Key::Escape));
+    assert_eq!(decoder.pop(), None);
+    debug!("key_decoder_test passed!");
 }
 
 struct Stdout;
@@ -105,3 +484,108 @@ macro_rules! println {
         $crate::console_blog::print(format_args!(concat!($fmt, "\r\n") $(, $($arg)+)?));
     }
 }
+
+#[allow(unused)]
+pub fn rate_limiter_test() {
+    let mut limiter = RateLimiter::new();
+    // off by default
+    assert!((0..1000).all(|_| limiter.allow()));
+    assert_eq!(limiter.dropped(), 0);
+    limiter.configure(4, 8);
+    let passed = (0..100).filter(|_| limiter.allow()).count();
+    assert_eq!(passed, 8);
+    assert_eq!(limiter.dropped(), 92);
+    for _ in 0..3 {
+        limiter.refill();
+        let passed = (0..100).filter(|_| limiter.allow()).count();
+        assert_eq!(passed, 4);
+    }
+    assert_eq!(limiter.dropped(), 92 + 3 * 96);
+    // an idle console saves up to `burst` bytes
+    for _ in 0..10 {
+        limiter.refill();
+    }
+    assert_eq!((0..100).filter(|_| limiter.allow()).count(), 8);
+    debug!("rate_limiter_test passed!");
+}
+
+#[allow(unused)]
+pub fn stdin_eof_test() {
+    for &ch in b"ab\ncd".iter() {
+        push_stdin(ch);
+    }
+    signal_stdin_eof();
+    assert!(stdin_eof());
+    let mut buf = [0u8; 16];
+    // buffered input is still read before EOF shows
+    assert_eq!(read_line(&mut buf), 3);
+    assert_eq!(&buf[..3], b"ab\n");
+    assert_eq!(read_line(&mut buf), 2);
+    assert_eq!(&buf[..2], b"cd");
+    assert_eq!(read_line(&mut buf), 0);
+    assert_eq!(poll_stdin(), StdinPoll::Eof);
+    // and it sticks
+    assert_eq!(read_line(&mut buf), 0);
+    clear_stdin_eof();
+    assert!(!stdin_eof());
+    assert_eq!(poll_stdin(), StdinPoll::Empty);
+    debug!("stdin_eof_test passed!");
+}
+
+#[allow(unused)]
+pub fn pop_stdin_fast_path_test() {
+    IN_BUFFER.lock().clear();
+    for &ch in b"hello".iter() {
+        push_stdin(ch);
+    }
+    let mut read = [0u8; 5];
+    for ch in read.iter_mut() {
+        *ch = pop_stdin();
+    }
+    assert_eq!(&read, b"hello");
+    debug!("pop_stdin_fast_path_test passed!");
+}
+
+#[allow(unused)]
+pub fn stdin_available_test() {
+    IN_BUFFER.lock().clear();
+    for &ch in b"abc".iter() {
+        push_stdin(ch);
+    }
+    assert_eq!(stdin_available(), 3);
+    assert_eq!(stdin_available(), 3);
+    assert_eq!(pop_stdin(), b'a');
+    assert_eq!(stdin_available(), 2);
+    assert_eq!(pop_stdin(), b'b');
+    assert_eq!(pop_stdin(), b'c');
+    assert_eq!(stdin_available(), 0);
+    debug!("stdin_available_test passed!");
+}
+
+#[allow(unused)]
+pub fn receive_stdin_test() {
+    IN_BUFFER.lock().clear();
+    // a mock Rx FIFO, drained the way the interrupt handler does
+    let mut fifo: VecDeque<u8> = b"ls\n".iter().copied().collect();
+    assert_eq!(receive_stdin(|| fifo.pop_front()), 3);
+    assert!(fifo.is_empty());
+    assert_eq!(stdin_available(), 3);
+    let mut fifo: VecDeque<u8> = b"pwd".iter().copied().collect();
+    assert_eq!(receive_stdin(|| fifo.pop_front()), 3);
+    assert_eq!(stdin_available(), 6);
+    let mut read = [0u8; 6];
+    for ch in read.iter_mut() {
+        *ch = pop_stdin();
+    }
+    assert_eq!(&read, b"ls\npwd");
+    assert_eq!(poll_stdin(), StdinPoll::Empty);
+    // a full buffer drops the overflow but still empties the FIFO
+    let mut fifo: VecDeque<u8> = (0..DEFAULT_IN_BUFFER_SIZE + 4).map(|i| i as u8).collect();
+    assert_eq!(
+        receive_stdin(|| fifo.pop_front()),
+        DEFAULT_IN_BUFFER_SIZE + 4
+    );
+    assert_eq!(stdin_available(), DEFAULT_IN_BUFFER_SIZE);
+    IN_BUFFER.lock().clear();
+    debug!("receive_stdin_test passed!");
+}
@@ -1,7 +1,7 @@
     .section .text.entry
     .globl _start
 _start:
-    # a0: hart id
+    # a0: hart id, a1: device tree, left for rust_main
     mv tp, a0
     la sp, boot_stack
     # li t1, 4096 * 16 # t1 = 4096 * 16 64KB
@@ -15,4 +15,9 @@ _start:
 boot_stack:
     .space 4096 * 16 * 4
     .globl boot_stack_top
-boot_stack_top:
\ No newline at end of file
+boot_stack_top:
+
+    # stacks kernel exceptions are taken on, 16K for each hart
+    .globl trap_stack
+trap_stack:
+    .space 4096 * 4 * 4
\ No newline at end of file
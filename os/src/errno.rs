@@ -0,0 +1,19 @@
+//! Error numbers returned (negated) by system calls, following Linux.
+
+pub const EPERM: isize = 1;
+pub const ENOENT: isize = 2;
+pub const ESRCH: isize = 3;
+pub const ENXIO: isize = 6;
+pub const ENOEXEC: isize = 8;
+pub const EBADF: isize = 9;
+pub const EAGAIN: isize = 11;
+pub const ENOMEM: isize = 12;
+pub const EACCES: isize = 13;
+pub const EFAULT: isize = 14;
+pub const EEXIST: isize = 17;
+pub const EINVAL: isize = 22;
+pub const ENOTTY: isize = 25;
+pub const EFBIG: isize = 27;
+pub const ESPIPE: isize = 29;
+pub const ENAMETOOLONG: isize = 36;
+pub const EOVERFLOW: isize = 75;
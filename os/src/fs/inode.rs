@@ -0,0 +1,240 @@
+use super::File;
+use crate::errno::{EBADF, EINVAL, ENXIO, EOVERFLOW};
+use crate::mm::UserBuffer;
+use alloc::string::String;
+use alloc::sync::Arc;
+use spin::Mutex;
+
+/// A file-like object addressed by byte offset.
+pub trait Inode: Send + Sync {
+    fn perm(&self) -> InodePerm;
+    fn size(&self) -> usize;
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, isize>;
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize, isize> {
+        Err(EINVAL)
+    }
+    /// Shrink or zero-extend the file to `size` bytes.
+    fn truncate(&self, _size: usize) -> Result<(), isize> {
+        Err(EINVAL)
+    }
+    /// Start of the first data region at or after `offset`, `None` past EOF.
+    /// Without holes that is `offset` itself.
+    fn next_data(&self, offset: usize) -> Option<usize> {
+        if offset < self.size() {
+            Some(offset)
+        } else {
+            None
+        }
+    }
+    /// Start of the first hole at or after `offset`, `None` past EOF. Every
+    /// file ends with an implicit hole at its size.
+    fn next_hole(&self, offset: usize) -> Option<usize> {
+        if offset < self.size() {
+            Some(self.size())
+        } else {
+            None
+        }
+    }
+}
+
+pub const SEEK_SET: usize = 0;
+pub const SEEK_CUR: usize = 1;
+pub const SEEK_END: usize = 2;
+pub const SEEK_DATA: usize = 3;
+pub const SEEK_HOLE: usize = 4;
+
+/// Owner and Unix permission bits of an inode.
+#[derive(Copy, Clone, Debug)]
+pub struct InodePerm {
+    pub mode: u16,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl InodePerm {
+    pub fn new(mode: u16, uid: u32, gid: u32) -> Self {
+        Self { mode, uid, gid }
+    }
+    /// Check the owner, group or other bits that apply to `uid`/`gid`.
+    /// Root is always allowed.
+    pub fn allows(&self, uid: u32, gid: u32, readable: bool, writable: bool) -> bool {
+        if uid == 0 {
+            return true;
+        }
+        let bits = if uid == self.uid {
+            self.mode >> 6
+        } else if gid == self.gid {
+            self.mode >> 3
+        } else {
+            self.mode
+        };
+        (!readable || bits & 0o4 != 0) && (!writable || bits & 0o2 != 0)
+    }
+}
+
+bitflags! {
+    pub struct OpenFlags: u32 {
+        const RDONLY = 0;
+        const WRONLY = 1 << 0;
+        const RDWR = 1 << 1;
+        const CREATE = 1 << 9;
+        const TRUNC = 1 << 10;
+        const NONBLOCK = 1 << 11;
+    }
+}
+
+impl OpenFlags {
+    /// Do not check validity for simplicity
+    /// Return (readable, writable)
+    pub fn read_write(&self) -> (bool, bool) {
+        if self.is_empty() {
+            (true, false)
+        } else if self.contains(Self::WRONLY) {
+            (false, true)
+        } else {
+            (true, true)
+        }
+    }
+}
+
+/// An opened inode together with its current offset.
+pub struct OpenFile {
+    readable: bool,
+    writable: bool,
+    path: String,
+    inner: Mutex<OpenFileInner>,
+}
+
+pub struct OpenFileInner {
+    offset: usize,
+    /// Status flags that `F_SETFL` may change
+    status: OpenFlags,
+    inode: Arc<dyn Inode>,
+}
+
+impl OpenFile {
+    pub fn new(flags: OpenFlags, path: &str, inode: Arc<dyn Inode>) -> Self {
+        let (readable, writable) = flags.read_write();
+        Self {
+            readable,
+            writable,
+            path: String::from(path),
+            inner: Mutex::new(OpenFileInner {
+                offset: 0,
+                status: flags & OpenFlags::NONBLOCK,
+                inode,
+            }),
+        }
+    }
+}
+
+impl OpenFileInner {
+    fn read_from(&self, mut offset: usize, mut buf: UserBuffer) -> Result<usize, isize> {
+        let mut total_read_size = 0usize;
+        for slice in buf.buffers.iter_mut() {
+            let read_size = self.inode.read_at(offset, *slice)?;
+            if read_size == 0 {
+                break;
+            }
+            offset += read_size;
+            total_read_size += read_size;
+        }
+        Ok(total_read_size)
+    }
+    fn write_to(&self, mut offset: usize, buf: UserBuffer) -> Result<usize, isize> {
+        let mut total_write_size = 0usize;
+        for slice in buf.buffers.iter() {
+            let write_size = self.inode.write_at(offset, *slice)?;
+            assert_eq!(write_size, slice.len());
+            offset += write_size;
+            total_write_size += write_size;
+        }
+        Ok(total_write_size)
+    }
+}
+
+impl File for OpenFile {
+    fn read(&self, buf: UserBuffer) -> Result<usize, isize> {
+        if !self.readable {
+            return Err(EBADF);
+        }
+        let mut inner = self.inner.lock();
+        let read_size = inner.read_from(inner.offset, buf)?;
+        inner.offset += read_size;
+        Ok(read_size)
+    }
+    fn write(&self, buf: UserBuffer) -> Result<usize, isize> {
+        if !self.writable {
+            return Err(EBADF);
+        }
+        let mut inner = self.inner.lock();
+        let write_size = inner.write_to(inner.offset, buf)?;
+        inner.offset += write_size;
+        Ok(write_size)
+    }
+    fn seek(&self, offset: isize, whence: usize) -> Result<usize, isize> {
+        let mut inner = self.inner.lock();
+        let new_offset = match whence {
+            SEEK_SET => offset,
+            SEEK_CUR => (inner.offset as isize)
+                .checked_add(offset)
+                .ok_or(EOVERFLOW)?,
+            SEEK_END => (inner.inode.size() as isize)
+                .checked_add(offset)
+                .ok_or(EOVERFLOW)?,
+            SEEK_DATA | SEEK_HOLE => {
+                if offset < 0 {
+                    return Err(ENXIO);
+                }
+                let found = if whence == SEEK_DATA {
+                    inner.inode.next_data(offset as usize)
+                } else {
+                    inner.inode.next_hole(offset as usize)
+                };
+                found.ok_or(ENXIO)? as isize
+            }
+            _ => return Err(EINVAL),
+        };
+        if new_offset < 0 {
+            return Err(EINVAL);
+        }
+        inner.offset = new_offset as usize;
+        Ok(inner.offset)
+    }
+    fn read_at(&self, offset: usize, buf: UserBuffer) -> Result<usize, isize> {
+        if !self.readable {
+            return Err(EBADF);
+        }
+        self.inner.lock().read_from(offset, buf)
+    }
+    fn write_at(&self, offset: usize, buf: UserBuffer) -> Result<usize, isize> {
+        if !self.writable {
+            return Err(EBADF);
+        }
+        self.inner.lock().write_to(offset, buf)
+    }
+    fn path(&self) -> String {
+        self.path.clone()
+    }
+    fn status_flags(&self) -> OpenFlags {
+        let mode = match (self.readable, self.writable) {
+            (true, true) => OpenFlags::RDWR,
+            (false, true) => OpenFlags::WRONLY,
+            _ => OpenFlags::RDONLY,
+        };
+        mode | self.inner.lock().status
+    }
+    fn set_status_flags(&self, flags: OpenFlags) -> Result<(), isize> {
+        self.inner.lock().status = flags & OpenFlags::NONBLOCK;
+        Ok(())
+    }
+    fn truncate(&self, size: usize) -> Result<(), isize> {
+        if !self.writable {
+            return Err(EINVAL);
+        }
+        self.inner.lock().inode.truncate(size)
+    }
+    fn inode(&self) -> Option<Arc<dyn Inode>> {
+        Some(self.inner.lock().inode.clone())
+    }
+}
@@ -1,14 +1,107 @@
+mod inode;
 mod mail;
 mod pipe;
+mod procfs;
 mod stdio;
+mod tmpfs;
 
+use crate::errno::{EACCES, EINVAL, ENOENT, ENOTTY, ESPIPE};
 use crate::mm::UserBuffer;
+use alloc::string::String;
+use alloc::sync::Arc;
 
 pub use mail::{MailBox, Socket};
 pub trait File: Send + Sync {
     fn read(&self, buf: UserBuffer) -> Result<usize, isize>;
     fn write(&self, buf: UserBuffer) -> Result<usize, isize>;
+    /// Move the file offset as `lseek` does and return the new offset.
+    fn seek(&self, _offset: isize, _whence: usize) -> Result<usize, isize> {
+        Err(ESPIPE)
+    }
+    /// Positional read that leaves the file offset alone.
+    fn read_at(&self, _offset: usize, _buf: UserBuffer) -> Result<usize, isize> {
+        Err(ESPIPE)
+    }
+    /// Positional write that leaves the file offset alone.
+    fn write_at(&self, _offset: usize, _buf: UserBuffer) -> Result<usize, isize> {
+        Err(ESPIPE)
+    }
+    /// What this file points to, as shown under `/proc/[pid]/fd`.
+    fn path(&self) -> String {
+        String::from("anon_inode:[file]")
+    }
+    /// Access mode and status flags, as `F_GETFL` reports them.
+    fn status_flags(&self) -> OpenFlags {
+        OpenFlags::RDWR
+    }
+    /// Change the status flags that `F_SETFL` may change, i.e. `O_NONBLOCK`.
+    fn set_status_flags(&self, _flags: OpenFlags) -> Result<(), isize> {
+        Err(EINVAL)
+    }
+    /// Device specific control, as `ioctl` does.
+    fn ioctl(&self, _cmd: usize, _arg: usize) -> Result<isize, isize> {
+        Err(ENOTTY)
+    }
+    /// Resize the underlying inode, if there is one.
+    fn truncate(&self, _size: usize) -> Result<(), isize> {
+        Err(EINVAL)
+    }
+    /// The inode to map for `mmap`, if this file can be mapped.
+    fn inode(&self) -> Option<Arc<dyn Inode>> {
+        None
+    }
 }
 
+pub use inode::{Inode, OpenFlags};
+use inode::{InodePerm, OpenFile};
 pub use pipe::{make_pipe, Pipe};
 pub use stdio::{Stdin, Stdout};
+
+fn lookup_inode(path: &str) -> Option<Arc<dyn Inode>> {
+    if let Some(proc_path) = path.strip_prefix("/proc") {
+        procfs::lookup(proc_path)
+    } else if let Some(tmp_path) = path.strip_prefix("/tmp") {
+        tmpfs::lookup(tmp_path)
+    } else {
+        None
+    }
+}
+
+/// Open `path` on behalf of user `uid`/`gid`, returning a positive errno on
+/// failure.
+pub fn open_file(path: &str, flags: OpenFlags, uid: u32, gid: u32) -> Result<Arc<OpenFile>, isize> {
+    let (readable, writable) = flags.read_write();
+    // procfs is read-only, even for root
+    if path.starts_with("/proc") && (writable || flags.contains(OpenFlags::CREATE)) {
+        return Err(EACCES);
+    }
+    let inode = match lookup_inode(path) {
+        Some(inode) => {
+            if !inode.perm().allows(uid, gid, readable, writable) {
+                return Err(EACCES);
+            }
+            inode
+        }
+        None if flags.contains(OpenFlags::CREATE) => {
+            let tmp_path = path.strip_prefix("/tmp").ok_or(EACCES)?;
+            tmpfs::create(tmp_path, uid, gid).ok_or(ENOENT)?
+        }
+        None => return Err(ENOENT),
+    };
+    if writable && flags.contains(OpenFlags::TRUNC) {
+        inode.truncate(0)?;
+    }
+    Ok(Arc::new(OpenFile::new(flags, path, inode)))
+}
+
+/// Resize the file at `path` on behalf of user `uid`/`gid`.
+pub fn truncate_file(path: &str, size: usize, uid: u32, gid: u32) -> Result<(), isize> {
+    if path.starts_with("/proc") {
+        return Err(EACCES);
+    }
+    let inode = lookup_inode(path).ok_or(ENOENT)?;
+    if !inode.perm().allows(uid, gid, false, true) {
+        return Err(EACCES);
+    }
+    inode.truncate(size)
+}
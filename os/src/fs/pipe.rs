@@ -1,12 +1,17 @@
-use super::File;
+use super::{File, OpenFlags};
+use crate::errno::EAGAIN;
 use crate::mm::UserBuffer;
 use crate::task::suspend_current_and_run_next;
+use crate::track_alloc;
+use alloc::string::String;
 use alloc::sync::{Arc, Weak};
+use core::sync::atomic::{AtomicBool, Ordering};
 use spin::Mutex;
 
 pub struct Pipe {
     readable: bool,
     writable: bool,
+    nonblock: AtomicBool,
     buffer: Arc<Mutex<PipeRingBuffer>>,
 }
 
@@ -15,6 +20,7 @@ impl Pipe {
         Self {
             readable: true,
             writable: false,
+            nonblock: AtomicBool::new(false),
             buffer,
         }
     }
@@ -22,6 +28,7 @@ impl Pipe {
         Self {
             readable: false,
             writable: true,
+            nonblock: AtomicBool::new(false),
             buffer,
         }
     }
@@ -97,7 +104,7 @@ impl PipeRingBuffer {
 
 /// Return (read_end, write_end)
 pub fn make_pipe() -> (Arc<Pipe>, Arc<Pipe>) {
-    let buffer = Arc::new(Mutex::new(PipeRingBuffer::new()));
+    let buffer = track_alloc!("pipe", Arc::new(Mutex::new(PipeRingBuffer::new())));
     let read_end = Arc::new(Pipe::read_end_with_buffer(buffer.clone()));
     let write_end = Arc::new(Pipe::write_end_with_buffer(buffer.clone()));
     buffer.lock().set_write_end(&write_end);
@@ -116,6 +123,13 @@ impl File for Pipe {
                 if ring_buffer.all_write_ends_closed() {
                     return Ok(read_size);
                 }
+                if self.nonblock.load(Ordering::Relaxed) {
+                    return if read_size > 0 {
+                        Ok(read_size)
+                    } else {
+                        Err(EAGAIN)
+                    };
+                }
                 drop(ring_buffer);
                 suspend_current_and_run_next();
                 continue;
@@ -141,6 +155,13 @@ impl File for Pipe {
             let mut ring_buffer = self.buffer.lock();
             let loop_write = ring_buffer.available_write();
             if loop_write == 0 {
+                if self.nonblock.load(Ordering::Relaxed) {
+                    return if write_size > 0 {
+                        Ok(write_size)
+                    } else {
+                        Err(EAGAIN)
+                    };
+                }
                 drop(ring_buffer);
                 suspend_current_and_run_next();
                 continue;
@@ -156,4 +177,24 @@ impl File for Pipe {
             }
         }
     }
+    fn path(&self) -> String {
+        String::from("pipe")
+    }
+    fn status_flags(&self) -> OpenFlags {
+        let mode = if self.writable {
+            OpenFlags::WRONLY
+        } else {
+            OpenFlags::RDONLY
+        };
+        if self.nonblock.load(Ordering::Relaxed) {
+            mode | OpenFlags::NONBLOCK
+        } else {
+            mode
+        }
+    }
+    fn set_status_flags(&self, flags: OpenFlags) -> Result<(), isize> {
+        self.nonblock
+            .store(flags.contains(OpenFlags::NONBLOCK), Ordering::Relaxed);
+        Ok(())
+    }
 }
@@ -0,0 +1,175 @@
+use super::{Inode, InodePerm};
+use crate::config::PAGE_SIZE;
+use crate::errno::ENOENT;
+use crate::mm::{stats, swap_usage};
+use crate::task::{current_task, find_task, task_pids, TaskStatus};
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::fmt::Write;
+
+/// Nodes of the `/proc` tree. Contents are generated when read.
+#[derive(Copy, Clone)]
+enum ProcEntry {
+    Root,
+    MemInfo,
+    Pid(usize),
+    Status(usize),
+    Maps(usize),
+    FdDir(usize),
+    Fd(usize, usize),
+}
+
+pub struct ProcFsInode {
+    entry: ProcEntry,
+}
+
+/// Resolve a path relative to the `/proc` mount point.
+pub fn lookup(path: &str) -> Option<Arc<dyn Inode>> {
+    if !path.is_empty() && !path.starts_with('/') {
+        return None;
+    }
+    let mut inode = ProcFsInode::root();
+    for name in path.split('/').filter(|name| !name.is_empty()) {
+        inode = inode.find(name)?;
+    }
+    Some(inode)
+}
+
+impl ProcFsInode {
+    pub fn root() -> Arc<Self> {
+        Arc::new(Self {
+            entry: ProcEntry::Root,
+        })
+    }
+
+    pub fn find(&self, name: &str) -> Option<Arc<ProcFsInode>> {
+        let entry = match (self.entry, name) {
+            (ProcEntry::Root, "meminfo") => ProcEntry::MemInfo,
+            // `self` is a symlink to the directory of the calling process
+            (ProcEntry::Root, "self") => ProcEntry::Pid(current_task()?.getpid()),
+            (ProcEntry::Root, name) => ProcEntry::Pid(name.parse().ok()?),
+            (ProcEntry::Pid(pid), "status") => ProcEntry::Status(pid),
+            (ProcEntry::Pid(pid), "maps") => ProcEntry::Maps(pid),
+            (ProcEntry::Pid(pid), "fd") => ProcEntry::FdDir(pid),
+            (ProcEntry::FdDir(pid), name) => ProcEntry::Fd(pid, name.parse().ok()?),
+            _ => return None,
+        };
+        let inode = Self { entry };
+        // make sure the process (and fd) exists at lookup time
+        inode.content()?;
+        Some(Arc::new(inode))
+    }
+
+    /// Generate the contents of this node, or `None` if its process is gone.
+    fn content(&self) -> Option<String> {
+        let mut s = String::new();
+        match self.entry {
+            ProcEntry::Root => {
+                s.push_str("meminfo\nself\n");
+                for pid in task_pids() {
+                    writeln!(s, "{}", pid).unwrap();
+                }
+            }
+            ProcEntry::MemInfo => {
+                let stats = stats();
+                let kb_per_frame = PAGE_SIZE / 1024;
+                writeln!(s, "MemTotal:\t{} kB", stats.total_frames * kb_per_frame).unwrap();
+                writeln!(s, "MemFree:\t{} kB", stats.free_frames * kb_per_frame).unwrap();
+                writeln!(s, "HeapTotal:\t{} kB", stats.heap_total / 1024).unwrap();
+                writeln!(s, "HeapUsed:\t{} kB", stats.heap_used / 1024).unwrap();
+                let (swapped, slots) = swap_usage();
+                writeln!(s, "SwapTotal:\t{} kB", slots * kb_per_frame).unwrap();
+                writeln!(s, "SwapFree:\t{} kB", (slots - swapped) * kb_per_frame).unwrap();
+            }
+            ProcEntry::Pid(pid) => {
+                find_task(pid)?;
+                s.push_str("status\nmaps\nfd\n");
+            }
+            ProcEntry::Status(pid) => {
+                let task = find_task(pid)?;
+                let inner = task.acquire_inner_lock();
+                let ppid = inner
+                    .parent
+                    .as_ref()
+                    .and_then(|parent| parent.upgrade())
+                    .map_or(0, |parent| parent.getpid());
+                let state = match inner.task_status {
+                    TaskStatus::Ready => "R (ready)",
+                    TaskStatus::Running => "R (running)",
+                    TaskStatus::Blocked => "S (sleeping)",
+                    TaskStatus::Zombie => "Z (zombie)",
+                };
+                let (mut vm_pages, mut rss_pages, mut swap_pages) = (0usize, 0usize, 0usize);
+                for area in inner.memory_set.iter_areas() {
+                    let range = area.vpn_range();
+                    vm_pages += range.get_end().0 - range.get_start().0;
+                    rss_pages += area.resident_pages();
+                    swap_pages += area.swapped_pages();
+                }
+                writeln!(s, "Name:\t{}", inner.name).unwrap();
+                writeln!(s, "Pid:\t{}", pid).unwrap();
+                writeln!(s, "PPid:\t{}", ppid).unwrap();
+                writeln!(s, "State:\t{}", state).unwrap();
+                writeln!(s, "VmSize:\t{} kB", vm_pages * PAGE_SIZE / 1024).unwrap();
+                writeln!(s, "VmRSS:\t{} kB", rss_pages * PAGE_SIZE / 1024).unwrap();
+                writeln!(s, "VmSwap:\t{} kB", swap_pages * PAGE_SIZE / 1024).unwrap();
+            }
+            ProcEntry::Maps(pid) => {
+                let task = find_task(pid)?;
+                let inner = task.acquire_inner_lock();
+                s.push_str(&inner.memory_set.dump());
+            }
+            ProcEntry::FdDir(pid) => {
+                let task = find_task(pid)?;
+                let inner = task.acquire_inner_lock();
+                for (fd, file) in inner.fd_table.iter().enumerate() {
+                    if file.is_some() {
+                        writeln!(s, "{}", fd).unwrap();
+                    }
+                }
+            }
+            ProcEntry::Fd(pid, fd) => {
+                let task = find_task(pid)?;
+                let inner = task.acquire_inner_lock();
+                let file = inner.fd_table.get(fd)?.as_ref()?;
+                writeln!(s, "{}", file.path()).unwrap();
+            }
+        }
+        Some(s)
+    }
+}
+
+impl Inode for ProcFsInode {
+    fn size(&self) -> usize {
+        self.content().map_or(0, |content| content.len())
+    }
+    /// Per-process entries belong to the owner of that process, and only the
+    /// owner may look at its open files.
+    fn perm(&self) -> InodePerm {
+        let (mode, pid) = match self.entry {
+            ProcEntry::Root => return InodePerm::new(0o555, 0, 0),
+            ProcEntry::MemInfo => return InodePerm::new(0o444, 0, 0),
+            ProcEntry::Pid(pid) => (0o555, pid),
+            ProcEntry::Status(pid) | ProcEntry::Maps(pid) => (0o444, pid),
+            ProcEntry::FdDir(pid) => (0o500, pid),
+            ProcEntry::Fd(pid, _) => (0o400, pid),
+        };
+        match find_task(pid) {
+            Some(task) => {
+                let inner = task.acquire_inner_lock();
+                InodePerm::new(mode, inner.uid, inner.gid)
+            }
+            None => InodePerm::new(mode, 0, 0),
+        }
+    }
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, isize> {
+        let content = self.content().ok_or(ENOENT)?;
+        let bytes = content.as_bytes();
+        if offset >= bytes.len() {
+            return Ok(0);
+        }
+        let len = buf.len().min(bytes.len() - offset);
+        buf[..len].copy_from_slice(&bytes[offset..offset + len]);
+        Ok(len)
+    }
+}
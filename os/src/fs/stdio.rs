@@ -1,7 +1,8 @@
-use super::File;
-use crate::console_blog::pop_stdin;
+use super::{File, OpenFlags};
+use crate::console_blog::{poll_stdin, StdinPoll};
 use crate::mm::UserBuffer;
 use crate::print;
+use alloc::string::String;
 
 pub struct Stdin;
 
@@ -11,7 +12,12 @@ impl File for Stdin {
     fn read(&self, mut user_buf: UserBuffer) -> Result<usize, isize> {
         assert_eq!(user_buf.len(), 1);
         // busy loop
-        let c = pop_stdin();
+        let c = match poll_stdin() {
+            StdinPoll::Byte(c) => c,
+            StdinPoll::Empty => 0,
+            // end of file reads nothing
+            StdinPoll::Eof => return Ok(0),
+        };
         // c = console_getchar();
 
         let ch = c as u8;
@@ -23,6 +29,12 @@ impl File for Stdin {
     fn write(&self, _user_buf: UserBuffer) -> Result<usize, isize> {
         panic!("Cannot write to stdin!");
     }
+    fn path(&self) -> String {
+        String::from("/dev/console")
+    }
+    fn status_flags(&self) -> OpenFlags {
+        OpenFlags::RDONLY
+    }
 }
 
 impl File for Stdout {
@@ -35,4 +47,10 @@ impl File for Stdout {
         }
         Ok(user_buf.len())
     }
+    fn path(&self) -> String {
+        String::from("/dev/console")
+    }
+    fn status_flags(&self) -> OpenFlags {
+        OpenFlags::WRONLY
+    }
 }
@@ -0,0 +1,105 @@
+use super::{Inode, InodePerm};
+use crate::errno::{EFBIG, ENOMEM};
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use lazy_static::*;
+use spin::Mutex;
+
+/// Files grow no larger, as they live on the kernel heap.
+pub const MAX_FILE_SIZE: usize = 0x100_0000;
+
+/// A regular file kept entirely in memory.
+pub struct TmpfsInode {
+    inner: Mutex<TmpfsInodeInner>,
+}
+
+pub struct TmpfsInodeInner {
+    data: Vec<u8>,
+    perm: InodePerm,
+}
+
+lazy_static! {
+    /// Files under `/tmp`. The directory is flat; names may not contain `/`.
+    static ref TMPFS_ROOT: Mutex<BTreeMap<String, Arc<TmpfsInode>>> = Mutex::new(BTreeMap::new());
+}
+
+fn file_name(path: &str) -> Option<&str> {
+    let name = path.strip_prefix('/')?;
+    if name.is_empty() || name.contains('/') {
+        None
+    } else {
+        Some(name)
+    }
+}
+
+/// Resolve a path relative to the `/tmp` mount point.
+pub fn lookup(path: &str) -> Option<Arc<dyn Inode>> {
+    let inode = TMPFS_ROOT.lock().get(file_name(path)?)?.clone();
+    Some(inode)
+}
+
+/// Create an empty file owned by `uid`/`gid`, or return the existing one.
+pub fn create(path: &str, uid: u32, gid: u32) -> Option<Arc<dyn Inode>> {
+    let name = file_name(path)?;
+    let inode = TMPFS_ROOT
+        .lock()
+        .entry(String::from(name))
+        .or_insert_with(|| {
+            Arc::new(TmpfsInode {
+                inner: Mutex::new(TmpfsInodeInner {
+                    data: Vec::new(),
+                    perm: InodePerm::new(0o644, uid, gid),
+                }),
+            })
+        })
+        .clone();
+    Some(inode)
+}
+
+impl Inode for TmpfsInode {
+    fn perm(&self) -> InodePerm {
+        self.inner.lock().perm
+    }
+    fn size(&self) -> usize {
+        self.inner.lock().data.len()
+    }
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, isize> {
+        let inner = self.inner.lock();
+        if offset >= inner.data.len() {
+            return Ok(0);
+        }
+        let len = buf.len().min(inner.data.len() - offset);
+        buf[..len].copy_from_slice(&inner.data[offset..offset + len]);
+        Ok(len)
+    }
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, isize> {
+        let end = offset.checked_add(buf.len()).ok_or(EFBIG)?;
+        let mut inner = self.inner.lock();
+        let size = end.max(inner.data.len());
+        inner.resize(size)?;
+        inner.data[offset..end].copy_from_slice(buf);
+        Ok(buf.len())
+    }
+    fn truncate(&self, size: usize) -> Result<(), isize> {
+        self.inner.lock().resize(size)
+    }
+}
+
+impl TmpfsInodeInner {
+    /// Shrink or zero-extend the data to `size` bytes. Fails with EFBIG past
+    /// `MAX_FILE_SIZE` and with ENOMEM if the heap has no room left.
+    fn resize(&mut self, size: usize) -> Result<(), isize> {
+        if size > MAX_FILE_SIZE {
+            return Err(EFBIG);
+        }
+        if size > self.data.len() {
+            self.data
+                .try_reserve_exact(size - self.data.len())
+                .map_err(|_| ENOMEM)?;
+        }
+        self.data.resize(size, 0);
+        Ok(())
+    }
+}
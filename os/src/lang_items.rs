@@ -1,6 +1,11 @@
-use crate::task::hart_id;
+use crate::console_blog;
+use crate::task::{dump_state, hart_id};
 use crate::{console::ANSICON, sbi::shutdown};
 use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Set by the first panic, so a panic inside the dump does not recurse.
+static PANICKED: AtomicBool = AtomicBool::new(false);
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
@@ -23,5 +28,9 @@ fn panic(info: &PanicInfo) -> ! {
             info.message().unwrap()
         );
     }
+    if !PANICKED.swap(true, Ordering::SeqCst) {
+        console_blog::flush();
+        dump_state();
+    }
     shutdown()
 }
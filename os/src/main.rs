@@ -16,20 +16,25 @@ extern crate bitflags;
 #[macro_use]
 extern crate log;
 
-use crate::{config::CPU_NUM, mm::init_kernel_space, sbi::send_ipi};
+use crate::{boot::machine, mm::init_kernel_space, sbi::send_ipi};
 
 #[macro_use]
 mod console;
+mod boot;
 mod config;
 #[macro_use]
 mod console_blog;
+mod errno;
 mod fs;
 mod lang_items;
 mod loader;
 mod logger;
 mod mm;
+mod perf;
 mod plic;
+mod random;
 mod sbi;
+mod sync;
 mod syscall;
 mod task;
 mod timer;
@@ -48,14 +53,35 @@ fn clear_bss() {
     (sbss as usize..ebss as usize).for_each(|a| unsafe { (a as *mut u8).write_volatile(0) });
 }
 
+/// The tests that need no tasks, run once memory management is up.
+#[cfg(debug_assertions)]
+fn self_test() {
+    boot::fdt::fdt_test();
+    console_blog::key_decoder_test();
+    console_blog::rate_limiter_test();
+    console_blog::stdin_eof_test();
+    console_blog::pop_stdin_fast_path_test();
+    console_blog::stdin_available_test();
+    console_blog::receive_stdin_test();
+    perf::perf_counter_test();
+    random::random_test();
+    timer::software_clock_test();
+    mm::self_test();
+}
+
 #[no_mangle]
-pub fn rust_main(hart_id: usize) -> ! {
+pub fn rust_main(hart_id: usize, dtb: usize) -> ! {
     if hart_id == 0 {
         clear_bss();
         logger::init();
+        boot::init(dtb);
         mm::init();
         debug!("[kernel {}] Hello, world!", hart_id);
         mm::remap_test();
+        #[cfg(debug_assertions)]
+        mm::paging_audit();
+        #[cfg(debug_assertions)]
+        self_test();
         trap::init();
         plic::init();
         plic::init_hart(hart_id);
@@ -74,6 +100,11 @@ pub fn rust_main(hart_id: usize) -> ! {
         debug!("trying to add initproc");
         task::add_initproc();
         debug!("initproc added to task manager!");
+        #[cfg(debug_assertions)]
+        {
+            task::self_test();
+            sync::self_test();
+        }
 
         unsafe {
             let satp: usize;
@@ -83,7 +114,7 @@ pub fn rust_main(hart_id: usize) -> ! {
             println_hart!("satp: {:#x}, sp: {:#x}", hart_id, satp, sp);
         }
 
-        for i in 1..CPU_NUM {
+        for i in 1..machine().harts {
             debug!("[kernel {}] Start {}", hart_id, i);
             let mask: usize = 1 << i;
             send_ipi(&mask as *const _ as usize);
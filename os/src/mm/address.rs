@@ -213,6 +213,10 @@ where
             || (self.l < other.r && other.r <= self.r)
             || (other.l < self.l && self.r < other.r)
     }
+
+    pub fn contains(&self, v: T) -> bool {
+        self.l <= v && v < self.r
+    }
 }
 impl<T> IntoIterator for SimpleRange<T>
 where
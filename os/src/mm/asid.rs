@@ -0,0 +1,141 @@
+//! Address space identifiers, tagging the TLB entries of each space so a
+//! switch does not have to fence anything. An ASID is fenced when it is
+//! handed out again, and changes to a page table are fenced by the
+//! `TlbFlushBatch` of its space.
+
+use super::MemorySet;
+use alloc::vec::Vec;
+use lazy_static::*;
+use riscv::register::satp;
+use spin::Mutex;
+
+/// Position and width of the ASID field of satp in Sv39.
+pub const ASID_SHIFT: usize = 44;
+const ASID_MASK: usize = 0xffff;
+
+/// Used by the kernel spaces, and shared by user spaces once the others run
+/// out. Its entries are fenced on every trap into or out of a user space
+/// sharing it, so sharing it stays correct.
+pub const KERNEL_ASID: usize = 0;
+
+struct AsidAllocator {
+    current: usize,
+    /// One past the largest ASID the hart implements, see `init`.
+    end: usize,
+    recycled: Vec<usize>,
+}
+
+impl AsidAllocator {
+    pub fn new() -> Self {
+        AsidAllocator {
+            current: KERNEL_ASID + 1,
+            end: KERNEL_ASID + 1,
+            recycled: Vec::new(),
+        }
+    }
+    pub fn alloc(&mut self) -> AsidHandle {
+        let asid = match self.recycled.pop() {
+            Some(asid) => {
+                // drop whatever the last space tagged with it left behind
+                unsafe {
+                    llvm_asm!("sfence.vma zero, $0" :: "r"(asid) :: "volatile");
+                }
+                asid
+            }
+            None if self.current < self.end => {
+                self.current += 1;
+                self.current - 1
+            }
+            None => KERNEL_ASID,
+        };
+        AsidHandle(asid)
+    }
+    pub fn dealloc(&mut self, asid: usize) {
+        assert!(asid < self.current);
+        assert!(
+            !self.recycled.contains(&asid),
+            "asid {} has been deallocated!",
+            asid
+        );
+        self.recycled.push(asid);
+    }
+}
+
+lazy_static! {
+    static ref ASID_ALLOCATOR: Mutex<AsidAllocator> = Mutex::new(AsidAllocator::new());
+}
+
+/// Probe how many ASID bits the hart keeps by writing all ones to the field,
+/// as the privileged spec suggests. Until this runs every space gets
+/// `KERNEL_ASID`.
+pub fn init() {
+    let bits = satp::read().bits();
+    let asid_len = unsafe {
+        satp::write(bits | ASID_MASK << ASID_SHIFT);
+        let asid_len = (satp::read().bits() >> ASID_SHIFT & ASID_MASK).count_ones();
+        satp::write(bits);
+        asid_len
+    };
+    ASID_ALLOCATOR.lock().end = 1 << asid_len;
+    info!("[asid] {} bits", asid_len);
+}
+
+#[derive(PartialEq, Eq, Debug)]
+pub struct AsidHandle(pub usize);
+
+impl AsidHandle {
+    pub fn kernel() -> Self {
+        AsidHandle(KERNEL_ASID)
+    }
+}
+
+impl Drop for AsidHandle {
+    fn drop(&mut self) {
+        if self.0 != KERNEL_ASID {
+            ASID_ALLOCATOR.lock().dealloc(self.0);
+        }
+    }
+}
+
+pub fn asid_alloc() -> AsidHandle {
+    ASID_ALLOCATOR.lock().alloc()
+}
+
+#[allow(unused)]
+pub fn asid_test() {
+    let mut allocator = AsidAllocator::new();
+    allocator.end = 4;
+    let asids: Vec<usize> = (0..3)
+        .map(|_| allocator.alloc())
+        .map(|handle| {
+            let asid = handle.0;
+            // not handed back to the global allocator
+            core::mem::forget(handle);
+            asid
+        })
+        .collect();
+    assert_eq!(asids, [1, 2, 3]);
+    // out of ASIDs, fall back to the shared one
+    let shared = allocator.alloc();
+    assert_eq!(shared.0, KERNEL_ASID);
+    allocator.dealloc(2);
+    let recycled = allocator.alloc();
+    assert_eq!(recycled.0, 2);
+    core::mem::forget(recycled);
+
+    // handles of the global allocator are distinct and recycled on drop
+    let first = asid_alloc();
+    let second = asid_alloc();
+    assert!(first.0 == KERNEL_ASID || first != second);
+    let asid = second.0;
+    drop(second);
+    assert_eq!(asid_alloc().0, asid);
+
+    // the token carries the ASID, kernel spaces use the shared one
+    let kernel_space = MemorySet::new_kernel();
+    assert_eq!(kernel_space.token() >> ASID_SHIFT & ASID_MASK, KERNEL_ASID);
+    let user_space = MemorySet::new_bare();
+    assert_eq!(user_space.token() >> ASID_SHIFT & ASID_MASK, asid);
+    assert_eq!(user_space.token() >> 60, 8);
+    debug!("asid_test passed!");
+}
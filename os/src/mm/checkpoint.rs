@@ -0,0 +1,103 @@
+//! Byte streams a `MemorySet` is checkpointed to and restored from, see
+//! `MemorySet::serialize`. Numbers are stored as little-endian 64-bit
+//! words: a header of `MAGIC`, `VERSION` and the layout of the space,
+//! then each area as its range, type, permission and flags, followed by
+//! `(vpn, page)` for the pages it has and `END_OF_AREA`.
+
+use crate::errno::EINVAL;
+use crate::fs::Inode;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+pub(super) const MAGIC: usize = 0x5450_4b43_4e2d_4352;
+pub(super) const VERSION: usize = 1;
+pub(super) const END_OF_AREA: usize = usize::MAX;
+
+pub(super) const AREA_FRAMED: usize = 1;
+pub(super) const AREA_LAZY: usize = 2;
+pub(super) const AREA_RESERVED: usize = 3;
+
+/// Flags of an area
+pub(super) const AREA_GROWABLE: usize = 1 << 0;
+pub(super) const AREA_FAST: usize = 1 << 1;
+
+pub trait CheckpointWriter {
+    fn write_all(&mut self, bytes: &[u8]) -> Result<(), isize>;
+    fn put(&mut self, value: usize) -> Result<(), isize> {
+        self.write_all(&(value as u64).to_le_bytes())
+    }
+}
+
+pub trait CheckpointReader {
+    /// Fill all of `bytes`, EINVAL if the stream ends first.
+    fn read_exact(&mut self, bytes: &mut [u8]) -> Result<(), isize>;
+    fn take(&mut self) -> Result<usize, isize> {
+        let mut word = [0u8; 8];
+        self.read_exact(&mut word)?;
+        Ok(u64::from_le_bytes(word) as usize)
+    }
+}
+
+impl CheckpointWriter for Vec<u8> {
+    fn write_all(&mut self, bytes: &[u8]) -> Result<(), isize> {
+        self.extend_from_slice(bytes);
+        Ok(())
+    }
+}
+
+impl CheckpointReader for &[u8] {
+    fn read_exact(&mut self, bytes: &mut [u8]) -> Result<(), isize> {
+        if self.len() < bytes.len() {
+            return Err(EINVAL);
+        }
+        let (head, rest) = self.split_at(bytes.len());
+        bytes.copy_from_slice(head);
+        *self = rest;
+        Ok(())
+    }
+}
+
+/// A whole file as a stream, from its first byte on. Writing truncates the
+/// file first, so a checkpoint replaces whatever it held before.
+pub struct InodeStream {
+    inode: Arc<dyn Inode>,
+    offset: usize,
+}
+
+impl InodeStream {
+    pub fn reader(inode: Arc<dyn Inode>) -> Self {
+        Self { inode, offset: 0 }
+    }
+    pub fn writer(inode: Arc<dyn Inode>) -> Result<Self, isize> {
+        inode.truncate(0)?;
+        Ok(Self { inode, offset: 0 })
+    }
+}
+
+impl CheckpointWriter for InodeStream {
+    fn write_all(&mut self, mut bytes: &[u8]) -> Result<(), isize> {
+        while !bytes.is_empty() {
+            let written = self.inode.write_at(self.offset, bytes)?;
+            if written == 0 {
+                return Err(EINVAL);
+            }
+            self.offset += written;
+            bytes = &bytes[written..];
+        }
+        Ok(())
+    }
+}
+
+impl CheckpointReader for InodeStream {
+    fn read_exact(&mut self, mut bytes: &mut [u8]) -> Result<(), isize> {
+        while !bytes.is_empty() {
+            let read = self.inode.read_at(self.offset, bytes)?;
+            if read == 0 {
+                return Err(EINVAL);
+            }
+            self.offset += read;
+            bytes = &mut bytes[read..];
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,258 @@
+use super::memory_set::MapType;
+use super::{MapPermission, MemorySet, MmapFlags, TlbFlushBatch, VirtAddr, VirtPageNum};
+use core::fmt::{self, Display, Formatter};
+use riscv::register::scause::{Exception, Trap};
+
+/// The kind of access that raised a page fault, taken from `scause`.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum AccessType {
+    Read,
+    Write,
+    Execute,
+}
+
+impl AccessType {
+    pub fn from_trap(trap: Trap) -> Option<Self> {
+        match trap {
+            Trap::Exception(Exception::LoadFault) | Trap::Exception(Exception::LoadPageFault) => {
+                Some(Self::Read)
+            }
+            Trap::Exception(Exception::StoreFault) | Trap::Exception(Exception::StorePageFault) => {
+                Some(Self::Write)
+            }
+            Trap::Exception(Exception::InstructionFault)
+            | Trap::Exception(Exception::InstructionPageFault) => Some(Self::Execute),
+            _ => None,
+        }
+    }
+    fn permission(&self) -> MapPermission {
+        match self {
+            Self::Read => MapPermission::R,
+            Self::Write => MapPermission::W,
+            Self::Execute => MapPermission::X,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum FaultKind {
+    /// No area covers the faulting address.
+    Unmapped,
+    /// The area does not allow this kind of access, e.g. a load from an
+    /// `X`-only page or an instruction fetch from a data page.
+    Permission,
+    /// The area allows the access but the page is not mapped.
+    NotPresent,
+    /// A store to a page the area allows writing to but which is mapped
+    /// read-only, i.e. a copy-on-write candidate.
+    WriteProtected,
+    /// The page table already allows the access; a stale TLB entry.
+    Spurious,
+}
+
+/// A user page fault no handler could resolve, with what is known about
+/// the faulting address.
+#[derive(Copy, Clone, Debug)]
+pub struct FaultReport {
+    pub va: VirtAddr,
+    pub access: AccessType,
+    pub kind: FaultKind,
+    /// Start, end, type and permission of the area holding `va`
+    pub area: Option<(VirtAddr, VirtAddr, MapType, MapPermission)>,
+    /// `va` is in the guard below the user stack
+    pub stack_overflow: bool,
+}
+
+impl Display for FaultReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "bad addr = {:#x}, {:?} {:?}",
+            self.va.0, self.access, self.kind
+        )?;
+        match self.area {
+            Some((start, end, map_type, perm)) => write!(
+                f,
+                ", area [{:#x}, {:#x}) {:?} {:?}",
+                start.0, end.0, map_type, perm
+            )?,
+            None => write!(f, ", no area")?,
+        }
+        if self.stack_overflow {
+            write!(f, ", stack overflow")?;
+        }
+        Ok(())
+    }
+}
+
+impl MemorySet {
+    /// Resolve a user page fault at `va`. It is classified first, then
+    /// handed to the handler for its kind: copy-on-write for a store to a
+    /// write protected page, lazy allocation for a page not present, and
+    /// stack growth for the page right below a growable stack. A fault no
+    /// handler claims, guard pages included, is reported back.
+    pub fn handle_fault(&mut self, va: VirtAddr, access: AccessType) -> Result<(), FaultReport> {
+        let vpn = va.floor();
+        let kind = self.classify_fault(va, access);
+        let resolved = match kind {
+            // a stale entry, the change was fenced on another hart
+            FaultKind::Spurious => {
+                TlbFlushBatch::new(self.asid()).add(vpn);
+                Ok(())
+            }
+            FaultKind::WriteProtected => self.handle_cow_fault(vpn),
+            FaultKind::NotPresent if access == AccessType::Write => self.handle_lazy_fault(vpn),
+            FaultKind::NotPresent => self.handle_lazy_read_fault(vpn),
+            // below the stack, either free or in its reserved room
+            FaultKind::Unmapped | FaultKind::Permission => self.grow_stack(vpn),
+        };
+        resolved.map_err(|_| FaultReport {
+            va,
+            access,
+            kind,
+            area: self.find_area(vpn).map(|area| {
+                (
+                    area.vpn_range().get_start().into(),
+                    area.vpn_range().get_end().into(),
+                    area.map_type(),
+                    area.map_perm(),
+                )
+            }),
+            stack_overflow: self.is_stack_guard(vpn),
+        })
+    }
+
+    /// Decide why a user access to `va` faulted by checking it against the
+    /// owning area's permission and the current page table entry.
+    pub fn classify_fault(&self, va: VirtAddr, access: AccessType) -> FaultKind {
+        let vpn: VirtPageNum = va.floor();
+        let area = match self.find_area(vpn) {
+            Some(area) => area,
+            None => return FaultKind::Unmapped,
+        };
+        let perm = area.map_perm();
+        if !perm.contains(MapPermission::U | access.permission()) {
+            return FaultKind::Permission;
+        }
+        let pte = match self.translate(vpn) {
+            Some(pte) if pte.is_valid() => pte,
+            _ => return FaultKind::NotPresent,
+        };
+        let allowed = match access {
+            AccessType::Read => pte.readable(),
+            AccessType::Write => pte.writable(),
+            AccessType::Execute => pte.executable(),
+        };
+        if allowed {
+            FaultKind::Spurious
+        } else if access == AccessType::Write {
+            FaultKind::WriteProtected
+        } else {
+            FaultKind::Permission
+        }
+    }
+}
+
+#[allow(unused)]
+pub fn fault_classify_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let w_only: VirtAddr = 0x1000.into();
+    let x_only: VirtAddr = 0x2000.into();
+    memory_set
+        .insert_framed_area(w_only, x_only, MapPermission::W | MapPermission::U)
+        .unwrap();
+    memory_set
+        .insert_framed_area(x_only, 0x3000.into(), MapPermission::X | MapPermission::U)
+        .unwrap();
+    assert_eq!(
+        memory_set.classify_fault(w_only, AccessType::Execute),
+        FaultKind::Permission
+    );
+    assert_eq!(
+        memory_set.classify_fault(x_only, AccessType::Read),
+        FaultKind::Permission
+    );
+    assert_eq!(
+        memory_set.classify_fault(x_only, AccessType::Execute),
+        FaultKind::Spurious
+    );
+    assert_eq!(
+        memory_set.classify_fault(0x3000.into(), AccessType::Read),
+        FaultKind::Unmapped
+    );
+    debug!("fault_classify_test passed!");
+}
+
+#[allow(unused)]
+pub fn handle_fault_test() {
+    use crate::config::{PAGE_SIZE, TRAP_CONTEXT};
+    let mut memory_set = MemorySet::new_bare();
+    // a trap context with a lazy stack below it, and a gap under that
+    memory_set
+        .insert_framed_area(
+            TRAP_CONTEXT.into(),
+            (TRAP_CONTEXT + PAGE_SIZE).into(),
+            MapPermission::R | MapPermission::W,
+        )
+        .unwrap();
+    let stack_bottom = TRAP_CONTEXT - 2 * PAGE_SIZE;
+    memory_set
+        .mmap(
+            stack_bottom,
+            2 * PAGE_SIZE,
+            0b11,
+            MmapFlags::PRIVATE | MmapFlags::ANONYMOUS | MmapFlags::FIXED_NOREPLACE,
+        )
+        .unwrap();
+    memory_set.set_stack_limit(3 * PAGE_SIZE).unwrap();
+    memory_set
+        .insert_framed_area(
+            0x10000.into(),
+            0x11000.into(),
+            MapPermission::R | MapPermission::U,
+        )
+        .unwrap();
+    // lazy pages are backed on the first touch
+    assert!(memory_set
+        .handle_fault((TRAP_CONTEXT - 8).into(), AccessType::Write)
+        .is_ok());
+    assert!(memory_set
+        .translate(VirtAddr::from(TRAP_CONTEXT - 8).floor())
+        .unwrap()
+        .is_valid());
+    // the page right below the stack grows it, one further down does not
+    let below: VirtAddr = (stack_bottom - 8).into();
+    assert!(memory_set.handle_fault(below, AccessType::Write).is_ok());
+    assert_eq!(
+        memory_set
+            .find_area(below.floor())
+            .unwrap()
+            .vpn_range()
+            .get_end(),
+        VirtAddr::from(TRAP_CONTEXT).floor()
+    );
+    let far: VirtAddr = (stack_bottom - 3 * PAGE_SIZE).into();
+    let report = memory_set.handle_fault(far, AccessType::Write).unwrap_err();
+    assert_eq!(report.kind, FaultKind::Unmapped);
+    assert!(report.area.is_none() && !report.stack_overflow);
+    // a store to read-only memory is reported with its area
+    let report = memory_set
+        .handle_fault(0x10008.into(), AccessType::Write)
+        .unwrap_err();
+    assert_eq!(report.kind, FaultKind::Permission);
+    let (start, end, map_type, _) = report.area.unwrap();
+    assert_eq!(
+        (start.0, end.0, map_type),
+        (0x10000, 0x11000, MapType::Framed)
+    );
+    // a guard under the stack stops growth and is named in the report
+    let stack_bottom = stack_bottom - PAGE_SIZE;
+    memory_set
+        .reserve_range(stack_bottom - PAGE_SIZE, PAGE_SIZE)
+        .unwrap();
+    let report = memory_set
+        .handle_fault((stack_bottom - 8).into(), AccessType::Write)
+        .unwrap_err();
+    assert!(report.stack_overflow);
+    debug!("handle_fault_test passed!");
+}
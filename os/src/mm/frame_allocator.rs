@@ -1,5 +1,7 @@
 use super::{PhysAddr, PhysPageNum};
-use crate::config::MEMORY_END;
+use crate::boot::machine;
+use crate::config::FAST_MEMORY;
+use alloc::vec;
 use alloc::vec::Vec;
 use core::fmt::{self, Debug, Formatter};
 use lazy_static::*;
@@ -36,75 +38,427 @@ trait FrameAllocator {
     fn new() -> Self;
     fn alloc(&mut self) -> Option<PhysPageNum>;
     fn dealloc(&mut self, ppn: PhysPageNum);
+    /// Allocate 2^order physically contiguous frames, aligned to their
+    /// total size, and return the first.
+    fn alloc_contiguous(&mut self, order: usize) -> Option<PhysPageNum>;
+    /// Return (allocated, total) frames.
+    fn usage(&self) -> (usize, usize);
+    /// Whether `ppn` is one of the frames managed here.
+    fn contains(&self, ppn: PhysPageNum) -> bool;
 }
 
-pub struct StackFrameAllocator {
-    current: usize,
+/// Largest block handed out or merged into, 2^MAX_ORDER frames.
+pub const MAX_ORDER: usize = 10;
+
+/// A set of block indices, one bit each, sized once by `with_capacity`.
+/// The kernel heap grows with frames taken under the frame allocator's
+/// lock, so the allocator must not touch the heap past `init`.
+#[derive(Default)]
+struct BlockSet {
+    bits: Vec<u64>,
+    len: usize,
+    /// No word below this one has a bit set
+    hint: usize,
+}
+
+#[cfg_attr(feature = "bitmap_allocator", allow(dead_code))]
+impl BlockSet {
+    fn with_capacity(blocks: usize) -> Self {
+        Self {
+            bits: vec![0; (blocks + 63) / 64],
+            len: 0,
+            hint: 0,
+        }
+    }
+    fn contains(&self, i: usize) -> bool {
+        self.bits
+            .get(i / 64)
+            .map_or(false, |word| word & (1 << (i % 64)) != 0)
+    }
+    fn insert(&mut self, i: usize) {
+        debug_assert!(!self.contains(i));
+        self.bits[i / 64] |= 1 << (i % 64);
+        self.len += 1;
+        self.hint = self.hint.min(i / 64);
+    }
+    /// Whether `i` was in the set.
+    fn remove(&mut self, i: usize) -> bool {
+        if !self.contains(i) {
+            return false;
+        }
+        self.bits[i / 64] &= !(1 << (i % 64));
+        self.len -= 1;
+        true
+    }
+    /// The lowest index in the set.
+    fn first(&mut self) -> Option<usize> {
+        if self.len == 0 {
+            return None;
+        }
+        while self.bits[self.hint] == 0 {
+            self.hint += 1;
+        }
+        Some(self.hint * 64 + self.bits[self.hint].trailing_zeros() as usize)
+    }
+}
+
+/// Free memory is kept as blocks of 2^order frames, each aligned to its
+/// size. A request splits the smallest block that fits, and a freed frame
+/// merges with its free buddy, up to `MAX_ORDER`.
+#[cfg_attr(feature = "bitmap_allocator", allow(dead_code))]
+pub struct BuddyFrameAllocator {
+    start: usize,
     end: usize,
-    recycled: Vec<usize>,
+    /// `start` rounded down to the largest block, block `i` of an order
+    /// starts at frame `base + (i << order)`
+    base: usize,
+    /// The free blocks of each order
+    free: [BlockSet; MAX_ORDER + 1],
+    allocated: usize,
 }
 
-impl StackFrameAllocator {
+#[cfg_attr(feature = "bitmap_allocator", allow(dead_code))]
+impl BuddyFrameAllocator {
     pub fn init(&mut self, l: PhysPageNum, r: PhysPageNum) {
-        self.current = l.0;
+        self.start = l.0;
         self.end = r.0;
-        debug!("last {} Physical Frames.", self.end - self.current);
+        self.base = l.0 >> MAX_ORDER << MAX_ORDER;
+        for (order, free) in self.free.iter_mut().enumerate() {
+            *free = BlockSet::with_capacity(((r.0 - self.base) >> order) + 1);
+        }
+        // cut the range into the largest aligned blocks
+        let mut ppn = l.0;
+        while ppn < r.0 {
+            let mut order = MAX_ORDER;
+            while ppn % (1 << order) != 0 || ppn + (1 << order) > r.0 {
+                order -= 1;
+            }
+            self.free[order].insert((ppn - self.base) >> order);
+            ppn += 1 << order;
+        }
+        debug!("last {} Physical Frames.", self.end - self.start);
+    }
+    /// Number of free blocks of each order.
+    pub fn free_blocks(&self) -> [usize; MAX_ORDER + 1] {
+        let mut blocks = [0; MAX_ORDER + 1];
+        for (order, free) in self.free.iter().enumerate() {
+            blocks[order] = free.len;
+        }
+        blocks
+    }
+    fn is_free(&self, ppn: usize) -> bool {
+        (0..=MAX_ORDER).any(|order| self.free[order].contains((ppn - self.base) >> order))
     }
 }
-impl FrameAllocator for StackFrameAllocator {
+
+impl FrameAllocator for BuddyFrameAllocator {
     fn new() -> Self {
         Self {
-            current: 0,
+            start: 0,
             end: 0,
-            recycled: Vec::new(),
+            base: 0,
+            free: Default::default(),
+            allocated: 0,
         }
     }
     fn alloc(&mut self) -> Option<PhysPageNum> {
-        if let Some(ppn) = self.recycled.pop() {
-            Some(ppn.into())
-        } else if self.current == self.end {
-            None
-        } else {
-            self.current += 1;
-            Some((self.current - 1).into())
-        }
+        self.alloc_contiguous(0)
     }
+    /// Frames are freed one at a time, as their `FrameTracker`s drop.
     fn dealloc(&mut self, ppn: PhysPageNum) {
         let ppn = ppn.0;
         // validity check
-        if ppn >= self.current || self.recycled.iter().any(|v| *v == ppn) {
+        if ppn < self.start || ppn >= self.end || self.is_free(ppn) {
             panic!("Frame ppn={:#x} has not been allocated!", ppn);
         }
-        // recycle
-        self.recycled.push(ppn);
+        let mut block = ppn - self.base;
+        let mut order = 0;
+        while order < MAX_ORDER && self.free[order].remove((block >> order) ^ 1) {
+            block &= !(1 << order);
+            order += 1;
+        }
+        self.free[order].insert(block >> order);
+        self.allocated -= 1;
+    }
+    fn alloc_contiguous(&mut self, order: usize) -> Option<PhysPageNum> {
+        if order > MAX_ORDER {
+            return None;
+        }
+        let mut from = (order..=MAX_ORDER).find(|&from| self.free[from].len > 0)?;
+        let index = self.free[from].first().unwrap();
+        self.free[from].remove(index);
+        let block = index << from;
+        // give back the upper halves
+        while from > order {
+            from -= 1;
+            self.free[from].insert((block >> from) + 1);
+        }
+        self.allocated += 1 << order;
+        Some((self.base + block).into())
+    }
+    fn usage(&self) -> (usize, usize) {
+        (self.allocated, self.end - self.start)
+    }
+    fn contains(&self, ppn: PhysPageNum) -> bool {
+        self.start <= ppn.0 && ppn.0 < self.end
+    }
+}
+
+/// One bit per frame, set while the frame is free. Bookkeeping is sized
+/// once from the range given to `init`, and a double free is caught.
+#[cfg_attr(not(feature = "bitmap_allocator"), allow(dead_code))]
+pub struct BitmapFrameAllocator {
+    start: usize,
+    frames: usize,
+    free: Vec<u64>,
+    /// No word below this one has a free frame
+    hint: usize,
+}
+
+#[cfg_attr(not(feature = "bitmap_allocator"), allow(dead_code))]
+impl BitmapFrameAllocator {
+    pub fn init(&mut self, l: PhysPageNum, r: PhysPageNum) {
+        self.start = l.0;
+        self.frames = r.0 - l.0;
+        self.free = vec![0; (self.frames + 63) / 64];
+        for i in 0..self.frames {
+            self.free[i / 64] |= 1 << (i % 64);
+        }
+        self.hint = 0;
+        debug!("last {} Physical Frames.", self.frames);
+    }
+    pub fn is_free(&self, ppn: PhysPageNum) -> bool {
+        let index = ppn.0 - self.start;
+        self.free[index / 64] & (1 << (index % 64)) != 0
+    }
+    /// What is wrong with freeing `ppn`, if anything.
+    fn dealloc_error(&self, ppn: PhysPageNum) -> Option<&'static str> {
+        if !self.contains(ppn) {
+            Some("is not managed")
+        } else if self.is_free(ppn) {
+            Some("has not been allocated")
+        } else {
+            None
+        }
+    }
+}
+
+impl FrameAllocator for BitmapFrameAllocator {
+    fn new() -> Self {
+        Self {
+            start: 0,
+            frames: 0,
+            free: Vec::new(),
+            hint: 0,
+        }
+    }
+    fn alloc(&mut self) -> Option<PhysPageNum> {
+        for word in self.hint..self.free.len() {
+            let bits = self.free[word];
+            if bits != 0 {
+                let bit = bits.trailing_zeros() as usize;
+                self.free[word] &= !(1 << bit);
+                self.hint = word;
+                return Some((self.start + word * 64 + bit).into());
+            }
+        }
+        self.hint = self.free.len();
+        None
+    }
+    fn dealloc(&mut self, ppn: PhysPageNum) {
+        // validity check
+        if let Some(error) = self.dealloc_error(ppn) {
+            panic!("Frame ppn={:#x} {}!", ppn.0, error);
+        }
+        let index = ppn.0 - self.start;
+        self.free[index / 64] |= 1 << (index % 64);
+        self.hint = self.hint.min(index / 64);
+    }
+    fn alloc_contiguous(&mut self, order: usize) -> Option<PhysPageNum> {
+        let n = 1 << order;
+        // the first index whose frame is aligned
+        let mut first = (self.start + n - 1) / n * n - self.start;
+        while first + n <= self.frames {
+            if (first..first + n).all(|i| self.free[i / 64] & (1 << (i % 64)) != 0) {
+                for i in first..first + n {
+                    self.free[i / 64] &= !(1 << (i % 64));
+                }
+                return Some((self.start + first).into());
+            }
+            first += n;
+        }
+        None
+    }
+    fn usage(&self) -> (usize, usize) {
+        let free: usize = self
+            .free
+            .iter()
+            .map(|bits| bits.count_ones() as usize)
+            .sum();
+        (self.frames - free, self.frames)
+    }
+    fn contains(&self, ppn: PhysPageNum) -> bool {
+        self.start <= ppn.0 && ppn.0 - self.start < self.frames
+    }
+}
+
+#[cfg(not(feature = "bitmap_allocator"))]
+type FrameAllocatorImpl = BuddyFrameAllocator;
+#[cfg(feature = "bitmap_allocator")]
+type FrameAllocatorImpl = BitmapFrameAllocator;
+
+/// Which kind of memory the frames of an area should come from, on boards
+/// with more than one. Either kind is taken from the other once it runs
+/// out.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Placement {
+    /// Main memory
+    Normal,
+    /// Memory faster but much smaller than main memory, the on-chip SRAM
+    /// of the LRV board, for the structures touched on every trap
+    Fast,
+}
+
+/// A frame allocator for each region of physical memory, main memory
+/// first.
+pub struct RegionFrameAllocator {
+    regions: Vec<(Placement, FrameAllocatorImpl)>,
+}
+
+impl RegionFrameAllocator {
+    pub fn add_region(&mut self, placement: Placement, l: PhysPageNum, r: PhysPageNum) {
+        let mut allocator = FrameAllocatorImpl::new();
+        allocator.init(l, r);
+        self.regions.push((placement, allocator));
+    }
+    /// Try `alloc` on the regions of `placement`, then on the others.
+    fn alloc_from<F>(&mut self, placement: Placement, mut alloc: F) -> Option<PhysPageNum>
+    where
+        F: FnMut(&mut FrameAllocatorImpl) -> Option<PhysPageNum>,
+    {
+        for &preferred in [true, false].iter() {
+            for (region, allocator) in self.regions.iter_mut() {
+                if (*region == placement) == preferred {
+                    if let Some(ppn) = alloc(allocator) {
+                        return Some(ppn);
+                    }
+                }
+            }
+        }
+        None
+    }
+    pub fn alloc_in(&mut self, placement: Placement) -> Option<PhysPageNum> {
+        self.alloc_from(placement, |allocator| allocator.alloc())
+    }
+    /// The placement of the region `ppn` lies in.
+    pub fn placement(&self, ppn: PhysPageNum) -> Option<Placement> {
+        self.regions
+            .iter()
+            .find(|(_, allocator)| allocator.contains(ppn))
+            .map(|(placement, _)| *placement)
     }
 }
 
-type FrameAllocatorImpl = StackFrameAllocator;
+impl FrameAllocator for RegionFrameAllocator {
+    fn new() -> Self {
+        Self {
+            regions: Vec::new(),
+        }
+    }
+    fn alloc(&mut self) -> Option<PhysPageNum> {
+        self.alloc_in(Placement::Normal)
+    }
+    fn dealloc(&mut self, ppn: PhysPageNum) {
+        match self
+            .regions
+            .iter_mut()
+            .find(|(_, allocator)| allocator.contains(ppn))
+        {
+            Some((_, allocator)) => allocator.dealloc(ppn),
+            None => panic!("Frame ppn={:#x} is not managed!", ppn.0),
+        }
+    }
+    fn alloc_contiguous(&mut self, order: usize) -> Option<PhysPageNum> {
+        self.alloc_from(Placement::Normal, |allocator| {
+            allocator.alloc_contiguous(order)
+        })
+    }
+    fn usage(&self) -> (usize, usize) {
+        self.regions
+            .iter()
+            .map(|(_, allocator)| allocator.usage())
+            .fold((0, 0), |(used, total), (u, t)| (used + u, total + t))
+    }
+    fn contains(&self, ppn: PhysPageNum) -> bool {
+        self.placement(ppn).is_some()
+    }
+}
 
 lazy_static! {
-    pub static ref FRAME_ALLOCATOR: Mutex<FrameAllocatorImpl> =
-        Mutex::new(FrameAllocatorImpl::new());
+    pub static ref FRAME_ALLOCATOR: Mutex<RegionFrameAllocator> =
+        Mutex::new(RegionFrameAllocator::new());
 }
 
 pub fn init_frame_allocator() {
     extern "C" {
         fn ekernel();
     }
-    FRAME_ALLOCATOR.lock().init(
-        PhysAddr::from(ekernel as usize).ceil(),
-        PhysAddr::from(MEMORY_END).floor(),
-    );
+    let mut allocator = FRAME_ALLOCATOR.lock();
+    for (start, end) in machine().free_memory(ekernel as usize) {
+        allocator.add_region(
+            Placement::Normal,
+            PhysAddr::from(start).ceil(),
+            PhysAddr::from(end).floor(),
+        );
+    }
+    for &(start, end) in FAST_MEMORY.iter() {
+        allocator.add_region(
+            Placement::Fast,
+            PhysAddr::from(start).ceil(),
+            PhysAddr::from(end).floor(),
+        );
+    }
 }
 
 pub fn frame_alloc() -> Option<FrameTracker> {
     FRAME_ALLOCATOR.lock().alloc().map(FrameTracker::new)
 }
 
+/// A frame from the memory `placement` asks for, or from any other if
+/// that has none left.
+pub fn frame_alloc_in(placement: Placement) -> Option<FrameTracker> {
+    FRAME_ALLOCATOR
+        .lock()
+        .alloc_in(placement)
+        .map(FrameTracker::new)
+}
+
+/// 2^order physically contiguous frames, aligned to their total size,
+/// lowest first. Each frame is freed on its own as its tracker drops.
+pub fn frame_alloc_contiguous(order: usize) -> Option<Vec<FrameTracker>> {
+    let first = FRAME_ALLOCATOR.lock().alloc_contiguous(order)?;
+    Some(
+        (first.0..first.0 + (1 << order))
+            .map(|ppn| FrameTracker::new(ppn.into()))
+            .collect(),
+    )
+}
+
+/// 2^order contiguous frames given away for good, without trackers or
+/// zeroing, for the kernel heap to grow into.
+pub fn frame_alloc_permanent(order: usize) -> Option<PhysPageNum> {
+    FRAME_ALLOCATOR.lock().alloc_contiguous(order)
+}
+
 fn frame_dealloc(ppn: PhysPageNum) {
     FRAME_ALLOCATOR.lock().dealloc(ppn);
 }
 
+/// Return (allocated, total) physical frames.
+pub fn frame_usage() -> (usize, usize) {
+    FRAME_ALLOCATOR.lock().usage()
+}
+
 #[allow(unused)]
 pub fn frame_allocator_test() {
     let mut v: Vec<FrameTracker> = Vec::new();
@@ -122,3 +476,165 @@ pub fn frame_allocator_test() {
     drop(v);
     debug!("frame_allocator_test passed!");
 }
+
+#[allow(unused)]
+pub fn buddy_allocator_test() {
+    let mut allocator = BuddyFrameAllocator::new();
+    allocator.init(PhysPageNum(0x80400), PhysPageNum(0x80410));
+    assert_eq!(allocator.free_blocks()[4], 1);
+    // lowest first, splitting the block as it goes
+    let first: Vec<usize> = (0..4).map(|_| allocator.alloc().unwrap().0).collect();
+    assert_eq!(first, [0x80400, 0x80401, 0x80402, 0x80403]);
+    assert_eq!(allocator.usage(), (4, 16));
+    assert_eq!(allocator.free_blocks()[..5], [0, 0, 1, 1, 0]);
+    allocator.dealloc(PhysPageNum(0x80401));
+    assert_eq!(allocator.alloc().unwrap().0, 0x80401);
+    for &ppn in first.iter() {
+        allocator.dealloc(PhysPageNum(ppn));
+    }
+    // the buddies merged back into one block
+    assert_eq!(allocator.free_blocks()[4], 1);
+    assert_eq!(allocator.usage(), (0, 16));
+    // ranges not aligned to the largest block are cut into smaller ones
+    let mut allocator = BuddyFrameAllocator::new();
+    allocator.init(PhysPageNum(0x80403), PhysPageNum(0x80410));
+    assert_eq!(allocator.free_blocks()[..4], [1, 0, 1, 1]);
+    assert_eq!(allocator.usage(), (0, 13));
+    debug!("buddy_allocator_test passed!");
+}
+
+#[allow(unused)]
+pub fn bitmap_allocator_test() {
+    let mut allocator = BitmapFrameAllocator::new();
+    allocator.init(PhysPageNum(0x80400), PhysPageNum(0x80400 + 100));
+    assert_eq!(allocator.usage(), (0, 100));
+    let frames: Vec<PhysPageNum> = (0..70).map(|_| allocator.alloc().unwrap()).collect();
+    assert_eq!(frames[69].0, 0x80400 + 69);
+    assert_eq!(allocator.usage(), (70, 100));
+    allocator.dealloc(frames[3]);
+    allocator.dealloc(frames[65]);
+    assert_eq!(allocator.usage(), (68, 100));
+    // the lowest free frame comes back first
+    assert_eq!(allocator.alloc().unwrap().0, frames[3].0);
+    assert_eq!(allocator.alloc().unwrap().0, frames[65].0);
+    assert_eq!(allocator.usage(), (70, 100));
+    // freeing frames[3] twice panics in dealloc, which can not be survived
+    // here; check what makes it panic instead
+    allocator.dealloc(frames[3]);
+    assert!(allocator.is_free(frames[3]));
+    assert_eq!(
+        allocator.dealloc_error(frames[3]),
+        Some("has not been allocated")
+    );
+    assert_eq!(
+        allocator.dealloc_error(PhysPageNum(0x80400 + 100)),
+        Some("is not managed")
+    );
+    assert_eq!(allocator.dealloc_error(frames[4]), None);
+    for &frame in frames.iter().filter(|frame| frame.0 != frames[3].0) {
+        allocator.dealloc(frame);
+    }
+    assert_eq!(allocator.usage(), (0, 100));
+    for _ in 0..100 {
+        allocator.alloc().unwrap();
+    }
+    assert!(allocator.alloc().is_none());
+    // all 128 MiB of RAM on QEMU
+    let mut allocator = BitmapFrameAllocator::new();
+    allocator.init(PhysPageNum(0x80000), PhysPageNum(0x88000));
+    assert_eq!(allocator.usage(), (0, 0x8000));
+    debug!("bitmap_allocator_test passed!");
+}
+
+#[allow(unused)]
+pub fn contiguous_alloc_test() {
+    let mut allocator = BuddyFrameAllocator::new();
+    allocator.init(PhysPageNum(0x80400), PhysPageNum(0x80410));
+    allocator.alloc().unwrap();
+    // aligned to their size, past the single frame
+    assert_eq!(allocator.alloc_contiguous(2).unwrap().0, 0x80404);
+    assert_eq!(allocator.alloc().unwrap().0, 0x80401);
+    assert!(allocator.alloc_contiguous(4).is_none());
+    assert_eq!(allocator.alloc_contiguous(3).unwrap().0, 0x80408);
+    assert_eq!(allocator.usage(), (14, 16));
+
+    let mut allocator = BitmapFrameAllocator::new();
+    allocator.init(PhysPageNum(0x80400), PhysPageNum(0x80400 + 100));
+    let frames: Vec<PhysPageNum> = (0..70).map(|_| allocator.alloc().unwrap()).collect();
+    // holes of 1 and 3 frames are too small for 4, and the one of 4 frames
+    // at 30 is not aligned
+    allocator.dealloc(frames[10]);
+    for frame in frames[20..23].iter().chain(frames[30..34].iter()) {
+        allocator.dealloc(*frame);
+    }
+    assert_eq!(allocator.alloc_contiguous(1).unwrap().0, frames[20].0);
+    assert_eq!(allocator.alloc_contiguous(2).unwrap().0, 0x80400 + 72);
+    assert_eq!(allocator.usage(), (68, 100));
+    assert!(allocator.alloc_contiguous(5).is_none());
+    debug!("contiguous_alloc_test passed!");
+}
+
+#[allow(unused)]
+pub fn frame_coalesce_test() {
+    let mut allocator = BuddyFrameAllocator::new();
+    allocator.init(PhysPageNum(0x80400), PhysPageNum(0x80410));
+    let frames: Vec<PhysPageNum> = (0..16).map(|_| allocator.alloc().unwrap()).collect();
+    assert!(allocator.alloc_contiguous(3).is_none());
+    // free frames 8..16 scattered, the blocks merge as the gaps close
+    for &i in [9, 12, 15, 13, 8, 10, 14].iter() {
+        allocator.dealloc(frames[i]);
+    }
+    assert_eq!(allocator.free_blocks()[..4], [1, 1, 1, 0]);
+    assert!(allocator.alloc_contiguous(3).is_none());
+    allocator.dealloc(frames[11]);
+    assert_eq!(allocator.free_blocks()[..4], [0, 0, 0, 1]);
+    assert_eq!(allocator.usage(), (8, 16));
+    assert_eq!(allocator.alloc_contiguous(3).unwrap().0, frames[8].0);
+    assert_eq!(allocator.usage(), (16, 16));
+    // free frames that are not buddies stay apart
+    allocator.dealloc(frames[1]);
+    allocator.dealloc(frames[2]);
+    assert_eq!(allocator.free_blocks()[..2], [2, 0]);
+    assert_eq!(allocator.alloc().unwrap().0, frames[1].0);
+    assert_eq!(allocator.alloc().unwrap().0, frames[2].0);
+    assert!(allocator.alloc().is_none());
+    debug!("frame_coalesce_test passed!");
+}
+
+#[allow(unused)]
+pub fn region_allocator_test() {
+    let mut allocator = RegionFrameAllocator::new();
+    allocator.add_region(
+        Placement::Normal,
+        PhysPageNum(0x80400),
+        PhysPageNum(0x80404),
+    );
+    allocator.add_region(Placement::Fast, PhysPageNum(0x20000), PhysPageNum(0x20002));
+    assert_eq!(allocator.usage(), (0, 6));
+    // each placement takes its own region first
+    assert_eq!(allocator.alloc_in(Placement::Fast).unwrap().0, 0x20000);
+    assert_eq!(allocator.alloc().unwrap().0, 0x80400);
+    assert_eq!(
+        allocator.placement(PhysPageNum(0x20000)),
+        Some(Placement::Fast)
+    );
+    assert_eq!(
+        allocator.placement(PhysPageNum(0x80403)),
+        Some(Placement::Normal)
+    );
+    assert_eq!(allocator.placement(PhysPageNum(0x20002)), None);
+    // and falls back to the other one once it runs out
+    assert_eq!(allocator.alloc_in(Placement::Fast).unwrap().0, 0x20001);
+    assert_eq!(allocator.alloc_in(Placement::Fast).unwrap().0, 0x80401);
+    for _ in 0..2 {
+        allocator.alloc().unwrap();
+    }
+    assert!(allocator.alloc().is_none());
+    allocator.dealloc(PhysPageNum(0x20001));
+    assert_eq!(allocator.alloc().unwrap().0, 0x20001);
+    assert_eq!(allocator.usage(), (6, 6));
+    // frees go back to the region they came from
+    allocator.dealloc(PhysPageNum(0x80402));
+    assert_eq!(allocator.alloc_in(Placement::Fast).unwrap().0, 0x80402);
+    debug!("region_allocator_test passed!");
+}
@@ -1,8 +1,25 @@
-use crate::config::KERNEL_HEAP_SIZE;
+use super::frame_allocator::{frame_alloc_permanent, MAX_ORDER};
+use super::PhysAddr;
+use crate::config::{CPU_NUM, KERNEL_HEAP_SIZE, PAGE_SIZE};
+use crate::task::hart_id;
 use buddy_system_allocator::LockedHeap;
+use core::alloc::{GlobalAlloc, Layout};
+use core::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "heap_tracking")]
+use {alloc::collections::BTreeMap, lazy_static::*, spin::Mutex};
+
+/// The kernel heap. It starts out as `HEAP_SPACE` and grows by blocks of
+/// frames when an allocation does not fit, see `grow`. With
+/// `heap_tracking` every allocation made while an `AllocTag` is active is
+/// recorded with its size and tag.
+pub struct TrackedAllocator {
+    heap: LockedHeap,
+}
 
 #[global_allocator]
-static HEAP_ALLOCATOR: LockedHeap = LockedHeap::empty();
+static HEAP_ALLOCATOR: TrackedAllocator = TrackedAllocator {
+    heap: LockedHeap::empty(),
+};
 
 #[alloc_error_handler]
 pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
@@ -14,11 +31,184 @@ static mut HEAP_SPACE: [u8; KERNEL_HEAP_SIZE] = [0; KERNEL_HEAP_SIZE];
 pub fn init_heap() {
     unsafe {
         HEAP_ALLOCATOR
+            .heap
             .lock()
             .init(HEAP_SPACE.as_ptr() as usize, KERNEL_HEAP_SIZE);
     }
 }
 
+#[cfg(feature = "heap_tracking")]
+lazy_static! {
+    /// Address of every live tagged allocation to its size and tag.
+    static ref TRACKED: Mutex<BTreeMap<usize, (usize, &'static str)>> = Mutex::new(BTreeMap::new());
+}
+
+/// Smallest block the heap grows by, 2^HEAP_GROW_ORDER frames.
+const HEAP_GROW_ORDER: usize = 4;
+
+const NOT_GROWING: AtomicBool = AtomicBool::new(false);
+/// Set while a hart takes frames for the heap, so that it never recurses
+/// into growing it. Growing takes the frame allocator's lock, which is why
+/// the frame allocator sizes its bookkeeping once and never allocates
+/// under that lock.
+static GROWING: [AtomicBool; CPU_NUM] = [NOT_GROWING; CPU_NUM];
+
+impl TrackedAllocator {
+    /// Add a block of frames large enough for `layout` to the heap. The
+    /// frames are never given back. Physical memory is identity mapped in
+    /// the kernel space, so they need no mapping of their own.
+    fn grow(&self, layout: Layout) -> bool {
+        let hart = hart_id();
+        if GROWING[hart].swap(true, Ordering::Acquire) {
+            return false;
+        }
+        let pages = (layout.size().max(layout.align()) + PAGE_SIZE - 1) / PAGE_SIZE;
+        let order = pages.next_power_of_two().trailing_zeros() as usize;
+        let order = order.max(HEAP_GROW_ORDER);
+        let grown = order <= MAX_ORDER
+            && match frame_alloc_permanent(order) {
+                Some(ppn) => {
+                    let start = PhysAddr::from(ppn).0;
+                    unsafe {
+                        self.heap
+                            .lock()
+                            .add_to_heap(start, start + (PAGE_SIZE << order));
+                    }
+                    true
+                }
+                None => false,
+            };
+        GROWING[hart].store(false, Ordering::Release);
+        grown
+    }
+}
+
+#[cfg(feature = "heap_tracking")]
+const NO_TAG: Mutex<Option<&'static str>> = Mutex::new(None);
+/// Tag of the allocations currently made on each hart.
+#[cfg(feature = "heap_tracking")]
+static ALLOC_TAG: [Mutex<Option<&'static str>>; CPU_NUM] = [NO_TAG; CPU_NUM];
+#[cfg(feature = "heap_tracking")]
+const NOT_TRACKING: AtomicBool = AtomicBool::new(false);
+/// Set while a hart updates `TRACKED`, whose own nodes are not tracked.
+#[cfg(feature = "heap_tracking")]
+static TRACKING: [AtomicBool; CPU_NUM] = [NOT_TRACKING; CPU_NUM];
+
+#[cfg(feature = "heap_tracking")]
+impl TrackedAllocator {
+    /// Run `f` on `TRACKED` unless this hart is already inside it.
+    fn track(f: impl FnOnce(&mut BTreeMap<usize, (usize, &'static str)>)) {
+        let hart = hart_id();
+        if TRACKING[hart].swap(true, Ordering::Acquire) {
+            return;
+        }
+        f(&mut TRACKED.lock());
+        TRACKING[hart].store(false, Ordering::Release);
+    }
+}
+
+unsafe impl GlobalAlloc for TrackedAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut ptr = self.heap.alloc(layout);
+        if ptr.is_null() && self.grow(layout) {
+            ptr = self.heap.alloc(layout);
+        }
+        #[cfg(feature = "heap_tracking")]
+        if !ptr.is_null() {
+            if let Some(tag) = *ALLOC_TAG[hart_id()].lock() {
+                Self::track(|tracked| {
+                    tracked.insert(ptr as usize, (layout.size(), tag));
+                });
+            }
+        }
+        ptr
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        #[cfg(feature = "heap_tracking")]
+        Self::track(|tracked| {
+            tracked.remove(&(ptr as usize));
+        });
+        self.heap.dealloc(ptr, layout)
+    }
+}
+
+/// Tags the allocations made on this hart until it is dropped, restoring
+/// the previous tag.
+pub struct AllocTag {
+    #[cfg(feature = "heap_tracking")]
+    previous: Option<&'static str>,
+}
+
+impl AllocTag {
+    #[allow(unused_variables)]
+    pub fn new(tag: &'static str) -> Self {
+        Self {
+            #[cfg(feature = "heap_tracking")]
+            previous: ALLOC_TAG[hart_id()].lock().replace(tag),
+        }
+    }
+}
+
+#[cfg(feature = "heap_tracking")]
+impl Drop for AllocTag {
+    fn drop(&mut self) {
+        *ALLOC_TAG[hart_id()].lock() = self.previous;
+    }
+}
+
+/// Evaluate an expression such as `Arc::new(..)` with its allocations
+/// tagged, see `heap_stats`.
+#[macro_export]
+macro_rules! track_alloc {
+    ($tag: expr, $e: expr) => {{
+        let _tag = $crate::mm::AllocTag::new($tag);
+        $e
+    }};
+}
+
+/// Allocate `layout` from the kernel heap under `tag`.
+///
+/// # Safety
+///
+/// As for `GlobalAlloc::alloc`.
+#[allow(dead_code)]
+pub unsafe fn alloc_tagged(layout: Layout, tag: &'static str) -> *mut u8 {
+    let _tag = AllocTag::new(tag);
+    alloc::alloc::alloc(layout)
+}
+
+/// Return (allocated, total) bytes of the kernel heap, allocations counted
+/// with what they are rounded up to.
+pub fn heap_usage() -> (usize, usize) {
+    let heap = HEAP_ALLOCATOR.heap.lock();
+    (heap.stats_alloc_actual(), heap.stats_total_bytes())
+}
+
+#[derive(Debug, Default)]
+pub struct HeapStats {
+    pub live_bytes: usize,
+    /// Tag to the number and total size of its live allocations
+    pub per_tag: alloc::collections::BTreeMap<&'static str, (usize, usize)>,
+}
+
+/// Live tagged allocations. Empty without `heap_tracking`.
+pub fn heap_stats() -> HeapStats {
+    #[allow(unused_mut)]
+    let mut stats = HeapStats::default();
+    #[cfg(feature = "heap_tracking")]
+    {
+        let live: alloc::vec::Vec<(usize, &'static str)> =
+            TRACKED.lock().values().copied().collect();
+        for (size, tag) in live {
+            stats.live_bytes += size;
+            let entry = stats.per_tag.entry(tag).or_insert((0, 0));
+            entry.0 += 1;
+            entry.1 += size;
+        }
+    }
+    stats
+}
+
 #[allow(unused)]
 pub fn heap_test() {
     use alloc::boxed::Box;
@@ -43,3 +233,48 @@ pub fn heap_test() {
     drop(v);
     debug!("heap_test passed!");
 }
+
+#[allow(unused)]
+pub fn heap_grow_test() {
+    use alloc::boxed::Box;
+    use alloc::vec::Vec;
+    let (_, total) = heap_usage();
+    let mut pages: Vec<Box<[u8; PAGE_SIZE]>> = Vec::with_capacity(KERNEL_HEAP_SIZE / PAGE_SIZE);
+    // fill up what is left of the heap, and a bit more
+    while heap_usage().1 == total {
+        pages.push(Box::new([0; PAGE_SIZE]));
+    }
+    assert!(heap_usage().1 >= total + (PAGE_SIZE << HEAP_GROW_ORDER));
+    pages.push(Box::new([1; PAGE_SIZE]));
+    assert!(pages.last().unwrap().iter().all(|&byte| byte == 1));
+    drop(pages);
+    debug!("heap_grow_test passed!");
+}
+
+#[cfg(feature = "heap_tracking")]
+#[allow(unused)]
+pub fn heap_tracking_test() {
+    use alloc::boxed::Box;
+    use alloc::vec::Vec;
+    let mut boxes: Vec<Box<[u8; 24]>> = Vec::with_capacity(10);
+    for _ in 0..10 {
+        boxes.push(track_alloc!("heap_tracking_test", Box::new([0u8; 24])));
+    }
+    let stats = heap_stats();
+    assert_eq!(stats.per_tag.get("heap_tracking_test"), Some(&(10, 240)));
+    boxes.truncate(5);
+    let stats = heap_stats();
+    assert_eq!(stats.per_tag.get("heap_tracking_test"), Some(&(5, 120)));
+    // nested tags restore the outer one
+    let outer = track_alloc!("outer", {
+        let inner = track_alloc!("inner", Box::new(1u64));
+        (Box::new(2u64), inner)
+    });
+    let stats = heap_stats();
+    assert_eq!(stats.per_tag.get("outer"), Some(&(1, 8)));
+    assert_eq!(stats.per_tag.get("inner"), Some(&(1, 8)));
+    drop(outer);
+    drop(boxes);
+    assert!(heap_stats().per_tag.get("heap_tracking_test").is_none());
+    debug!("heap_tracking_test passed!");
+}
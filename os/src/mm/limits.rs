@@ -0,0 +1,86 @@
+//! Per-process memory limits, as `prlimit` reads and sets them. A space
+//! past a limit gets ENOMEM for mappings and faults that would grow it,
+//! instead of running the whole system out of frames.
+
+use crate::errno::{EINVAL, EPERM};
+
+/// No limit at all.
+pub const RLIM_INFINITY: usize = usize::MAX;
+
+/// Bytes of pages backed by frames, with the numbers of Linux.
+pub const RLIMIT_RSS: usize = 5;
+/// Bytes of user mappings.
+pub const RLIMIT_AS: usize = 9;
+
+/// A limit enforced at `cur`, which anyone may move up to `max`. Only root
+/// raises `max`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RLimit {
+    pub cur: usize,
+    pub max: usize,
+}
+
+impl RLimit {
+    pub const INFINITY: Self = Self {
+        cur: RLIM_INFINITY,
+        max: RLIM_INFINITY,
+    };
+    /// Whether `bytes` in use would be past the limit.
+    pub fn exceeded_by(&self, bytes: usize) -> bool {
+        self.cur != RLIM_INFINITY && bytes > self.cur
+    }
+}
+
+/// The limits of a user space, kept across `exec` and inherited on fork.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MemoryLimits {
+    /// User mappings, reserved ranges aside
+    pub address_space: RLimit,
+    /// User pages backed by frames, the shared zero frame aside
+    pub resident: RLimit,
+}
+
+impl Default for MemoryLimits {
+    fn default() -> Self {
+        Self {
+            address_space: RLimit::INFINITY,
+            resident: RLimit::INFINITY,
+        }
+    }
+}
+
+impl MemoryLimits {
+    fn limit_mut(&mut self, resource: usize) -> Option<&mut RLimit> {
+        match resource {
+            RLIMIT_AS => Some(&mut self.address_space),
+            RLIMIT_RSS => Some(&mut self.resident),
+            _ => None,
+        }
+    }
+    pub fn get(&self, resource: usize) -> Option<RLimit> {
+        match resource {
+            RLIMIT_AS => Some(self.address_space),
+            RLIMIT_RSS => Some(self.resident),
+            _ => None,
+        }
+    }
+    /// Replace the limit on `resource`, returning the old one. Fails with
+    /// EINVAL for a resource without a limit here or `cur` above `max`,
+    /// and with EPERM for raising `max` without being `privileged`.
+    pub fn set(
+        &mut self,
+        resource: usize,
+        limit: RLimit,
+        privileged: bool,
+    ) -> Result<RLimit, isize> {
+        let old = self.limit_mut(resource).ok_or(EINVAL)?;
+        if limit.cur > limit.max {
+            return Err(EINVAL);
+        }
+        if limit.max > old.max && !privileged {
+            return Err(EPERM);
+        }
+        Ok(core::mem::replace(old, limit))
+    }
+}
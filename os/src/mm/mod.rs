@@ -1,26 +1,123 @@
 mod address;
+mod asid;
+mod checkpoint;
+mod fault;
 mod frame_allocator;
 mod heap_allocator;
+mod limits;
 mod memory_set;
 mod page_table;
+mod rmap;
+mod shared;
+mod stats;
+mod swap;
+mod tlb;
+mod uaccess;
+mod vmalloc;
 
 pub use address::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
 use address::{StepByOne, VPNRange};
-pub use frame_allocator::{frame_alloc, FrameTracker};
+pub use checkpoint::InodeStream;
+pub use fault::{AccessType, FaultKind};
+pub use frame_allocator::{frame_alloc, frame_alloc_contiguous, frame_usage, FrameTracker};
+pub use frame_allocator::{frame_alloc_in, Placement};
+pub use heap_allocator::{heap_stats, AllocTag};
+pub use limits::{MemoryLimits, RLimit, RLIM_INFINITY};
+#[cfg(debug_assertions)]
+pub use memory_set::paging_audit;
 pub use memory_set::remap_test;
-pub use memory_set::{MapPermission, MemorySet, KERNEL_SPACE};
-pub use page_table::{
-    translate_writable_va, translated_byte_buffer, translated_refmut, translated_str,
-    PageTableEntry, UserBuffer, UserBufferIterator,
-};
-use page_table::{PTEFlags, PageTable};
+pub use memory_set::{kernel_ioremap, MapPermission, MemorySet, MmapFlags, KERNEL_SPACE};
+pub use page_table::{translate_writable_va, PageTableEntry};
+use page_table::{PTEFlags, PageTable, TranslateResult, HUGE_PAGES};
+pub use shared::{shm_create, shm_get, shm_release, SharedFrame};
+pub use stats::{stats, MemStats};
+pub use swap::{reclaim, swap_usage, SwapSlot};
+use tlb::TlbFlushBatch;
+pub use uaccess::{copy_from_user, copy_to_user, strncpy_from_user, UserPtr, PATH_MAX};
+pub use uaccess::{UserBuffer, UserBufferIterator};
 
 pub fn init() {
     heap_allocator::init_heap();
     frame_allocator::init_frame_allocator();
     KERNEL_SPACE.lock().activate();
+    asid::init();
 }
 
 pub fn init_kernel_space() {
     KERNEL_SPACE.lock().activate();
 }
+
+/// Run the memory management tests. The heap ones go first, while every
+/// allocation still lands in the initial heap, and the one running out of
+/// frames goes last.
+#[cfg(debug_assertions)]
+pub fn self_test() {
+    heap_allocator::heap_test();
+    #[cfg(feature = "heap_tracking")]
+    heap_allocator::heap_tracking_test();
+    heap_allocator::heap_grow_test();
+    frame_allocator::frame_allocator_test();
+    frame_allocator::buddy_allocator_test();
+    frame_allocator::bitmap_allocator_test();
+    frame_allocator::contiguous_alloc_test();
+    frame_allocator::frame_coalesce_test();
+    frame_allocator::region_allocator_test();
+    asid::asid_test();
+    stats::stats_test();
+    tlb::tlb_batch_test();
+    page_table::translate_cache_test();
+    page_table::translate_detailed_test();
+    page_table::huge_page_test();
+    page_table::accessed_scan_test();
+    page_table::page_table_walk_test();
+    page_table::shrink_test();
+    vmalloc::vmalloc_test();
+    uaccess::uaccess_test();
+    rmap::rmap_test();
+    fault::fault_classify_test();
+    fault::handle_fault_test();
+    swap::swap_test();
+    memory_set::guard_page_test();
+    memory_set::relocate_test();
+    memory_set::reserve_commit_test();
+    memory_set::exec_replace_test();
+    memory_set::elf_align_test();
+    memory_set::mmio_munmap_test();
+    memory_set::remap_to_test();
+    memory_set::region_digest_test();
+    memory_set::reserve_commit_perm_test();
+    memory_set::deep_clone_test();
+    memory_set::template_cow_test();
+    memory_set::from_descriptors_test();
+    memory_set::elf_stack_room_test();
+    memory_set::activate_masked_test();
+    memory_set::mmio_regions_test();
+    memory_set::ioremap_test();
+    memory_set::new_kernel_from_test();
+    memory_set::mlock_test();
+    memory_set::null_page_test();
+    memory_set::trap_context_region_test();
+    memory_set::stack_non_executable_test();
+    memory_set::transfer_region_test();
+    memory_set::range_within_single_area_test();
+    memory_set::lazy_area_test();
+    memory_set::demand_paged_elf_test();
+    memory_set::checkpoint_test();
+    memory_set::memory_limits_test();
+    memory_set::wx_test();
+    memory_set::mprotect_test();
+    memory_set::mremap_test();
+    memory_set::brk_test();
+    memory_set::shared_area_test();
+    memory_set::stack_growth_test();
+    memory_set::huge_area_test();
+    memory_set::mmap_flags_test();
+    memory_set::munmap_split_test();
+    memory_set::zero_page_test();
+    memory_set::find_free_area_test();
+    memory_set::aslr_test();
+    memory_set::dump_test();
+    memory_set::madvise_test();
+    memory_set::mmap_file_test();
+    memory_set::oom_test();
+}
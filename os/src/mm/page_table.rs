@@ -1,8 +1,13 @@
-use super::{frame_alloc, FrameTracker, PhysAddr, PhysPageNum, StepByOne, VirtAddr, VirtPageNum};
-use alloc::string::String;
+use super::memory_set::{fault_in_current, is_zero_ppn};
+use super::rmap;
+use super::{frame_alloc, AccessType, FrameTracker, PhysAddr, PhysPageNum};
+use super::{VPNRange, VirtAddr, VirtPageNum};
+use crate::errno::ENOMEM;
+use alloc::collections::{BTreeMap, BTreeSet};
 use alloc::vec;
 use alloc::vec::Vec;
 use bitflags::*;
+use core::cell::Cell;
 
 bitflags! {
     pub struct PTEFlags: u8 {
@@ -17,6 +22,9 @@ bitflags! {
     }
 }
 
+/// Pages in a 2 MiB megapage, mapped by a single level-1 leaf entry.
+pub const HUGE_PAGES: usize = 512;
+
 #[derive(Copy, Clone)]
 #[repr(C)]
 pub struct PageTableEntry {
@@ -50,20 +58,72 @@ impl PageTableEntry {
     pub fn executable(&self) -> bool {
         (self.flags() & PTEFlags::X) != PTEFlags::empty()
     }
+    pub fn is_user(&self) -> bool {
+        (self.flags() & PTEFlags::U) != PTEFlags::empty()
+    }
+    /// A valid entry with any of R, W and X maps memory instead of pointing
+    /// to the next level table.
+    pub fn is_leaf(&self) -> bool {
+        self.is_valid()
+            && self
+                .flags()
+                .intersects(PTEFlags::R | PTEFlags::W | PTEFlags::X)
+    }
+    /// The entry `vpn` would have as a single page, given the leaf covering
+    /// it was found at `depth`.
+    fn page_of(&self, vpn: VirtPageNum, depth: usize) -> Self {
+        if depth == 2 || !self.is_valid() {
+            return *self;
+        }
+        let pages = 1 << (9 * (2 - depth));
+        PageTableEntry::new(PhysPageNum(self.ppn().0 + vpn.0 % pages), self.flags())
+    }
+}
+
+/// Something wrong with a page table, found by `PageTable::audit`.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum PagingIssue {
+    /// A leaf both writable and executable.
+    WritableExecutable(VirtPageNum),
+    /// A user leaf in the kernel space.
+    UserInKernel(VirtPageNum),
+    /// A table without a valid entry left, pointed to from `depth`.
+    EmptyTable(VirtPageNum, usize),
+}
+
+/// Outcome of walking the page table for a single vpn.
+#[derive(Copy, Clone)]
+pub enum TranslateResult {
+    Mapped(PageTableEntry),
+    /// The leaf entry exists but is not valid.
+    Reserved,
+    /// Some intermediate table on the way to the leaf is missing.
+    Absent,
 }
 
 pub struct PageTable {
     root_ppn: PhysPageNum,
     frames: Vec<FrameTracker>,
+    /// Entries in use in each table of `frames`, for `shrink` to tell the
+    /// empty ones. Empty for a table from `from_token`.
+    valid_entries: BTreeMap<PhysPageNum, usize>,
+    /// The last valid entry returned by `translate`. Dropped whenever its vpn
+    /// is mapped or unmapped; hardware A/D updates are not reflected.
+    last_translated: Cell<Option<(VirtPageNum, PageTableEntry)>>,
 }
 
-/// Assume that it won't oom when creating/mapping.
+/// Only `try_map` copes with running out of frames for tables, the rest
+/// assume it won't happen.
 impl PageTable {
     pub fn new() -> Self {
         let frame = frame_alloc().unwrap();
+        let mut valid_entries = BTreeMap::new();
+        valid_entries.insert(frame.ppn, 0);
         PageTable {
             root_ppn: frame.ppn,
             frames: vec![frame],
+            valid_entries,
+            last_translated: Cell::new(None),
         }
     }
     /// Temporarily used to get arguments from user space.
@@ -71,79 +131,374 @@ impl PageTable {
         Self {
             root_ppn: PhysPageNum::from(satp & ((1usize << 44) - 1)),
             frames: Vec::new(),
+            valid_entries: BTreeMap::new(),
+            last_translated: Cell::new(None),
         }
     }
-    fn find_pte_create(&mut self, vpn: VirtPageNum) -> Option<&mut PageTableEntry> {
+    /// The entry for `vpn` at `depth`, 2 for a page and 1 for a megapage,
+    /// and the table it is in, creating the tables on the way. `None` if
+    /// there is no frame left for a table.
+    fn find_pte_create(
+        &mut self,
+        vpn: VirtPageNum,
+        depth: usize,
+    ) -> Option<(&mut PageTableEntry, PhysPageNum)> {
+        // the caller is about to modify this entry
+        self.invalidate_cached(vpn);
         let idxs = vpn.indexes();
         let mut ppn = self.root_ppn;
-        let mut result: Option<&mut PageTableEntry> = None;
+        let mut result: Option<(&mut PageTableEntry, PhysPageNum)> = None;
         for i in 0..3 {
             let pte = &mut ppn.get_pte_array()[idxs[i]];
-            if i == 2 {
-                result = Some(pte);
+            if i == depth {
+                result = Some((pte, ppn));
                 break;
             }
+            assert!(!pte.is_leaf(), "vpn {:?} is inside a huge page", vpn);
             if !pte.is_valid() {
-                let frame = frame_alloc().unwrap();
+                let frame = frame_alloc()?;
                 *pte = PageTableEntry::new(frame.ppn, PTEFlags::V);
+                self.entry_changed(ppn, true);
+                if !self.frames.is_empty() {
+                    self.valid_entries.insert(frame.ppn, 0);
+                }
                 self.frames.push(frame);
             }
             ppn = pte.ppn();
         }
         result
     }
-    fn find_pte(&self, vpn: VirtPageNum) -> Option<&PageTableEntry> {
+    /// Count an entry of `table` turning valid or invalid.
+    fn entry_changed(&mut self, table: PhysPageNum, valid: bool) {
+        if let Some(count) = self.valid_entries.get_mut(&table) {
+            if valid {
+                *count += 1;
+            } else {
+                *count -= 1;
+            }
+        }
+    }
+    /// The leaf entry covering `vpn` and its depth, 1 for a megapage. Stops
+    /// at the last level otherwise, whether the entry there is valid or not.
+    fn find_pte(&self, vpn: VirtPageNum) -> Option<(&PageTableEntry, usize)> {
         let idxs = vpn.indexes();
         let mut ppn = self.root_ppn;
-        let mut result: Option<&PageTableEntry> = None;
         for i in 0..3 {
             let pte = &ppn.get_pte_array()[idxs[i]];
-            if i == 2 {
-                result = Some(pte);
-                break;
+            if i == 2 || pte.is_leaf() {
+                return Some((pte, i));
             }
             if !pte.is_valid() {
                 return None;
             }
             ppn = pte.ppn();
         }
-        result
+        None
     }
     #[allow(unused)]
     pub fn map(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) {
-        let pte = self.find_pte_create(vpn).unwrap();
-        assert!(!pte.is_valid(), "vpn {:?} is mapped before mapping", vpn);
+        self.try_map(vpn, ppn, flags).unwrap();
+    }
+    /// `map`, failing with ENOMEM if a table on the way can not be
+    /// allocated.
+    pub fn try_map(
+        &mut self,
+        vpn: VirtPageNum,
+        ppn: PhysPageNum,
+        flags: PTEFlags,
+    ) -> Result<(), isize> {
+        let (pte, table) = self.find_pte_create(vpn, 2).ok_or(ENOMEM)?;
+        assert!(pte.bits == 0, "vpn {:?} is mapped before mapping", vpn);
         #[cfg(feature = "board_lrv")]
         let flags = flags | PTEFlags::A | PTEFlags::D;
-        *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
+        // a valid entry without R, W and X points to a table, so a page
+        // granting no access keeps its frame in an invalid entry
+        let flags = if flags.intersects(PTEFlags::R | PTEFlags::W | PTEFlags::X) {
+            flags | PTEFlags::V
+        } else {
+            flags - PTEFlags::V
+        };
+        *pte = PageTableEntry::new(ppn, flags);
+        self.entry_changed(table, true);
+        if self.tracks(flags, ppn) {
+            rmap::add(ppn, (self.root_ppn, vpn));
+        }
+        Ok(())
     }
     #[allow(unused)]
     pub fn unmap(&mut self, vpn: VirtPageNum) {
-        let pte = self.find_pte_create(vpn).unwrap();
-        assert!(pte.is_valid(), "vpn {:?} is invalid before unmapping", vpn);
+        let (pte, table) = self.find_pte_create(vpn, 2).unwrap();
+        assert!(
+            pte.bits != 0,
+            "vpn {:?} is not mapped before unmapping",
+            vpn
+        );
+        let (ppn, flags) = (pte.ppn(), pte.flags());
+        *pte = PageTableEntry::empty();
+        self.entry_changed(table, false);
+        if self.tracks(flags, ppn) {
+            rmap::remove(ppn, (self.root_ppn, vpn));
+        }
+    }
+    /// Whether a page mapping `ppn` with `flags` is in the reverse map:
+    /// user pages of tables owned here, the zero frame aside.
+    fn tracks(&self, flags: PTEFlags, ppn: PhysPageNum) -> bool {
+        flags.contains(PTEFlags::U) && !self.frames.is_empty() && !is_zero_ppn(ppn)
+    }
+    /// Map the `HUGE_PAGES` pages from `vpn` with a single level-1 leaf.
+    /// Both `vpn` and `ppn` have to be aligned to a megapage, and `flags`
+    /// have to include one of R, W and X.
+    pub fn map_huge(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) {
+        assert!(vpn.0 % HUGE_PAGES == 0 && ppn.0 % HUGE_PAGES == 0);
+        assert!(flags.intersects(PTEFlags::R | PTEFlags::W | PTEFlags::X));
+        let (pte, table) = self.find_pte_create(vpn, 1).unwrap();
+        assert!(!pte.is_valid(), "vpn {:?} is mapped before mapping", vpn);
+        #[cfg(feature = "board_lrv")]
+        let flags = flags | PTEFlags::A | PTEFlags::D;
+        *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
+        self.entry_changed(table, true);
+        // any page of the megapage may be cached
+        self.last_translated.set(None);
+    }
+    pub fn unmap_huge(&mut self, vpn: VirtPageNum) {
+        assert!(self.is_huge(vpn), "vpn {:?} is not a huge page", vpn);
+        let (pte, table) = self.find_pte_create(vpn, 1).unwrap();
         *pte = PageTableEntry::empty();
+        self.entry_changed(table, false);
+        self.last_translated.set(None);
+    }
+    /// Whether `vpn` starts a megapage mapped by `map_huge`.
+    pub fn is_huge(&self, vpn: VirtPageNum) -> bool {
+        vpn.0 % HUGE_PAGES == 0 && matches!(self.find_pte(vpn), Some((_, 1)))
+    }
+    fn invalidate_cached(&self, vpn: VirtPageNum) {
+        if let Some((cached_vpn, _)) = self.last_translated.get() {
+            if cached_vpn == vpn {
+                self.last_translated.set(None);
+            }
+        }
     }
     pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
-        self.find_pte(vpn).copied()
+        if let Some((cached_vpn, pte)) = self.last_translated.get() {
+            if cached_vpn == vpn {
+                return Some(pte);
+            }
+        }
+        let pte = self
+            .find_pte(vpn)
+            .map(|(pte, depth)| pte.page_of(vpn, depth));
+        if let Some(pte) = pte {
+            if pte.is_valid() {
+                self.last_translated.set(Some((vpn, pte)));
+            }
+        }
+        pte
     }
+    pub fn translate_detailed(&self, vpn: VirtPageNum) -> TranslateResult {
+        match self.find_pte(vpn) {
+            Some((pte, depth)) if pte.is_valid() => {
+                TranslateResult::Mapped(pte.page_of(vpn, depth))
+            }
+            Some(_) => TranslateResult::Reserved,
+            None => TranslateResult::Absent,
+        }
+    }
+    #[allow(dead_code)]
     pub fn translate_va(&self, va: VirtAddr) -> Option<PhysAddr> {
-        self.find_pte(va.clone().floor()).map(|pte| {
+        let vpn = va.clone().floor();
+        self.find_pte(vpn).map(|(pte, depth)| {
+            let pte = pte.page_of(vpn, depth);
             let aligned_pa: PhysAddr = pte.ppn().into();
             let offset = va.page_offset();
             let aligned_pa_usize: usize = aligned_pa.into();
             (aligned_pa_usize + offset).into()
         })
     }
+    /// Unlink the tables below the root left without a valid entry, and
+    /// those left empty in turn, and hand their frames back. The hardware
+    /// may still cache pointers to them, so the caller fences the whole
+    /// address space before dropping the frames.
+    pub fn shrink(&mut self) -> Vec<FrameTracker> {
+        if !self.valid_entries.values().any(|&count| count == 0) {
+            return Vec::new();
+        }
+        let mut freed = BTreeSet::new();
+        self.shrink_table(self.root_ppn, 0, &mut freed);
+        if freed.is_empty() {
+            return Vec::new();
+        }
+        let (kept, freed): (Vec<_>, Vec<_>) = self
+            .frames
+            .drain(..)
+            .partition(|frame| !freed.contains(&frame.ppn));
+        self.frames = kept;
+        self.last_translated.set(None);
+        freed
+    }
+    fn shrink_table(
+        &mut self,
+        table: PhysPageNum,
+        depth: usize,
+        freed: &mut BTreeSet<PhysPageNum>,
+    ) {
+        for pte in table.get_pte_array().iter_mut() {
+            if !pte.is_valid() || pte.is_leaf() {
+                continue;
+            }
+            let child = pte.ppn();
+            if depth == 0 {
+                self.shrink_table(child, 1, freed);
+            }
+            if self.valid_entries.get(&child) == Some(&0) {
+                *pte = PageTableEntry::empty();
+                self.entry_changed(table, false);
+                self.valid_entries.remove(&child);
+                freed.insert(child);
+            }
+        }
+    }
+    pub fn root_ppn(&self) -> PhysPageNum {
+        self.root_ppn
+    }
     pub fn token(&self) -> usize {
         8usize << 60 | self.root_ppn.0
     }
+    /// The A and D bits of each valid page in `range`, with a megapage
+    /// reporting its bits for every page of it. Pages not mapped are left
+    /// out.
+    pub fn scan_accessed(&self, range: VPNRange) -> Vec<(VirtPageNum, PTEFlags)> {
+        range
+            .into_iter()
+            .filter_map(|vpn| match self.find_pte(vpn) {
+                Some((pte, _)) if pte.is_valid() => {
+                    Some((vpn, pte.flags() & (PTEFlags::A | PTEFlags::D)))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+    /// Reset the A and D bits of the valid entries in `range` and return
+    /// how many pages had A set. The hardware does not set them again
+    /// through a cached TLB entry, so the caller fences. The lrv board
+    /// faults instead of setting them, so there they stay set as `try_map`
+    /// left them and only the count is taken.
+    pub fn clear_accessed(&mut self, range: VPNRange) -> usize {
+        let mask = if cfg!(feature = "board_lrv") {
+            PTEFlags::empty()
+        } else {
+            PTEFlags::A | PTEFlags::D
+        };
+        let end = range.get_end();
+        let mut vpn = range.get_start();
+        let mut accessed = 0;
+        while vpn < end {
+            let (valid, depth) = match self.find_pte(vpn) {
+                Some((pte, depth)) => (pte.is_valid(), depth),
+                None => (false, 2),
+            };
+            // the rest of a megapage shares its entry
+            let pages = if depth == 1 {
+                (HUGE_PAGES - vpn.0 % HUGE_PAGES).min(end.0 - vpn.0)
+            } else {
+                1
+            };
+            if valid {
+                let (pte, _) = self.find_pte_create(vpn, depth).unwrap();
+                if pte.flags().contains(PTEFlags::A) {
+                    accessed += pages;
+                }
+                pte.bits &= !(mask.bits as usize);
+            }
+            vpn = VirtPageNum(vpn.0 + pages);
+        }
+        self.last_translated.set(None);
+        accessed
+    }
+    /// Visit every entry in use with the first vpn it covers and its depth,
+    /// 2 for a page. Besides the valid ones, those are the pages mapped
+    /// without access. A table entry is visited before the entries of the
+    /// table it points to.
+    pub fn walk<F: FnMut(VirtPageNum, &PageTableEntry, usize)>(&self, mut visit: F) {
+        walk_table(self.root_ppn, 0, 0, &mut visit);
+    }
+    /// The W+X leaves, the user leaves if this is the kernel space, and
+    /// the tables emptied by unmapping but still linked in.
+    pub fn audit(&self, kernel: bool) -> Vec<PagingIssue> {
+        let mut issues = Vec::new();
+        // (depth, index prefix) of the tables pointed to and of those with
+        // a valid entry
+        let mut tables = Vec::new();
+        let mut used = BTreeSet::new();
+        self.walk(|vpn, pte, depth| {
+            let shift = 9 * (3 - depth);
+            if depth > 0 {
+                used.insert((depth - 1, vpn.0 >> shift));
+            }
+            if !pte.is_valid() {
+                // a page mapped without access
+            } else if !pte.is_leaf() {
+                tables.push((vpn, depth));
+            } else if pte.writable() && pte.executable() {
+                issues.push(PagingIssue::WritableExecutable(vpn));
+            } else if kernel && pte.is_user() {
+                issues.push(PagingIssue::UserInKernel(vpn));
+            }
+        });
+        for (vpn, depth) in tables {
+            if !used.contains(&(depth, vpn.0 >> (9 * (2 - depth)))) {
+                issues.push(PagingIssue::EmptyTable(vpn, depth));
+            }
+        }
+        issues
+    }
+}
+
+fn walk_table<F: FnMut(VirtPageNum, &PageTableEntry, usize)>(
+    ppn: PhysPageNum,
+    depth: usize,
+    prefix: usize,
+    visit: &mut F,
+) {
+    for (i, pte) in ppn.get_pte_array().iter().enumerate() {
+        if pte.bits == 0 {
+            continue;
+        }
+        let index = prefix << 9 | i;
+        visit(VirtPageNum(index << (9 * (2 - depth))), pte, depth);
+        if pte.is_valid() && !pte.is_leaf() && depth < 2 {
+            walk_table(pte.ppn(), depth + 1, index, visit);
+        }
+    }
+}
+
+/// Translate a user page for a kernel access, faulting it in first if it
+/// is a lazy page of the running task.
+fn translate_user(
+    page_table: &PageTable,
+    token: usize,
+    vpn: VirtPageNum,
+    access: AccessType,
+) -> Option<PageTableEntry> {
+    match page_table.translate(vpn) {
+        Some(pte) if pte.is_valid() => Some(pte),
+        _ if fault_in_current(token, vpn, access) => page_table.translate(vpn),
+        pte => pte,
+    }
+}
+
+impl Drop for PageTable {
+    fn drop(&mut self) {
+        if !self.frames.is_empty() {
+            rmap::forget(self.root_ppn);
+        }
+    }
 }
 
 pub fn translate_writable_va(token: usize, va: usize) -> Result<usize, isize> {
     let va = VirtAddr::from(va);
     let vpn = va.floor();
     let page_table = PageTable::from_token(token);
-    let pte = page_table.translate(vpn).unwrap();
+    let pte = translate_user(&page_table, token, vpn, AccessType::Write).ok_or(-1isize)?;
     if !pte.writable() || !pte.is_valid() {
         return Err(-1);
     }
@@ -153,117 +508,200 @@ pub fn translate_writable_va(token: usize, va: usize) -> Result<usize, isize> {
     Ok(usize::from(pa))
 }
 
-pub fn translated_byte_buffer(
-    token: usize,
-    ptr: *const u8,
-    len: usize,
-) -> Result<Vec<&'static mut [u8]>, isize> {
-    let page_table = PageTable::from_token(token);
-    let mut start = ptr as usize;
-    let end = start + len;
-    let mut v = Vec::new();
-    while start < end {
-        let start_va = VirtAddr::from(start);
-        let mut vpn = start_va.floor();
-        let pte = page_table.translate(vpn);
-        if pte.is_none() {
-            return Err(-1);
-        }
-        let pte = pte.unwrap();
-        if !pte.readable() || !pte.is_valid() {
-            return Err(-1);
-        }
-        let ppn = pte.ppn();
-        vpn.step();
-        let mut end_va: VirtAddr = vpn.into();
-        end_va = end_va.min(VirtAddr::from(end));
-        if end_va.page_offset() == 0 {
-            v.push(&mut ppn.get_bytes_array()[start_va.page_offset()..]);
-        } else {
-            v.push(&mut ppn.get_bytes_array()[start_va.page_offset()..end_va.page_offset()]);
-        }
-        start = end_va.into();
-    }
-    Ok(v)
+#[allow(unused)]
+pub fn translate_cache_test() {
+    let mut page_table = PageTable::new();
+    let vpn = VirtPageNum(0x10);
+    let first = frame_alloc().unwrap();
+    let second = frame_alloc().unwrap();
+    page_table.map(vpn, first.ppn, PTEFlags::R | PTEFlags::U);
+    assert!(page_table.translate(vpn).unwrap().ppn() == first.ppn);
+    page_table.unmap(vpn);
+    assert!(!page_table
+        .translate(vpn)
+        .map_or(false, |pte| pte.is_valid()));
+    page_table.map(vpn, second.ppn, PTEFlags::R | PTEFlags::U);
+    assert!(page_table.translate(vpn).unwrap().ppn() == second.ppn);
+    debug!("translate_cache_test passed!");
 }
 
-pub fn translated_str(token: usize, ptr: *const u8) -> String {
-    let page_table = PageTable::from_token(token);
-    let mut string = String::new();
-    let mut va = ptr as usize;
-    loop {
-        let ch: u8 = *(page_table
-            .translate_va(VirtAddr::from(va))
-            .unwrap()
-            .get_mut());
-        if ch == 0 {
-            break;
-        } else {
-            string.push(ch as char);
-            va += 1;
-        }
+#[allow(unused)]
+pub fn translate_detailed_test() {
+    let mut page_table = PageTable::new();
+    let vpn = VirtPageNum(0x10);
+    let frame = frame_alloc().unwrap();
+    assert!(matches!(
+        page_table.translate_detailed(vpn),
+        TranslateResult::Absent
+    ));
+    page_table.map(vpn, frame.ppn, PTEFlags::R | PTEFlags::U);
+    match page_table.translate_detailed(vpn) {
+        TranslateResult::Mapped(pte) => assert!(pte.ppn() == frame.ppn),
+        _ => panic!("vpn {:?} should be mapped", vpn),
     }
-    string
-}
-
-pub fn translated_refmut<T>(token: usize, ptr: *mut T) -> &'static mut T {
-    let page_table = PageTable::from_token(token);
-    let va = ptr as usize;
-    page_table
-        .translate_va(VirtAddr::from(va))
-        .unwrap()
-        .get_mut()
-}
-
-pub struct UserBuffer {
-    pub buffers: Vec<&'static mut [u8]>,
+    page_table.unmap(vpn);
+    assert!(matches!(
+        page_table.translate_detailed(vpn),
+        TranslateResult::Reserved
+    ));
+    // a neighbour sharing the leaf table is reserved, a distant page is absent
+    assert!(matches!(
+        page_table.translate_detailed(VirtPageNum(0x11)),
+        TranslateResult::Reserved
+    ));
+    assert!(matches!(
+        page_table.translate_detailed(VirtPageNum(1 << 18)),
+        TranslateResult::Absent
+    ));
+    debug!("translate_detailed_test passed!");
 }
 
-impl UserBuffer {
-    pub fn new(buffers: Vec<&'static mut [u8]>) -> Self {
-        Self { buffers }
-    }
-    pub fn len(&self) -> usize {
-        let mut total: usize = 0;
-        for b in self.buffers.iter() {
-            total += b.len();
-        }
-        total
+#[allow(unused)]
+pub fn huge_page_test() {
+    let mut page_table = PageTable::new();
+    let vpn = VirtPageNum(HUGE_PAGES);
+    page_table.map_huge(vpn, PhysPageNum(0x80200), PTEFlags::R | PTEFlags::W);
+    // the root and a single level-1 table
+    assert_eq!(page_table.frames.len(), 2);
+    assert!(page_table.is_huge(vpn) && !page_table.is_huge(VirtPageNum(vpn.0 + 1)));
+    let pte = page_table.translate(VirtPageNum(vpn.0 + 5)).unwrap();
+    assert!(pte.ppn() == PhysPageNum(0x80205) && pte.writable());
+    match page_table.translate_detailed(VirtPageNum(vpn.0 + HUGE_PAGES - 1)) {
+        TranslateResult::Mapped(pte) => assert!(pte.ppn() == PhysPageNum(0x803ff)),
+        _ => panic!("vpn {:?} should be mapped", vpn),
     }
+    assert!(page_table
+        .translate(VirtPageNum(vpn.0 + HUGE_PAGES))
+        .is_none());
+    page_table.unmap_huge(vpn);
+    assert!(matches!(
+        page_table.translate_detailed(VirtPageNum(vpn.0 + 5)),
+        TranslateResult::Absent
+    ));
+    // the range takes single pages again
+    let frame = frame_alloc().unwrap();
+    page_table.map(VirtPageNum(vpn.0 + 5), frame.ppn, PTEFlags::R);
+    assert!(page_table.translate(VirtPageNum(vpn.0 + 5)).unwrap().ppn() == frame.ppn);
+    debug!("huge_page_test passed!");
 }
 
-impl IntoIterator for UserBuffer {
-    type Item = *mut u8;
-    type IntoIter = UserBufferIterator;
-    fn into_iter(self) -> Self::IntoIter {
-        UserBufferIterator {
-            buffers: self.buffers,
-            current_buffer: 0,
-            current_idx: 0,
-        }
+#[allow(unused)]
+pub fn accessed_scan_test() {
+    // the lrv board maps everything accessed and dirty already
+    if cfg!(feature = "board_lrv") {
+        return;
     }
+    let mut page_table = PageTable::new();
+    let frames: Vec<FrameTracker> = (0..3).map(|_| frame_alloc().unwrap()).collect();
+    let flags = [
+        PTEFlags::R | PTEFlags::A,
+        PTEFlags::R | PTEFlags::W | PTEFlags::A | PTEFlags::D,
+        PTEFlags::R,
+    ];
+    for (i, frame) in frames.iter().enumerate() {
+        page_table.map(VirtPageNum(0x10 + i), frame.ppn, flags[i]);
+    }
+    // a megapage that has been read
+    let huge = VirtPageNum(HUGE_PAGES);
+    page_table.map_huge(huge, PhysPageNum(0x80200), PTEFlags::R | PTEFlags::A);
+    let scanned = page_table.scan_accessed(VPNRange::new(VirtPageNum(0x10), VirtPageNum(0x14)));
+    assert_eq!(scanned.len(), 3);
+    assert_eq!(scanned[0], (VirtPageNum(0x10), PTEFlags::A));
+    assert_eq!(scanned[1], (VirtPageNum(0x11), PTEFlags::A | PTEFlags::D));
+    assert_eq!(scanned[2], (VirtPageNum(0x12), PTEFlags::empty()));
+    let range = VPNRange::new(VirtPageNum(0x10), VirtPageNum(huge.0 + 2));
+    assert_eq!(page_table.clear_accessed(range), 4);
+    // the whole megapage was cleared, not just the pages in the range
+    let range = VPNRange::new(VirtPageNum(huge.0 + 2), VirtPageNum(huge.0 + HUGE_PAGES));
+    assert_eq!(page_table.clear_accessed(range), 0);
+    let range = VPNRange::new(VirtPageNum(0x10), VirtPageNum(0x13));
+    assert!(page_table
+        .scan_accessed(range)
+        .iter()
+        .all(|(_, flags)| flags.is_empty()));
+    // the rest of the entries is kept
+    assert!(page_table.translate(VirtPageNum(0x11)).unwrap().writable());
+    page_table.unmap_huge(huge);
+    debug!("accessed_scan_test passed!");
 }
 
-pub struct UserBufferIterator {
-    buffers: Vec<&'static mut [u8]>,
-    current_buffer: usize,
-    current_idx: usize,
+#[allow(unused)]
+pub fn page_table_walk_test() {
+    let mut page_table = PageTable::new();
+    let frames: Vec<FrameTracker> = (0..3).map(|_| frame_alloc().unwrap()).collect();
+    page_table.map(VirtPageNum(0x10), frames[0].ppn, PTEFlags::R | PTEFlags::U);
+    page_table.map(
+        VirtPageNum(0x11),
+        frames[1].ppn,
+        PTEFlags::R | PTEFlags::W | PTEFlags::X,
+    );
+    // in a level-0 entry and a table of its own
+    let far = VirtPageNum(3 << 18 | 5);
+    page_table.map(far, frames[2].ppn, PTEFlags::R);
+    let mut visited = Vec::new();
+    page_table.walk(|vpn, pte, depth| visited.push((vpn, depth, pte.is_leaf())));
+    assert_eq!(
+        visited,
+        [
+            (VirtPageNum(0), 0, false),
+            (VirtPageNum(0), 1, false),
+            (VirtPageNum(0x10), 2, true),
+            (VirtPageNum(0x11), 2, true),
+            (VirtPageNum(3 << 18), 0, false),
+            (VirtPageNum(3 << 18), 1, false),
+            (far, 2, true),
+        ]
+    );
+    assert_eq!(
+        page_table.audit(true),
+        [
+            PagingIssue::UserInKernel(VirtPageNum(0x10)),
+            PagingIssue::WritableExecutable(VirtPageNum(0x11)),
+        ]
+    );
+    assert_eq!(
+        page_table.audit(false),
+        [PagingIssue::WritableExecutable(VirtPageNum(0x11))]
+    );
+    // unmapping leaves the tables on the way behind
+    page_table.unmap(VirtPageNum(0x11));
+    page_table.unmap(far);
+    assert_eq!(
+        page_table.audit(false),
+        [PagingIssue::EmptyTable(VirtPageNum(3 << 18), 1)]
+    );
+    debug!("page_table_walk_test passed!");
 }
 
-impl Iterator for UserBufferIterator {
-    type Item = *mut u8;
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.current_buffer >= self.buffers.len() {
-            None
-        } else {
-            let r = &mut self.buffers[self.current_buffer][self.current_idx] as *mut _;
-            if self.current_idx + 1 == self.buffers[self.current_buffer].len() {
-                self.current_idx = 0;
-                self.current_buffer += 1;
-            } else {
-                self.current_idx += 1;
-            }
-            Some(r)
-        }
-    }
+#[allow(unused)]
+pub fn shrink_test() {
+    let mut page_table = PageTable::new();
+    let frames: Vec<FrameTracker> = (0..3).map(|_| frame_alloc().unwrap()).collect();
+    let near = VirtPageNum(0x10);
+    let far = VirtPageNum(3 << 18 | 5);
+    page_table.map(near, frames[0].ppn, PTEFlags::R | PTEFlags::U);
+    page_table.map(VirtPageNum(0x11), frames[1].ppn, PTEFlags::R | PTEFlags::U);
+    page_table.map(far, frames[2].ppn, PTEFlags::R | PTEFlags::U);
+    // the root and two tables on the way to each page
+    assert_eq!(page_table.frames.len(), 5);
+    assert!(page_table.shrink().is_empty());
+    // a table with a page left stays
+    page_table.unmap(near);
+    assert!(page_table.shrink().is_empty());
+    page_table.unmap(far);
+    assert_eq!(page_table.shrink().len(), 2);
+    assert_eq!(page_table.frames.len(), 3);
+    assert!(page_table.audit(false).is_empty());
+    assert!(matches!(
+        page_table.translate_detailed(far),
+        TranslateResult::Absent
+    ));
+    // the last page takes every table with it, the root aside
+    page_table.unmap(VirtPageNum(0x11));
+    assert_eq!(page_table.shrink().len(), 2);
+    assert_eq!(page_table.frames.len(), 1);
+    page_table.map(far, frames[2].ppn, PTEFlags::R | PTEFlags::U);
+    assert!(page_table.translate(far).unwrap().ppn() == frames[2].ppn);
+    assert_eq!(page_table.frames.len(), 3);
+    debug!("shrink_test passed!");
 }
@@ -0,0 +1,116 @@
+//! The reverse map: for every frame mapped into a user space, the page
+//! tables and pages mapping it. Kept up to date by `PageTable` as user
+//! pages are mapped and unmapped, so that a frame shared by several spaces,
+//! copy-on-write or shared memory, can be taken away from all of them.
+//!
+//! Spaces are told apart by the root of their page table. The zero frame
+//! and megapages are left out.
+
+use super::{PhysPageNum, VirtPageNum};
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use lazy_static::*;
+use spin::Mutex;
+
+/// A page mapping a frame: the root of its page table and the page.
+pub type Mapper = (PhysPageNum, VirtPageNum);
+
+lazy_static! {
+    static ref RMAP: Mutex<BTreeMap<PhysPageNum, Vec<Mapper>>> = Mutex::new(BTreeMap::new());
+}
+
+pub fn add(ppn: PhysPageNum, mapper: Mapper) {
+    RMAP.lock().entry(ppn).or_insert_with(Vec::new).push(mapper);
+}
+
+pub fn remove(ppn: PhysPageNum, mapper: Mapper) {
+    let mut rmap = RMAP.lock();
+    if let Some(mappers) = rmap.get_mut(&ppn) {
+        if let Some(i) = mappers.iter().position(|m| *m == mapper) {
+            mappers.swap_remove(i);
+        }
+        if mappers.is_empty() {
+            rmap.remove(&ppn);
+        }
+    }
+}
+
+/// Drop every mapping of the page table at `root`, which is going away
+/// along with its entries.
+pub fn forget(root: PhysPageNum) {
+    RMAP.lock().retain(|_, mappers| {
+        mappers.retain(|(mapper_root, _)| *mapper_root != root);
+        !mappers.is_empty()
+    });
+}
+
+/// The pages mapping `ppn`, in no particular order.
+pub fn mappers(ppn: PhysPageNum) -> Vec<Mapper> {
+    RMAP.lock().get(&ppn).cloned().unwrap_or_default()
+}
+
+/// The frames mapped by more than one page, with their mappers.
+pub fn shared_frames() -> Vec<(PhysPageNum, Vec<Mapper>)> {
+    RMAP.lock()
+        .iter()
+        .filter(|(_, mappers)| mappers.len() > 1)
+        .map(|(ppn, mappers)| (*ppn, mappers.clone()))
+        .collect()
+}
+
+#[allow(unused)]
+pub fn rmap_test() {
+    use super::{shm_create, shm_get, shm_release, MemorySet, MmapFlags, VirtAddr, VirtPageNum};
+    use crate::config::PAGE_SIZE;
+
+    let mut first = MemorySet::new_bare();
+    let mut second = MemorySet::new_bare();
+    let id = shm_create(PAGE_SIZE).unwrap();
+    let segment = shm_get(id).unwrap();
+    let ppn = segment.ppn(0);
+    let (first_va, second_va) = (0x1000_0000, 0x2000_0000);
+    first.attach_shared(&segment, first_va, 0b11).unwrap();
+    second.attach_shared(&segment, second_va, 0b1).unwrap();
+    let first_vpn = VirtAddr::from(first_va).floor();
+    let second_vpn = VirtAddr::from(second_va).floor();
+    let mut found = mappers(ppn);
+    found.sort();
+    let mut expected = [
+        (first.root_ppn(), first_vpn),
+        (second.root_ppn(), second_vpn),
+    ];
+    expected.sort();
+    assert_eq!(found, expected);
+    assert!(shared_frames().iter().any(|(shared, _)| *shared == ppn));
+    // unmapping drops a mapper, dropping a space all of its
+    first.detach_shared(first_va).unwrap();
+    assert_eq!(mappers(ppn), [(second.root_ppn(), second_vpn)]);
+    first.attach_shared(&segment, first_va, 0b1).unwrap();
+    assert_eq!(mappers(ppn).len(), 2);
+    drop(first);
+    assert_eq!(mappers(ppn), [(second.root_ppn(), second_vpn)]);
+    second.detach_shared(second_va).unwrap();
+    assert!(mappers(ppn).is_empty());
+    shm_release(segment);
+
+    let mut memory_set = MemorySet::new_bare();
+    let start = 0x1000_0000;
+    memory_set
+        .mmap(
+            start,
+            2 * PAGE_SIZE,
+            0b11,
+            MmapFlags::PRIVATE | MmapFlags::ANONYMOUS | MmapFlags::FIXED,
+        )
+        .unwrap();
+    let vpn = VirtAddr::from(start).floor();
+    memory_set.handle_lazy_fault(vpn).unwrap();
+    let ppn = memory_set.translate(vpn).unwrap().ppn();
+    assert_eq!(mappers(ppn), [(memory_set.root_ppn(), vpn)]);
+    // a page only read maps the zero frame, which is not tracked
+    let zero_vpn = VirtPageNum(vpn.0 + 1);
+    memory_set.handle_lazy_read_fault(zero_vpn).unwrap();
+    let zero_ppn = memory_set.translate(zero_vpn).unwrap().ppn();
+    assert!(mappers(zero_ppn).is_empty());
+    debug!("rmap_test passed!");
+}
@@ -0,0 +1,74 @@
+//! Frames mapped by several spaces at once, for shared memory segments.
+
+use super::{frame_alloc, FrameTracker, PhysPageNum};
+use crate::config::PAGE_SIZE;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use lazy_static::*;
+use spin::Mutex;
+
+/// A list of frames that spaces map as `MapType::Shared` areas. Clones
+/// refer to the same frames, which are freed with the last one.
+#[derive(Clone)]
+pub struct SharedFrame {
+    frames: Arc<Vec<FrameTracker>>,
+}
+
+impl SharedFrame {
+    /// `pages` zeroed frames, `None` if there are not enough.
+    pub fn new(pages: usize) -> Option<Self> {
+        let mut frames = Vec::with_capacity(pages);
+        for _ in 0..pages {
+            frames.push(frame_alloc()?);
+        }
+        Some(Self {
+            frames: Arc::new(frames),
+        })
+    }
+    pub fn pages(&self) -> usize {
+        self.frames.len()
+    }
+    pub fn ppn(&self, page: usize) -> PhysPageNum {
+        self.frames[page].ppn
+    }
+    /// Number of clones, mappings and the registry included.
+    pub fn holders(&self) -> usize {
+        Arc::strong_count(&self.frames)
+    }
+    pub fn ptr_eq(&self, other: &SharedFrame) -> bool {
+        Arc::ptr_eq(&self.frames, &other.frames)
+    }
+}
+
+lazy_static! {
+    /// Segments made by `shm_create`, by id.
+    static ref SHM_SEGMENTS: Mutex<BTreeMap<usize, SharedFrame>> = Mutex::new(BTreeMap::new());
+}
+
+/// Make a segment of `len` bytes, rounded up to whole pages, and return its
+/// id.
+pub fn shm_create(len: usize) -> Option<usize> {
+    if len == 0 || len > 1 << 30 {
+        return None;
+    }
+    let segment = SharedFrame::new((len + PAGE_SIZE - 1) / PAGE_SIZE)?;
+    let mut segments = SHM_SEGMENTS.lock();
+    let id = segments.keys().next_back().map_or(1, |id| id + 1);
+    segments.insert(id, segment);
+    Some(id)
+}
+
+pub fn shm_get(id: usize) -> Option<SharedFrame> {
+    SHM_SEGMENTS.lock().get(&id).cloned()
+}
+
+/// Called with a segment just detached: once no space maps it anymore it is
+/// dropped from the registry, and its frames are freed.
+pub fn shm_release(segment: SharedFrame) {
+    let mut segments = SHM_SEGMENTS.lock();
+    // the registry and `segment` itself
+    if segment.holders() == 2 {
+        segments.retain(|_, registered| !registered.ptr_eq(&segment));
+    }
+}
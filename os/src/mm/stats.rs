@@ -0,0 +1,52 @@
+//! Memory usage of the whole system, see `sys_meminfo` and `/proc/meminfo`.
+
+use super::frame_usage;
+use super::heap_allocator::heap_usage;
+
+/// Frames and pages are 4 KiB each, the kernel heap is in bytes.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct MemStats {
+    pub total_frames: usize,
+    pub free_frames: usize,
+    pub heap_total: usize,
+    /// Including what allocations are rounded up to.
+    pub heap_used: usize,
+    /// Pages backed by frames in the calling process. Left 0 by `stats`,
+    /// filled in by `sys_meminfo`.
+    pub resident_pages: usize,
+}
+
+pub fn stats() -> MemStats {
+    let (allocated, total_frames) = frame_usage();
+    let (heap_used, heap_total) = heap_usage();
+    MemStats {
+        total_frames,
+        free_frames: total_frames - allocated,
+        heap_total,
+        heap_used,
+        resident_pages: 0,
+    }
+}
+
+#[allow(unused)]
+pub fn stats_test() {
+    use super::frame_alloc;
+    use alloc::boxed::Box;
+    use alloc::vec::Vec;
+    let before = stats();
+    assert!(before.free_frames <= before.total_frames);
+    assert!(before.heap_used <= before.heap_total);
+    let frames: Vec<_> = (0..4).map(|_| frame_alloc().unwrap()).collect();
+    let buf = Box::new([0u8; 1024]);
+    let during = stats();
+    // the Vec of frames takes some heap too
+    assert_eq!(during.free_frames, before.free_frames - 4);
+    assert!(during.heap_used >= before.heap_used + 1024);
+    drop(frames);
+    drop(buf);
+    let after = stats();
+    assert_eq!(after.free_frames, before.free_frames);
+    assert_eq!(after.heap_used, before.heap_used);
+    debug!("stats_test passed!");
+}
@@ -0,0 +1,284 @@
+//! Swapping pages of user lazy areas out to a block device. A page swapped
+//! out leaves its slot in the area and an invalid page table entry behind,
+//! and is read back into a fresh frame by the next fault on it.
+//!
+//! There is no swap until a device is registered with `swap_on`.
+
+use super::{frame_usage, rmap};
+use crate::config::PAGE_SIZE;
+use crate::task::{current_task, find_task, task_pids, TaskControlBlock, TaskStatus};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use lazy_static::*;
+use spin::Mutex;
+
+pub const BLOCK_SIZE: usize = 512;
+const BLOCKS_PER_SLOT: usize = PAGE_SIZE / BLOCK_SIZE;
+
+/// Reclaim starts once no more frames than this are free.
+const LOW_FRAMES: usize = 16;
+/// Pages swapped out by one `reclaim`.
+const RECLAIM_BATCH: usize = 32;
+
+pub trait BlockDevice: Send + Sync {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]);
+    fn write_block(&self, block_id: usize, buf: &[u8]);
+}
+
+struct SwapArea {
+    device: Arc<dyn BlockDevice>,
+    /// First block of the area on the device
+    start_block: usize,
+    current: usize,
+    /// One past the last slot
+    end: usize,
+    recycled: Vec<usize>,
+}
+
+impl SwapArea {
+    fn alloc(&mut self) -> Option<usize> {
+        if let Some(slot) = self.recycled.pop() {
+            Some(slot)
+        } else if self.current < self.end {
+            self.current += 1;
+            Some(self.current - 1)
+        } else {
+            None
+        }
+    }
+    fn dealloc(&mut self, slot: usize) {
+        assert!(slot < self.current);
+        assert!(
+            !self.recycled.contains(&slot),
+            "swap slot {} has been deallocated!",
+            slot
+        );
+        self.recycled.push(slot);
+    }
+    fn first_block(&self, slot: usize) -> usize {
+        self.start_block + slot * BLOCKS_PER_SLOT
+    }
+}
+
+lazy_static! {
+    static ref SWAP_AREA: Mutex<Option<SwapArea>> = Mutex::new(None);
+}
+
+/// Swap to the `blocks` blocks of `device` from `start_block` on, whole
+/// pages of them. Replaces the area of an earlier call, which must have no
+/// pages swapped out.
+pub fn swap_on(device: Arc<dyn BlockDevice>, start_block: usize, blocks: usize) {
+    let mut swap_area = SWAP_AREA.lock();
+    assert!(swap_area
+        .as_ref()
+        .map_or(true, |area| area.current == area.recycled.len()));
+    *swap_area = Some(SwapArea {
+        device,
+        start_block,
+        current: 0,
+        end: blocks / BLOCKS_PER_SLOT,
+        recycled: Vec::new(),
+    });
+    info!("[swap] {} pages", blocks / BLOCKS_PER_SLOT);
+}
+
+/// (used, total) slots of the swap area, in pages.
+pub fn swap_usage() -> (usize, usize) {
+    SWAP_AREA.lock().as_ref().map_or((0, 0), |area| {
+        (area.current - area.recycled.len(), area.end)
+    })
+}
+
+/// A page worth of the swap area, given back on drop.
+#[derive(Debug)]
+pub struct SwapSlot(usize);
+
+impl SwapSlot {
+    /// Store `page` in a free slot, `None` if there is no swap or it is
+    /// full.
+    pub fn write(page: &[u8]) -> Option<Self> {
+        let mut swap_area = SWAP_AREA.lock();
+        let area = swap_area.as_mut()?;
+        let slot = area.alloc()?;
+        let first_block = area.first_block(slot);
+        for (i, block) in page.chunks(BLOCK_SIZE).enumerate() {
+            area.device.write_block(first_block + i, block);
+        }
+        Some(SwapSlot(slot))
+    }
+    pub fn read(&self, page: &mut [u8]) {
+        let swap_area = SWAP_AREA.lock();
+        let area = swap_area.as_ref().unwrap();
+        let first_block = area.first_block(self.0);
+        for (i, block) in page.chunks_mut(BLOCK_SIZE).enumerate() {
+            area.device.read_block(first_block + i, block);
+        }
+    }
+}
+
+impl Drop for SwapSlot {
+    fn drop(&mut self) {
+        if let Some(area) = SWAP_AREA.lock().as_mut() {
+            area.dealloc(self.0);
+        }
+    }
+}
+
+/// Swap out up to a batch of pages if frames run low, taking them from
+/// the tasks not running on another hart. Returns the number of pages
+/// swapped out. The caller must not hold the inner lock of any task.
+pub fn reclaim() -> usize {
+    let (used, total) = frame_usage();
+    let (swapped, slots) = swap_usage();
+    if used + LOW_FRAMES < total || swapped == slots {
+        return 0;
+    }
+    let current = current_task();
+    let mut reclaimed = 0;
+    for pid in task_pids() {
+        if reclaimed == RECLAIM_BATCH {
+            break;
+        }
+        let task = match find_task(pid) {
+            Some(task) => task,
+            None => continue,
+        };
+        let is_current = current
+            .as_ref()
+            .map_or(false, |current| Arc::ptr_eq(current, &task));
+        let mut inner = task.acquire_inner_lock();
+        // its stale TLB entries could not be fenced from here
+        if inner.task_status == TaskStatus::Running && !is_current {
+            continue;
+        }
+        reclaimed += inner.memory_set.swap_out_pages(RECLAIM_BATCH - reclaimed);
+        // pages not touched until the next round are the cold ones then
+        inner.memory_set.clear_accessed();
+    }
+    if reclaimed < RECLAIM_BATCH {
+        reclaimed += reclaim_shared(current.as_ref(), RECLAIM_BATCH - reclaimed);
+    }
+    if reclaimed > 0 {
+        debug!("[swap] {} pages swapped out", reclaimed);
+    }
+    reclaimed
+}
+
+/// Swap out up to `count` copy-on-write frames shared by several tasks,
+/// from all the spaces the reverse map finds mapping them, so that they
+/// share one slot and the frame is freed. Frames also mapped by a space no
+/// task has are left alone. Returns the number of frames freed.
+fn reclaim_shared(current: Option<&Arc<TaskControlBlock>>, count: usize) -> usize {
+    let mut tasks = Vec::new();
+    for pid in task_pids() {
+        if let Some(task) = find_task(pid) {
+            let root = task.acquire_inner_lock().memory_set.root_ppn();
+            tasks.push((root, task));
+        }
+    }
+    let mut reclaimed = 0;
+    for (ppn, mappers) in rmap::shared_frames() {
+        if reclaimed == count {
+            break;
+        }
+        let owners: Option<Vec<_>> = mappers
+            .iter()
+            .map(|(root, vpn)| {
+                tasks
+                    .iter()
+                    .find(|(task_root, _)| task_root == root)
+                    .map(|(_, task)| (task, *vpn))
+            })
+            .collect();
+        let owners = match owners {
+            Some(owners) => owners,
+            None => continue,
+        };
+        let mut slot = None;
+        let mut swapped = 0;
+        for (task, vpn) in owners.iter() {
+            let is_current = current.map_or(false, |current| Arc::ptr_eq(current, task));
+            let mut inner = task.acquire_inner_lock();
+            if inner.task_status == TaskStatus::Running && !is_current {
+                continue;
+            }
+            if inner.memory_set.swap_out_shared(*vpn, ppn, &mut slot) {
+                swapped += 1;
+            }
+        }
+        if swapped == owners.len() {
+            reclaimed += 1;
+        }
+    }
+    reclaimed
+}
+
+#[allow(unused)]
+pub fn swap_test() {
+    use super::{AccessType, MemorySet, MmapFlags, VirtAddr};
+    use alloc::vec;
+
+    struct RamDisk(Mutex<Vec<u8>>);
+    impl BlockDevice for RamDisk {
+        fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+            let start = block_id * BLOCK_SIZE;
+            buf.copy_from_slice(&self.0.lock()[start..start + BLOCK_SIZE]);
+        }
+        fn write_block(&self, block_id: usize, buf: &[u8]) {
+            let start = block_id * BLOCK_SIZE;
+            self.0.lock()[start..start + BLOCK_SIZE].copy_from_slice(buf);
+        }
+    }
+
+    let saved = SWAP_AREA.lock().take();
+    // four pages after a block that is not part of the area
+    let disk = Arc::new(RamDisk(Mutex::new(vec![0; BLOCK_SIZE + 4 * PAGE_SIZE])));
+    swap_on(disk.clone(), 1, 4 * BLOCKS_PER_SLOT + 3);
+    assert_eq!(swap_usage(), (0, 4));
+
+    let mut memory_set = MemorySet::new_bare();
+    let start = 0x1000_0000;
+    memory_set
+        .mmap(
+            start,
+            4 * PAGE_SIZE,
+            0b11,
+            MmapFlags::PRIVATE | MmapFlags::ANONYMOUS | MmapFlags::FIXED,
+        )
+        .unwrap();
+    let va = |page: usize| VirtAddr::from(start + page * PAGE_SIZE);
+    for page in 0..3 {
+        memory_set
+            .handle_fault(va(page), AccessType::Write)
+            .unwrap();
+        let ppn = memory_set.translate(va(page).floor()).unwrap().ppn();
+        ppn.get_bytes_array().fill(page as u8 + 1);
+    }
+    // the zero frame is never swapped out
+    memory_set.handle_fault(va(3), AccessType::Read).unwrap();
+    let (used, _) = frame_usage();
+    assert_eq!(memory_set.swap_out_pages(8), 3);
+    assert_eq!(frame_usage().0, used - 3);
+    assert_eq!(swap_usage(), (3, 4));
+    assert_eq!(memory_set.resident_set_size(), 0);
+    assert_eq!(memory_set.swapped_pages(), 3);
+    assert!(disk.0.lock()[..BLOCK_SIZE].iter().all(|&b| b == 0));
+    for page in 0..3 {
+        assert!(!memory_set.translate(va(page).floor()).unwrap().is_valid());
+    }
+    // faulting a page back gives its slot back
+    memory_set.handle_fault(va(1), AccessType::Read).unwrap();
+    let pte = memory_set.translate(va(1).floor()).unwrap();
+    assert!(pte.writable() && pte.ppn().get_bytes_array().iter().all(|&b| b == 2));
+    assert_eq!(swap_usage(), (2, 4));
+    // a fork reads the pages still out, unmapping frees their slots
+    let child = MemorySet::from_existed_user(&memory_set).unwrap();
+    let ppn = child.translate(va(2).floor()).unwrap().ppn();
+    assert!(ppn.get_bytes_array().iter().all(|&b| b == 3));
+    assert_eq!(swap_usage(), (2, 4));
+    memory_set.munmap(start, 4 * PAGE_SIZE).unwrap();
+    assert_eq!(swap_usage(), (0, 4));
+
+    *SWAP_AREA.lock() = saved;
+    debug!("swap_test passed!");
+}
@@ -0,0 +1,115 @@
+//! Batched TLB maintenance. Pages unmapped or changed in a space are
+//! collected in a `TlbFlushBatch` and fenced together once the change is
+//! done: one `sfence.vma` per page for a few of them, a single fence of the
+//! whole ASID for more than `FLUSH_ALL_THRESHOLD`.
+
+use super::{VPNRange, VirtAddr, VirtPageNum};
+
+/// More pages than this are cheaper to fence all at once.
+pub const FLUSH_ALL_THRESHOLD: usize = 32;
+
+/// Pages whose TLB entries are stale, fenced by `flush` or on drop.
+pub struct TlbFlushBatch {
+    /// `None` for a batch that fences nothing
+    asid: Option<usize>,
+    vas: [usize; FLUSH_ALL_THRESHOLD],
+    len: usize,
+    /// Past the threshold, the whole ASID is fenced
+    all: bool,
+}
+
+impl TlbFlushBatch {
+    pub fn new(asid: usize) -> Self {
+        Self {
+            asid: Some(asid),
+            vas: [0; FLUSH_ALL_THRESHOLD],
+            len: 0,
+            all: false,
+        }
+    }
+    /// A batch for pages mapped a moment ago and never accessed, which can
+    /// not be in the TLB yet.
+    pub fn none() -> Self {
+        let mut batch = Self::new(0);
+        batch.asid = None;
+        batch
+    }
+    pub fn add(&mut self, vpn: VirtPageNum) {
+        if self.all {
+            return;
+        }
+        if self.len == FLUSH_ALL_THRESHOLD {
+            self.all = true;
+            self.len = 0;
+            return;
+        }
+        self.vas[self.len] = VirtAddr::from(vpn).0;
+        self.len += 1;
+    }
+    pub fn add_range(&mut self, range: VPNRange) {
+        for vpn in range {
+            if self.all {
+                break;
+            }
+            self.add(vpn);
+        }
+    }
+    /// Fence the whole ASID, for changes beyond single pages such as page
+    /// tables going away.
+    pub fn add_all(&mut self) {
+        self.all = true;
+        self.len = 0;
+    }
+    /// Number of pages to be fenced one by one, 0 once the whole ASID is.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    pub fn flushes_all(&self) -> bool {
+        self.all
+    }
+    pub fn flush(&mut self) {
+        if let Some(asid) = self.asid {
+            if self.all {
+                unsafe {
+                    llvm_asm!("sfence.vma zero, $0" :: "r"(asid) :: "volatile");
+                }
+            } else {
+                for &va in self.vas[..self.len].iter() {
+                    unsafe {
+                        llvm_asm!("sfence.vma $0, $1" :: "r"(va), "r"(asid) :: "volatile");
+                    }
+                }
+            }
+        }
+        self.len = 0;
+        self.all = false;
+    }
+}
+
+impl Drop for TlbFlushBatch {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+#[allow(unused)]
+pub fn tlb_batch_test() {
+    let mut batch = TlbFlushBatch::new(0);
+    batch.add(VirtPageNum(0x10));
+    batch.add_range(VPNRange::new(VirtPageNum(0x20), VirtPageNum(0x22)));
+    assert_eq!(batch.len(), 3);
+    assert!(!batch.flushes_all());
+    batch.flush();
+    assert_eq!(batch.len(), 0);
+    // one page past the threshold and the whole ASID goes
+    let end = VirtPageNum(0x100 + FLUSH_ALL_THRESHOLD);
+    batch.add_range(VPNRange::new(VirtPageNum(0x100), end));
+    assert_eq!(batch.len(), FLUSH_ALL_THRESHOLD);
+    batch.add(end);
+    assert!(batch.flushes_all());
+    assert_eq!(batch.len(), 0);
+    batch.add(VirtPageNum(0x10));
+    assert_eq!(batch.len(), 0);
+    drop(batch);
+    debug!("tlb_batch_test passed!");
+}
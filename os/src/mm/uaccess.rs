@@ -0,0 +1,320 @@
+//! Checked access to user memory for system calls. Every page touched must
+//! be mapped with U and the permission the access needs; pages the user
+//! could fault in (lazy, copy-on-write, stack growth) are faulted in first.
+//! A bad pointer is reported as EFAULT instead of panicking.
+
+use super::memory_set::fault_in_current;
+use super::{AccessType, PageTable, PageTableEntry, PhysPageNum, VirtAddr, VirtPageNum};
+use crate::config::PAGE_SIZE;
+use crate::errno::{EFAULT, ENAMETOOLONG};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use core::mem::{size_of, MaybeUninit};
+
+/// Longest path accepted from user space, the terminating nul included.
+pub const PATH_MAX: usize = 4096;
+
+/// Sv39 only translates addresses whose bits 63 to 38 are all equal, the
+/// others would alias a lower address in the page table walk.
+fn is_canonical(va: usize) -> bool {
+    ((va as isize) << 25 >> 25) as usize == va
+}
+
+fn allows(pte: &PageTableEntry, access: AccessType) -> bool {
+    pte.is_valid()
+        && pte.is_user()
+        && match access {
+            AccessType::Read => pte.readable(),
+            AccessType::Write => pte.writable(),
+            AccessType::Execute => pte.executable(),
+        }
+}
+
+/// The frame behind `vpn` if the user may access it as `access`.
+fn user_page(
+    page_table: &PageTable,
+    token: usize,
+    vpn: VirtPageNum,
+    access: AccessType,
+) -> Result<PhysPageNum, isize> {
+    match page_table.translate(vpn) {
+        Some(pte) if allows(&pte, access) => return Ok(pte.ppn()),
+        _ => {}
+    }
+    if fault_in_current(token, vpn, access) {
+        if let Some(pte) = page_table.translate(vpn) {
+            if allows(&pte, access) {
+                return Ok(pte.ppn());
+            }
+        }
+    }
+    Err(EFAULT)
+}
+
+/// `[ptr, ptr + len)` as slices of the frames behind it, one per page.
+fn user_slices(
+    token: usize,
+    ptr: usize,
+    len: usize,
+    access: AccessType,
+) -> Result<Vec<&'static mut [u8]>, isize> {
+    let end = ptr.checked_add(len).ok_or(EFAULT)?;
+    let page_table = PageTable::from_token(token);
+    let mut slices = Vec::new();
+    let mut start = ptr;
+    while start < end {
+        if !is_canonical(start) {
+            return Err(EFAULT);
+        }
+        let va = VirtAddr::from(start);
+        let ppn = user_page(&page_table, token, va.floor(), access)?;
+        let offset = va.page_offset();
+        let chunk = (PAGE_SIZE - offset).min(end - start);
+        slices.push(&mut ppn.get_bytes_array()[offset..offset + chunk]);
+        start += chunk;
+    }
+    Ok(slices)
+}
+
+/// Copy a `T` out of user space, even if it straddles a page boundary.
+pub fn copy_from_user<T: Copy>(token: usize, ptr: *const T) -> Result<T, isize> {
+    let mut value = MaybeUninit::<T>::uninit();
+    let dst =
+        unsafe { core::slice::from_raw_parts_mut(value.as_mut_ptr() as *mut u8, size_of::<T>()) };
+    let mut copied = 0;
+    for src in user_slices(token, ptr as usize, size_of::<T>(), AccessType::Read)? {
+        dst[copied..copied + src.len()].copy_from_slice(src);
+        copied += src.len();
+    }
+    Ok(unsafe { value.assume_init() })
+}
+
+/// Copy `value` into user space, even if it straddles a page boundary.
+pub fn copy_to_user<T: Copy>(token: usize, ptr: *mut T, value: &T) -> Result<(), isize> {
+    let src =
+        unsafe { core::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) };
+    let mut copied = 0;
+    for dst in user_slices(token, ptr as usize, size_of::<T>(), AccessType::Write)? {
+        let len = dst.len();
+        dst.copy_from_slice(&src[copied..copied + len]);
+        copied += len;
+    }
+    Ok(())
+}
+
+/// Read the nul terminated string at `ptr`, of at most `max` bytes with
+/// the nul. Fails with ENAMETOOLONG if there is no nul in them. Bytes are
+/// taken as Latin-1, as `translated_str` did.
+pub fn strncpy_from_user(token: usize, ptr: *const u8, max: usize) -> Result<String, isize> {
+    let page_table = PageTable::from_token(token);
+    let mut string = String::new();
+    let mut va = ptr as usize;
+    let end = va.saturating_add(max);
+    while va < end {
+        if !is_canonical(va) {
+            return Err(EFAULT);
+        }
+        let start_va = VirtAddr::from(va);
+        let ppn = user_page(&page_table, token, start_va.floor(), AccessType::Read)?;
+        let offset = start_va.page_offset();
+        let chunk = (PAGE_SIZE - offset).min(end - va);
+        for &byte in ppn.get_bytes_array()[offset..offset + chunk].iter() {
+            if byte == 0 {
+                return Ok(string);
+            }
+            string.push(byte as char);
+        }
+        va += chunk;
+    }
+    Err(ENAMETOOLONG)
+}
+
+/// A pointer into the user space of `token`, checked on every access.
+pub struct UserPtr<T> {
+    token: usize,
+    ptr: usize,
+    _marker: PhantomData<*mut T>,
+}
+
+impl<T: Copy> UserPtr<T> {
+    pub fn new(token: usize, ptr: *mut T) -> Self {
+        Self {
+            token,
+            ptr: ptr as usize,
+            _marker: PhantomData,
+        }
+    }
+    pub fn is_null(&self) -> bool {
+        self.ptr == 0
+    }
+    /// The `count`th `T` after this one.
+    pub fn add(&self, count: usize) -> Self {
+        Self {
+            token: self.token,
+            ptr: self.ptr.wrapping_add(count.wrapping_mul(size_of::<T>())),
+            _marker: PhantomData,
+        }
+    }
+    pub fn read(&self) -> Result<T, isize> {
+        copy_from_user(self.token, self.ptr as *const T)
+    }
+    pub fn write(&self, value: T) -> Result<(), isize> {
+        copy_to_user(self.token, self.ptr as *mut T, &value)
+    }
+}
+
+pub struct UserBuffer {
+    pub buffers: Vec<&'static mut [u8]>,
+}
+
+impl UserBuffer {
+    pub fn new(buffers: Vec<&'static mut [u8]>) -> Self {
+        Self { buffers }
+    }
+    /// The `len` bytes at `ptr`, each page checked for `access` as by
+    /// `copy_from_user`. The kernel writes to the buffer of a read, so that
+    /// one needs `AccessType::Write`, and a write needs `AccessType::Read`.
+    pub fn from_user(
+        token: usize,
+        ptr: *const u8,
+        len: usize,
+        access: AccessType,
+    ) -> Result<Self, isize> {
+        user_slices(token, ptr as usize, len, access).map(Self::new)
+    }
+    /// Append the buffer of another `from_user`, e.g. the next iovec.
+    pub fn append(&mut self, mut other: UserBuffer) {
+        self.buffers.append(&mut other.buffers);
+    }
+    /// The buffer a page at a time, or less at its ends.
+    pub fn chunks(&self) -> impl Iterator<Item = &[u8]> {
+        self.buffers.iter().map(|chunk| &**chunk)
+    }
+    pub fn chunks_mut(&mut self) -> impl Iterator<Item = &mut [u8]> {
+        self.buffers.iter_mut().map(|chunk| &mut **chunk)
+    }
+    pub fn len(&self) -> usize {
+        let mut total: usize = 0;
+        for b in self.buffers.iter() {
+            total += b.len();
+        }
+        total
+    }
+}
+
+impl IntoIterator for UserBuffer {
+    type Item = *mut u8;
+    type IntoIter = UserBufferIterator;
+    fn into_iter(self) -> Self::IntoIter {
+        UserBufferIterator {
+            buffers: self.buffers,
+            current_buffer: 0,
+            current_idx: 0,
+        }
+    }
+}
+
+pub struct UserBufferIterator {
+    buffers: Vec<&'static mut [u8]>,
+    current_buffer: usize,
+    current_idx: usize,
+}
+
+impl Iterator for UserBufferIterator {
+    type Item = *mut u8;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.current_buffer >= self.buffers.len() {
+            None
+        } else {
+            let r = &mut self.buffers[self.current_buffer][self.current_idx] as *mut _;
+            if self.current_idx + 1 == self.buffers[self.current_buffer].len() {
+                self.current_idx = 0;
+                self.current_buffer += 1;
+            } else {
+                self.current_idx += 1;
+            }
+            Some(r)
+        }
+    }
+}
+
+#[allow(unused)]
+pub fn uaccess_test() {
+    use super::{MapPermission, MemorySet};
+    let mut memory_set = MemorySet::new_bare();
+    let start = 0x10000;
+    memory_set
+        .insert_framed_area(
+            start.into(),
+            (start + 2 * PAGE_SIZE).into(),
+            MapPermission::R | MapPermission::W | MapPermission::U,
+        )
+        .unwrap();
+    memory_set
+        .insert_framed_area(
+            (start + 2 * PAGE_SIZE).into(),
+            (start + 3 * PAGE_SIZE).into(),
+            MapPermission::R | MapPermission::U,
+        )
+        .unwrap();
+    memory_set
+        .insert_framed_area(
+            (start + 3 * PAGE_SIZE).into(),
+            (start + 4 * PAGE_SIZE).into(),
+            MapPermission::R | MapPermission::W,
+        )
+        .unwrap();
+    let token = memory_set.token();
+    // a value across the page boundary
+    let ptr = UserPtr::<u64>::new(token, (start + PAGE_SIZE - 4) as *mut u64);
+    ptr.write(0x0123_4567_89ab_cdef).unwrap();
+    assert_eq!(ptr.read(), Ok(0x0123_4567_89ab_cdef));
+    assert_eq!(ptr.add(1).read(), Ok(0));
+    // read-only, kernel-only, unmapped and non-canonical pages
+    let read_only = UserPtr::<u64>::new(token, (start + 2 * PAGE_SIZE) as *mut u64);
+    assert_eq!(read_only.read(), Ok(0));
+    assert_eq!(read_only.write(1), Err(EFAULT));
+    let kernel_only = UserPtr::<u64>::new(token, (start + 3 * PAGE_SIZE) as *mut u64);
+    assert_eq!(kernel_only.read(), Err(EFAULT));
+    assert_eq!(
+        copy_from_user(token, (start + 4 * PAGE_SIZE) as *const u64),
+        Err(EFAULT)
+    );
+    assert_eq!(
+        copy_from_user(token, (start | 1 << 40) as *const u64),
+        Err(EFAULT)
+    );
+    assert_eq!(
+        copy_from_user(token, (PAGE_SIZE * 2 - 4) as *const u64),
+        Err(EFAULT)
+    );
+    // strings stop at the nul, or fail past `max`
+    let ptr = (start + PAGE_SIZE - 3) as *const u8;
+    let mut text = UserBuffer::from_user(token, ptr, 6, AccessType::Write).unwrap();
+    let bytes = b"path\0x";
+    let mut copied = 0;
+    for chunk in text.chunks_mut() {
+        let len = chunk.len();
+        chunk.copy_from_slice(&bytes[copied..copied + len]);
+        copied += len;
+    }
+    assert_eq!(
+        text.chunks().map(|chunk| chunk.len()).collect::<Vec<_>>(),
+        [3, 3]
+    );
+    assert_eq!(
+        strncpy_from_user(token, ptr, PATH_MAX),
+        Ok(String::from("path"))
+    );
+    assert_eq!(strncpy_from_user(token, ptr, 4), Err(ENAMETOOLONG));
+    assert_eq!(
+        strncpy_from_user(token, 0 as *const u8, PATH_MAX),
+        Err(EFAULT)
+    );
+    // buffers are checked as a whole
+    assert!(UserBuffer::from_user(token, ptr, 2 * PAGE_SIZE, AccessType::Write).is_err());
+    assert!(UserBuffer::from_user(token, ptr, 2 * PAGE_SIZE, AccessType::Read).is_ok());
+    assert!(UserBuffer::from_user(token, ptr, usize::MAX, AccessType::Read).is_err());
+    debug!("uaccess_test passed!");
+}
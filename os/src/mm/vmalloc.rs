@@ -0,0 +1,153 @@
+//! Kernel buffers of many pages, mapped at contiguous addresses of the
+//! kernel space from `KERNEL_VMM_START` on but backed by frames from
+//! anywhere, so they need no physically contiguous memory. Each buffer is
+//! followed by an unmapped guard page.
+
+use super::{MapPermission, VirtAddr, KERNEL_SPACE};
+use crate::config::{KERNEL_VMM_END, KERNEL_VMM_START, PAGE_SIZE};
+use alloc::collections::BTreeMap;
+use lazy_static::*;
+use spin::Mutex;
+
+/// First fit over the free ranges of the region, in pages.
+struct VmmAllocator {
+    /// First page of each free range to its length
+    free: BTreeMap<usize, usize>,
+}
+
+impl VmmAllocator {
+    fn new(start: usize, pages: usize) -> Self {
+        let mut free = BTreeMap::new();
+        free.insert(start, pages);
+        Self { free }
+    }
+    fn alloc(&mut self, pages: usize) -> Option<usize> {
+        let (&start, &len) = self.free.iter().find(|(_, len)| **len >= pages)?;
+        self.free.remove(&start);
+        if len > pages {
+            self.free.insert(start + pages, len - pages);
+        }
+        Some(start)
+    }
+    /// Give back `pages` from `start`, merging them with the free ranges
+    /// right before and after.
+    fn dealloc(&mut self, start: usize, pages: usize) {
+        let (mut start, mut pages) = (start, pages);
+        if let Some((&prev, &len)) = self.free.range(..start).next_back() {
+            assert!(prev + len <= start, "vmalloc range {:#x} is free", start);
+            if prev + len == start {
+                self.free.remove(&prev);
+                start = prev;
+                pages += len;
+            }
+        }
+        if let Some(len) = self.free.remove(&(start + pages)) {
+            pages += len;
+        }
+        self.free.insert(start, pages);
+    }
+}
+
+lazy_static! {
+    static ref KERNEL_VMM: Mutex<VmmAllocator> = Mutex::new(VmmAllocator::new(
+        VirtAddr::from(KERNEL_VMM_START).floor().0,
+        (KERNEL_VMM_END - KERNEL_VMM_START) / PAGE_SIZE,
+    ));
+}
+
+/// A zeroed kernel buffer from `vmalloc`, unmapped and freed on drop.
+#[derive(Debug)]
+pub struct VmBuffer {
+    start: usize,
+    pages: usize,
+}
+
+impl VmBuffer {
+    pub fn start(&self) -> usize {
+        self.start
+    }
+    pub fn len(&self) -> usize {
+        self.pages * PAGE_SIZE
+    }
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.start as *const u8, self.len()) }
+    }
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.start as *mut u8, self.len()) }
+    }
+}
+
+impl Drop for VmBuffer {
+    fn drop(&mut self) {
+        KERNEL_SPACE
+            .lock()
+            .remove_area_with_start_vpn(VirtAddr::from(self.start).floor());
+        KERNEL_VMM
+            .lock()
+            .dealloc(VirtAddr::from(self.start).floor().0, self.pages + 1);
+    }
+}
+
+/// A buffer of `len` bytes rounded up to whole pages, `None` for a zero
+/// `len` or if the region or the frames run out.
+pub fn vmalloc(len: usize) -> Option<VmBuffer> {
+    if len == 0 {
+        return None;
+    }
+    let pages = (len + PAGE_SIZE - 1) / PAGE_SIZE;
+    // and the guard page
+    let start_vpn = KERNEL_VMM.lock().alloc(pages + 1)?;
+    let start = start_vpn * PAGE_SIZE;
+    let mapped = KERNEL_SPACE.lock().insert_framed_area(
+        start.into(),
+        (start + pages * PAGE_SIZE).into(),
+        MapPermission::R | MapPermission::W,
+    );
+    if mapped.is_err() {
+        KERNEL_VMM.lock().dealloc(start_vpn, pages + 1);
+        return None;
+    }
+    Some(VmBuffer { start, pages })
+}
+
+#[allow(unused)]
+pub fn vmalloc_test() {
+    use super::frame_usage;
+
+    let (used, _) = frame_usage();
+    let mut first = vmalloc(3 * PAGE_SIZE - 1).unwrap();
+    let start = first.start();
+    assert!(KERNEL_VMM_START <= start && start < KERNEL_VMM_END);
+    assert_eq!(first.len(), 3 * PAGE_SIZE);
+    assert!(first.as_slice().iter().all(|&b| b == 0));
+    first.as_mut_slice().fill(0x5a);
+    // past the guard page
+    let second = vmalloc(PAGE_SIZE).unwrap();
+    assert_eq!(second.start(), start + 4 * PAGE_SIZE);
+    {
+        let kernel_space = KERNEL_SPACE.lock();
+        let ppns: alloc::vec::Vec<_> = (0..3)
+            .map(|i| {
+                let vpn = VirtAddr::from(first.start() + i * PAGE_SIZE).floor();
+                kernel_space.translate(vpn).unwrap().ppn()
+            })
+            .collect();
+        assert!(ppns
+            .iter()
+            .all(|ppn| ppn.get_bytes_array().iter().all(|&b| b == 0x5a)));
+        let guard = VirtAddr::from(first.start() + 3 * PAGE_SIZE).floor();
+        assert!(kernel_space
+            .translate(guard)
+            .map_or(true, |pte| !pte.is_valid()));
+    }
+    assert!(frame_usage().0 >= used + 4);
+    // freed ranges merge and are handed out again
+    drop(first);
+    let third = vmalloc(2 * PAGE_SIZE).unwrap();
+    assert_eq!(third.start(), start);
+    drop(second);
+    drop(third);
+    assert_eq!(vmalloc(8 * PAGE_SIZE).unwrap().start(), start);
+    assert!(vmalloc(0).is_none());
+    debug!("vmalloc_test passed!");
+}
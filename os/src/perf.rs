@@ -0,0 +1,153 @@
+//! Software performance counters, handed out as fds by `perf_event_open`.
+
+use crate::config::CPU_NUM;
+use crate::errno::{EINVAL, ENOTTY};
+use crate::fs::File;
+use crate::mm::UserBuffer;
+use crate::task::hart_id;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use lazy_static::*;
+use spin::Mutex;
+
+pub const PERF_TYPE_SOFTWARE: u32 = 1;
+pub const PERF_COUNT_SW_PAGE_FAULTS: u64 = 2;
+pub const PERF_COUNT_SW_CONTEXT_SWITCHES: u64 = 3;
+pub const PERF_EVENT_IOC_RESET: usize = 0x2403;
+
+/// The leading fields of Linux's `struct perf_event_attr`, the only ones
+/// looked at.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct PerfEventAttr {
+    pub type_: u32,
+    pub size: u32,
+    pub config: u64,
+}
+
+/// Whether `config` names a software event we count.
+pub fn is_supported(attr: &PerfEventAttr) -> bool {
+    attr.type_ == PERF_TYPE_SOFTWARE
+        && (attr.config == PERF_COUNT_SW_PAGE_FAULTS
+            || attr.config == PERF_COUNT_SW_CONTEXT_SWITCHES)
+}
+
+pub struct PerfCounter {
+    config: u64,
+    count: AtomicU64,
+}
+
+impl PerfCounter {
+    pub fn new(config: u64) -> Arc<Self> {
+        Arc::new(Self {
+            config,
+            count: AtomicU64::new(0),
+        })
+    }
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+    pub fn reset(&self) {
+        self.count.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Counters attached to a task or a hart. They are only borrowed from the
+/// fds that own them, so closing the fd stops the counting.
+#[derive(Default)]
+pub struct PerfCounters {
+    counters: Vec<Weak<PerfCounter>>,
+}
+
+impl PerfCounters {
+    pub fn attach(&mut self, counter: &Arc<PerfCounter>) {
+        self.counters.retain(|counter| counter.strong_count() > 0);
+        self.counters.push(Arc::downgrade(counter));
+    }
+    /// Bump every counter of event `config`.
+    pub fn count(&self, config: u64) {
+        for counter in self.counters.iter().filter_map(|counter| counter.upgrade()) {
+            if counter.config == config {
+                counter.count.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+lazy_static! {
+    /// cpu-wide counters of each hart
+    pub static ref CPU_COUNTERS: Vec<Mutex<PerfCounters>> =
+        (0..CPU_NUM).map(|_| Mutex::new(PerfCounters::default())).collect();
+}
+
+/// Count event `config` for the running task, whose counters are given,
+/// and for the hart it runs on.
+pub fn count_event(task_counters: &PerfCounters, config: u64) {
+    task_counters.count(config);
+    CPU_COUNTERS[hart_id()].lock().count(config);
+}
+
+/// The file behind a perf event fd. Reading gives the count as a `u64`.
+pub struct PerfEventFile {
+    counter: Arc<PerfCounter>,
+}
+
+impl PerfEventFile {
+    pub fn new(counter: Arc<PerfCounter>) -> Self {
+        Self { counter }
+    }
+}
+
+impl File for PerfEventFile {
+    fn read(&self, buf: UserBuffer) -> Result<usize, isize> {
+        if buf.len() < 8 {
+            return Err(EINVAL);
+        }
+        let count = self.counter.count().to_ne_bytes();
+        for (byte_ref, byte) in buf.into_iter().zip(count.iter()) {
+            unsafe {
+                *byte_ref = *byte;
+            }
+        }
+        Ok(count.len())
+    }
+    fn write(&self, _buf: UserBuffer) -> Result<usize, isize> {
+        Err(EINVAL)
+    }
+    fn ioctl(&self, cmd: usize, _arg: usize) -> Result<isize, isize> {
+        match cmd {
+            PERF_EVENT_IOC_RESET => {
+                self.counter.reset();
+                Ok(0)
+            }
+            _ => Err(ENOTTY),
+        }
+    }
+    fn path(&self) -> String {
+        String::from("anon_inode:[perf_event]")
+    }
+}
+
+#[allow(unused)]
+pub fn perf_counter_test() {
+    let mut counters = PerfCounters::default();
+    let switches = PerfCounter::new(PERF_COUNT_SW_CONTEXT_SWITCHES);
+    let faults = PerfCounter::new(PERF_COUNT_SW_PAGE_FAULTS);
+    counters.attach(&switches);
+    counters.attach(&faults);
+    for _ in 0..10 {
+        counters.count(PERF_COUNT_SW_CONTEXT_SWITCHES);
+    }
+    counters.count(PERF_COUNT_SW_PAGE_FAULTS);
+    assert_eq!(switches.count(), 10);
+    assert_eq!(faults.count(), 1);
+    switches.reset();
+    assert_eq!(switches.count(), 0);
+    // a dropped counter is forgotten
+    drop(faults);
+    counters.attach(&switches);
+    assert_eq!(counters.counters.len(), 2);
+    debug!("perf_counter_test passed!");
+}
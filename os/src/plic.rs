@@ -1,5 +1,7 @@
 use rv_plic::{Priority, PLIC};
 
+use crate::boot::machine;
+use crate::mm::{kernel_ioremap, MapPermission};
 use crate::trap::{push_trap_record, UserTrapRecord, USER_EXT_INT_MAP};
 use crate::uart;
 
@@ -7,6 +9,9 @@ use crate::uart;
 pub const PLIC_BASE: usize = 0xc00_0000;
 #[cfg(any(feature = "board_qemu", feature = "board_lrv"))]
 pub const PLIC_PRIORITY_BIT: usize = 3;
+/// The register window, up to the context registers of the last hart.
+#[cfg(any(feature = "board_qemu", feature = "board_lrv"))]
+const PLIC_SIZE: usize = 0x400_0000;
 
 pub type Plic = PLIC<{ PLIC_BASE }, { PLIC_PRIORITY_BIT }>;
 
@@ -21,14 +26,29 @@ pub fn get_context(hart_id: usize, mode: char) -> usize {
         }
 }
 
+/// Map the registers of the PLIC, before anything touches them. `Plic` is
+/// built for `PLIC_BASE`, so a PLIC found elsewhere can not be driven.
+fn map_registers() {
+    let base = machine().plic;
+    if base != PLIC_BASE {
+        panic!(
+            "PLIC at {:#x}, the kernel drives one at {:#x}",
+            base, PLIC_BASE
+        );
+    }
+    kernel_ioremap(PLIC_BASE, PLIC_SIZE, MapPermission::R | MapPermission::W).unwrap();
+}
+
 #[cfg(feature = "board_qemu")]
 pub fn init() {
+    map_registers();
     Plic::set_priority(9, Priority::lowest());
     Plic::set_priority(12, Priority::lowest());
 }
 
 #[cfg(feature = "board_lrv")]
 pub fn init() {
+    map_registers();
     Plic::set_priority(4, Priority::lowest());
     Plic::set_priority(5, Priority::lowest());
 }
@@ -0,0 +1,49 @@
+//! Kernel entropy for layout randomization: a splitmix64 generator whose
+//! state also takes in the time counter on every draw, so the sequence
+//! depends on when it is drawn from. Not meant for cryptography.
+
+use lazy_static::*;
+use riscv::register::time;
+use spin::Mutex;
+
+const GOLDEN_GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+
+lazy_static! {
+    static ref STATE: Mutex<u64> = Mutex::new(GOLDEN_GAMMA);
+}
+
+pub fn random() -> u64 {
+    let mut state = STATE.lock();
+    *state = state
+        .wrapping_add(GOLDEN_GAMMA)
+        .wrapping_add(time::read() as u64);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// A random number in `[0, bound)`, 0 for a zero bound.
+pub fn random_below(bound: usize) -> usize {
+    if bound == 0 {
+        0
+    } else {
+        (random() % bound as u64) as usize
+    }
+}
+
+#[allow(unused)]
+pub fn random_test() {
+    let draws: [u64; 4] = [random(), random(), random(), random()];
+    for i in 0..draws.len() {
+        for j in i + 1..draws.len() {
+            assert_ne!(draws[i], draws[j]);
+        }
+    }
+    // every bit gets set in a few draws
+    let ones = (0..64).fold(0, |ones, _| ones | random());
+    assert_eq!(ones, u64::MAX);
+    assert!((0..64).all(|_| random_below(10) < 10));
+    assert_eq!(random_below(0), 0);
+    debug!("random_test passed!");
+}
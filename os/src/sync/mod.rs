@@ -0,0 +1,9 @@
+mod mutex;
+
+#[allow(unused_imports)]
+pub use mutex::{BlockingMutex, BlockingMutexGuard};
+
+#[cfg(debug_assertions)]
+pub fn self_test() {
+    mutex::blocking_mutex_test();
+}
@@ -0,0 +1,180 @@
+use crate::task::{block_current_and_run_next, current_task, set_task_nice, wake_task};
+use crate::task::{BlockReason, TaskControlBlock, TaskStatus};
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use spin::Mutex;
+
+/// A mutex that parks contending tasks instead of spinning.
+///
+/// The lock is handed to waiters in FIFO order. While it is contended the
+/// holder runs at the highest priority and the lowest nice value among
+/// itself and the waiters, so it is favoured under the stride and the
+/// priority policy alike, and drops back to its own values on release.
+#[allow(dead_code)]
+pub struct BlockingMutex<T> {
+    inner: Mutex<BlockingMutexInner>,
+    data: UnsafeCell<T>,
+}
+
+struct BlockingMutexInner {
+    /// The holder and its priority and nice value before any donation.
+    owner: Option<(Arc<TaskControlBlock>, isize, isize)>,
+    wait_queue: VecDeque<Arc<TaskControlBlock>>,
+}
+
+unsafe impl<T: Send> Sync for BlockingMutex<T> {}
+
+#[allow(dead_code)]
+pub struct BlockingMutexGuard<'a, T> {
+    mutex: &'a BlockingMutex<T>,
+}
+
+#[allow(dead_code)]
+impl<T> BlockingMutex<T> {
+    pub fn new(data: T) -> Self {
+        Self {
+            inner: Mutex::new(BlockingMutexInner {
+                owner: None,
+                wait_queue: VecDeque::new(),
+            }),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn lock(&self) -> BlockingMutexGuard<T> {
+        let task = current_task().unwrap();
+        if !self.acquire_or_wait(&task) {
+            drop(task);
+            // the lock is handed over directly, so it is ours once we run again
+            block_current_and_run_next();
+        }
+        BlockingMutexGuard { mutex: self }
+    }
+
+    /// Take the lock for `task` if it is free. Otherwise queue `task` as
+    /// blocked, donate its priority and nice value to the holder and return
+    /// false.
+    fn acquire_or_wait(&self, task: &Arc<TaskControlBlock>) -> bool {
+        let mut inner = self.inner.lock();
+        match &inner.owner {
+            None => {
+                let (priority, nice) = priority_and_nice(task);
+                inner.owner = Some((task.clone(), priority, nice));
+                true
+            }
+            Some((owner, _, _)) => {
+                task.acquire_inner_lock()
+                    .block(BlockReason::Mutex(self as *const _ as usize));
+                let (priority, nice) = priority_and_nice(task);
+                donate(owner, priority, nice);
+                inner.wait_queue.push_back(task.clone());
+                false
+            }
+        }
+    }
+
+    /// Restore the holder's priority and nice value and pass the lock to the
+    /// first waiter, which is returned so that the caller can wake it.
+    fn release(&self) -> Option<Arc<TaskControlBlock>> {
+        let mut inner = self.inner.lock();
+        let (owner, base_priority, base_nice) = inner.owner.take().unwrap();
+        owner.acquire_inner_lock().priority = base_priority;
+        set_task_nice(&owner, base_nice);
+        let next = inner.wait_queue.pop_front()?;
+        let (next_priority, next_nice) = priority_and_nice(&next);
+        // the remaining waiters now donate to the new holder
+        for waiter in inner.wait_queue.iter() {
+            let (priority, nice) = priority_and_nice(waiter);
+            donate(&next, priority, nice);
+        }
+        inner.owner = Some((next.clone(), next_priority, next_nice));
+        Some(next)
+    }
+}
+
+fn priority_and_nice(task: &Arc<TaskControlBlock>) -> (isize, isize) {
+    let inner = task.acquire_inner_lock();
+    (inner.priority, inner.nice)
+}
+
+/// Raise `task` to at least `priority` and at most `nice`, what the stride
+/// and the priority policy rank by.
+fn donate(task: &Arc<TaskControlBlock>, priority: isize, nice: isize) {
+    let mut inner = task.acquire_inner_lock();
+    inner.priority = inner.priority.max(priority);
+    let favoured = nice < inner.nice;
+    drop(inner);
+    if favoured {
+        // moved to its new level if it is queued
+        set_task_nice(task, nice);
+    }
+}
+
+impl<'a, T> Deref for BlockingMutexGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for BlockingMutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<'a, T> Drop for BlockingMutexGuard<'a, T> {
+    fn drop(&mut self) {
+        if let Some(next) = self.mutex.release() {
+            wake_task(next);
+        }
+    }
+}
+
+/// Drive the queueing logic with tasks that are not running: the waiters are
+/// only marked blocked and ready, never switched.
+#[allow(unused)]
+pub fn blocking_mutex_test() {
+    let elf_data = crate::loader::get_app_data_by_name("initproc").unwrap();
+    let tasks: alloc::vec::Vec<_> = [(4, 0), (8, -5), (6, -10)]
+        .iter()
+        .map(|&(priority, nice)| {
+            let task = TaskControlBlock::new("initproc", elf_data);
+            let mut inner = task.acquire_inner_lock();
+            inner.priority = priority;
+            inner.nice = nice;
+            drop(inner);
+            task
+        })
+        .collect();
+    let status = |task: &Arc<TaskControlBlock>| task.acquire_inner_lock().task_status;
+    let mutex = BlockingMutex::new(0usize);
+    assert!(mutex.acquire_or_wait(&tasks[0]));
+    assert!(!mutex.acquire_or_wait(&tasks[1]));
+    assert!(!mutex.acquire_or_wait(&tasks[2]));
+    assert!(status(&tasks[1]) == TaskStatus::Blocked);
+    let reason = tasks[1].acquire_inner_lock().block_reason;
+    assert_eq!(
+        reason,
+        Some(BlockReason::Mutex(&mutex as *const _ as usize))
+    );
+    // the holder inherits the highest waiting priority and the lowest
+    // waiting nice value, whichever waiter they come from
+    assert_eq!(priority_and_nice(&tasks[0]), (8, -10));
+    // FIFO handoff, not priority order
+    let next = mutex.release().unwrap();
+    assert!(Arc::ptr_eq(&next, &tasks[1]));
+    wake_task(next);
+    assert!(status(&tasks[1]) == TaskStatus::Ready);
+    assert_eq!(priority_and_nice(&tasks[0]), (4, 0));
+    assert_eq!(priority_and_nice(&tasks[1]), (8, -10));
+    let next = mutex.release().unwrap();
+    assert!(Arc::ptr_eq(&next, &tasks[2]));
+    wake_task(next);
+    assert_eq!(priority_and_nice(&tasks[1]), (8, -5));
+    assert_eq!(priority_and_nice(&tasks[2]), (6, -10));
+    assert!(mutex.release().is_none());
+    debug!("blocking_mutex_test passed!");
+}
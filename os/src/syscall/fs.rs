@@ -1,58 +1,352 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
 use core::cmp::min;
 
-use crate::fs::{make_pipe, File};
+use crate::config::CPU_NUM;
+use crate::errno::{EACCES, EBADF, EFAULT, EINVAL, ENOENT, ESRCH};
+use crate::fs::{make_pipe, open_file, truncate_file, File, OpenFlags};
+use crate::perf::{is_supported, PerfCounter, PerfEventAttr, PerfEventFile, CPU_COUNTERS};
 use crate::task::{current_task, current_user_token};
 use crate::{
-    mm::{translated_byte_buffer, translated_refmut, UserBuffer},
+    mm::{copy_from_user, strncpy_from_user, AccessType, UserBuffer, UserPtr, PATH_MAX},
     task::find_task,
 };
 
+/// Run `op` on the file behind `fd` with the user buffer `buf[..len]`.
+/// The task lock is released before `op` so that it may block.
+fn file_io(
+    fd: usize,
+    buf: *const u8,
+    len: usize,
+    access: AccessType,
+    op: impl FnOnce(&dyn File, UserBuffer) -> Result<usize, isize>,
+) -> isize {
+    let token = current_user_token();
+    let task = current_task().unwrap();
+    let inner = task.acquire_inner_lock();
+    let file = match inner.fd_table.get(fd) {
+        Some(Some(file)) => file.clone(),
+        _ => return -EBADF,
+    };
+    // release Task lock manually to avoid deadlock
+    drop(inner);
+    let buf = match UserBuffer::from_user(token, buf, len, access) {
+        Ok(buf) => buf,
+        Err(errno) => return -errno,
+    };
+    match op(file.as_ref(), buf) {
+        Ok(io_len) => io_len as isize,
+        Err(errno) => -errno,
+    }
+}
+
 pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
+    file_io(fd, buf, len, AccessType::Read, |file, buf| file.write(buf))
+}
+
+pub fn sys_read(fd: usize, buf: *const u8, len: usize) -> isize {
+    file_io(fd, buf, len, AccessType::Write, |file, buf| file.read(buf))
+}
+
+/// Read at `offset` without moving the file offset.
+pub fn sys_pread64(fd: usize, buf: *const u8, len: usize, offset: isize) -> isize {
+    if offset < 0 {
+        return -EINVAL;
+    }
+    file_io(fd, buf, len, AccessType::Write, |file, buf| {
+        file.read_at(offset as usize, buf)
+    })
+}
+
+/// Write at `offset` without moving the file offset. EINVAL if the write
+/// would end past the largest offset.
+pub fn sys_pwrite64(fd: usize, buf: *const u8, len: usize, offset: isize) -> isize {
+    if offset < 0
+        || (offset as usize)
+            .checked_add(len)
+            .map_or(true, |end| end > isize::MAX as usize)
+    {
+        return -EINVAL;
+    }
+    file_io(fd, buf, len, AccessType::Read, |file, buf| {
+        file.write_at(offset as usize, buf)
+    })
+}
+
+/// One buffer of a `readv`/`writev` request, laid out as `struct iovec`.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct IoVec {
+    pub iov_base: *mut u8,
+    pub iov_len: usize,
+}
+
+const IOV_MAX: usize = 1024;
+
+/// Gather the `iovcnt` user buffers described at `iov` into one `UserBuffer`,
+/// so the file sees them as a single transfer.
+fn translated_iovecs(
+    token: usize,
+    iov: *const IoVec,
+    iovcnt: usize,
+    access: AccessType,
+) -> Result<UserBuffer, isize> {
+    if iovcnt > IOV_MAX {
+        return Err(EINVAL);
+    }
+    let mut buffers = UserBuffer::new(Vec::new());
+    let mut total_len = 0usize;
+    for i in 0..iovcnt {
+        let iovec = copy_from_user(token, iov.wrapping_add(i)).map_err(|_| EFAULT)?;
+        // the total must fit in the return value
+        total_len = total_len
+            .checked_add(iovec.iov_len)
+            .filter(|&len| len <= isize::MAX as usize)
+            .ok_or(EINVAL)?;
+        buffers.append(UserBuffer::from_user(
+            token,
+            iovec.iov_base,
+            iovec.iov_len,
+            access,
+        )?);
+    }
+    Ok(buffers)
+}
+
+/// Run `op` on the file behind `fd` with the buffers of an iovec array.
+fn file_iov_io(
+    fd: usize,
+    iov: *const IoVec,
+    iovcnt: usize,
+    access: AccessType,
+    op: impl FnOnce(&dyn File, UserBuffer) -> Result<usize, isize>,
+) -> isize {
     let token = current_user_token();
     let task = current_task().unwrap();
     let inner = task.acquire_inner_lock();
-    if fd >= inner.fd_table.len() {
-        return -1;
+    let file = match inner.fd_table.get(fd) {
+        Some(Some(file)) => file.clone(),
+        _ => return -EBADF,
+    };
+    // release Task lock manually to avoid deadlock
+    drop(inner);
+    let buf = match translated_iovecs(token, iov, iovcnt, access) {
+        Ok(buf) => buf,
+        Err(errno) => return -errno,
+    };
+    match op(file.as_ref(), buf) {
+        Ok(io_len) => io_len as isize,
+        Err(errno) => -errno,
     }
-    if let Some(file) = &inner.fd_table[fd] {
-        let file = file.clone();
-        // release Task lock manually to avoid deadlock
-        drop(inner);
-        if let Ok(buffers) = translated_byte_buffer(token, buf, len) {
-            match file.write(UserBuffer::new(buffers)) {
-                Ok(write_len) => write_len as isize,
-                Err(_) => -1,
-            }
-        } else {
-            -1
+}
+
+pub fn sys_readv(fd: usize, iov: *const IoVec, iovcnt: usize) -> isize {
+    file_iov_io(fd, iov, iovcnt, AccessType::Write, |file, buf| {
+        file.read(buf)
+    })
+}
+
+pub fn sys_writev(fd: usize, iov: *const IoVec, iovcnt: usize) -> isize {
+    file_iov_io(fd, iov, iovcnt, AccessType::Read, |file, buf| {
+        file.write(buf)
+    })
+}
+
+pub fn sys_open(path: *const u8, flags: u32) -> isize {
+    let task = current_task().unwrap();
+    let token = current_user_token();
+    let path = match strncpy_from_user(token, path, PATH_MAX) {
+        Ok(path) => path,
+        Err(errno) => return -errno,
+    };
+    let flags = match OpenFlags::from_bits(flags) {
+        Some(flags) => flags,
+        None => return -1,
+    };
+    let (uid, gid) = {
+        let inner = task.acquire_inner_lock();
+        (inner.uid, inner.gid)
+    };
+    match open_file(path.as_str(), flags, uid, gid) {
+        Ok(file) => {
+            let mut inner = task.acquire_inner_lock();
+            let fd = inner.alloc_fd();
+            inner.fd_table[fd] = Some(file);
+            fd as isize
         }
-    } else {
-        -1
+        Err(errno) => -errno,
     }
 }
 
-pub fn sys_read(fd: usize, buf: *const u8, len: usize) -> isize {
+pub fn sys_truncate(path: *const u8, len: usize) -> isize {
+    let task = current_task().unwrap();
     let token = current_user_token();
+    let path = match strncpy_from_user(token, path, PATH_MAX) {
+        Ok(path) => path,
+        Err(errno) => return -errno,
+    };
+    let (uid, gid) = {
+        let inner = task.acquire_inner_lock();
+        (inner.uid, inner.gid)
+    };
+    match truncate_file(path.as_str(), len, uid, gid) {
+        Ok(()) => 0,
+        Err(errno) => -errno,
+    }
+}
+
+pub fn sys_ftruncate(fd: usize, len: usize) -> isize {
     let task = current_task().unwrap();
     let inner = task.acquire_inner_lock();
-    if fd >= inner.fd_table.len() {
-        return -1;
+    let file = match inner.fd_table.get(fd) {
+        Some(Some(file)) => file.clone(),
+        _ => return -EBADF,
+    };
+    drop(inner);
+    match file.truncate(len) {
+        Ok(()) => 0,
+        Err(errno) => -errno,
     }
-    if let Some(file) = &inner.fd_table[fd] {
-        let file = file.clone();
-        // release Task lock manually to avoid deadlock
-        drop(inner);
-        if let Ok(buffers) = translated_byte_buffer(token, buf, len) {
-            match file.read(UserBuffer::new(buffers)) {
-                Ok(read_len) => read_len as isize,
-                Err(_) => -1,
+}
+
+pub fn sys_lseek(fd: usize, offset: isize, whence: usize) -> isize {
+    let task = current_task().unwrap();
+    let inner = task.acquire_inner_lock();
+    let file = match inner.fd_table.get(fd) {
+        Some(Some(file)) => file.clone(),
+        _ => return -EBADF,
+    };
+    drop(inner);
+    match file.seek(offset, whence) {
+        Ok(offset) => offset as isize,
+        Err(errno) => -errno,
+    }
+}
+
+const F_DUPFD: usize = 0;
+const F_GETFD: usize = 1;
+const F_SETFD: usize = 2;
+const F_GETFL: usize = 3;
+const F_SETFL: usize = 4;
+const F_DUPFD_CLOEXEC: usize = 1030;
+const FD_CLOEXEC: usize = 1;
+/// Upper bound for fds handed out by `F_DUPFD`
+const FD_LIMIT: usize = 1024;
+
+pub fn sys_fcntl(fd: usize, cmd: usize, arg: usize) -> isize {
+    let task = current_task().unwrap();
+    let mut inner = task.acquire_inner_lock();
+    let file = match inner.fd_table.get(fd) {
+        Some(Some(file)) => file.clone(),
+        _ => return -EBADF,
+    };
+    match cmd {
+        F_DUPFD | F_DUPFD_CLOEXEC => {
+            if arg >= FD_LIMIT {
+                return -EINVAL;
             }
-        } else {
-            -1
+            let new_fd = inner.alloc_fd_from(arg);
+            inner.fd_table[new_fd] = Some(file);
+            if cmd == F_DUPFD_CLOEXEC {
+                inner.fd_cloexec.insert(new_fd);
+            }
+            new_fd as isize
         }
-    } else {
-        -1
+        F_GETFD => {
+            if inner.fd_cloexec.contains(&fd) {
+                FD_CLOEXEC as isize
+            } else {
+                0
+            }
+        }
+        F_SETFD => {
+            if arg & FD_CLOEXEC != 0 {
+                inner.fd_cloexec.insert(fd);
+            } else {
+                inner.fd_cloexec.remove(&fd);
+            }
+            0
+        }
+        F_GETFL => file.status_flags().bits() as isize,
+        F_SETFL => {
+            drop(inner);
+            match file.set_status_flags(OpenFlags::from_bits_truncate(arg as u32)) {
+                Ok(()) => 0,
+                Err(errno) => -errno,
+            }
+        }
+        _ => -EINVAL,
+    }
+}
+
+pub fn sys_ioctl(fd: usize, cmd: usize, arg: usize) -> isize {
+    let task = current_task().unwrap();
+    let inner = task.acquire_inner_lock();
+    let file = match inner.fd_table.get(fd) {
+        Some(Some(file)) => file.clone(),
+        _ => return -EBADF,
+    };
+    drop(inner);
+    match file.ioctl(cmd, arg) {
+        Ok(ret) => ret,
+        Err(errno) => -errno,
+    }
+}
+
+/// Open a software event counter. `pid` 0 is the caller and `pid` -1 with
+/// a `cpu` counts everything on that hart; event groups are not supported.
+pub fn sys_perf_event_open(
+    attr: *const PerfEventAttr,
+    pid: isize,
+    cpu: isize,
+    group_fd: isize,
+    flags: usize,
+) -> isize {
+    let token = current_user_token();
+    let attr = match copy_from_user(token, attr) {
+        Ok(attr) => attr,
+        Err(_) => return -EFAULT,
+    };
+    if !is_supported(&attr) {
+        return -ENOENT;
+    }
+    if group_fd != -1 || flags != 0 {
+        return -EINVAL;
     }
+    let task = current_task().unwrap();
+    let (uid, gid) = {
+        let inner = task.acquire_inner_lock();
+        (inner.uid, inner.gid)
+    };
+    let counter = PerfCounter::new(attr.config);
+    match (pid, cpu) {
+        (-1, cpu) if cpu >= 0 && (cpu as usize) < CPU_NUM => {
+            if uid != 0 {
+                return -EACCES;
+            }
+            CPU_COUNTERS[cpu as usize].lock().attach(&counter);
+        }
+        (pid, -1) if pid >= 0 => {
+            let target = if pid == 0 {
+                task.clone()
+            } else {
+                match find_task(pid as usize) {
+                    Some(target) => target,
+                    None => return -ESRCH,
+                }
+            };
+            let mut target_inner = target.acquire_inner_lock();
+            if uid != 0 && (target_inner.uid, target_inner.gid) != (uid, gid) {
+                return -EACCES;
+            }
+            target_inner.perf_counters.attach(&counter);
+        }
+        _ => return -EINVAL,
+    }
+    let mut inner = task.acquire_inner_lock();
+    let fd = inner.alloc_fd();
+    inner.fd_table[fd] = Some(Arc::new(PerfEventFile::new(counter)));
+    fd as isize
 }
 
 pub fn sys_close(fd: usize) -> isize {
@@ -64,7 +358,7 @@ pub fn sys_close(fd: usize) -> isize {
     if inner.fd_table[fd].is_none() {
         return -1;
     }
-    inner.fd_table[fd].take();
+    inner.close_fd(fd);
     0
 }
 
@@ -77,9 +371,16 @@ pub fn sys_pipe(pipe: *mut usize) -> isize {
     inner.fd_table[read_fd] = Some(pipe_read);
     let write_fd = inner.alloc_fd();
     inner.fd_table[write_fd] = Some(pipe_write);
-    *translated_refmut(token, pipe) = read_fd;
-    *translated_refmut(token, unsafe { pipe.add(1) }) = write_fd;
-    0
+    // the pages may have to be faulted in, which takes the lock
+    drop(inner);
+    let pipe = UserPtr::new(token, pipe);
+    match pipe
+        .write(read_fd)
+        .and_then(|_| pipe.add(1).write(write_fd))
+    {
+        Ok(()) => 0,
+        Err(errno) => -errno,
+    }
 }
 
 pub fn sys_mailwrite(pid: usize, buf: *mut u8, len: usize) -> isize {
@@ -92,9 +393,9 @@ pub fn sys_mailwrite(pid: usize, buf: *mut u8, len: usize) -> isize {
             return 0;
         }
 
-        if let Ok(buffers) = translated_byte_buffer(token, buf, min(len, 256)) {
+        if let Ok(buf) = UserBuffer::from_user(token, buf, min(len, 256), AccessType::Read) {
             let socket = receive_task.create_socket();
-            match socket.write(UserBuffer::new(buffers)) {
+            match socket.write(buf) {
                 Ok(write_len) => write_len as isize,
                 Err(_) => -1,
             }
@@ -120,8 +421,8 @@ pub fn sys_mailread(buf: *mut u8, len: usize) -> isize {
         return 0;
     }
     let mail_box = task.acquire_inner_lock().mail_box.clone();
-    if let Ok(buffers) = translated_byte_buffer(token, buf, min(len, 256)) {
-        match mail_box.read(UserBuffer::new(buffers)) {
+    if let Ok(buf) = UserBuffer::from_user(token, buf, min(len, 256), AccessType::Write) {
+        match mail_box.read(buf) {
             Ok(read_len) => {
                 debug!("mail read {} len", read_len);
                 read_len as isize
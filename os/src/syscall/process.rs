@@ -1,16 +1,24 @@
 use core::mem::size_of;
 
+use crate::boot::machine;
 use crate::config::CPU_NUM;
+use crate::errno::{EFAULT, EINVAL, ENOMEM, EPERM, ESRCH};
 use crate::loader::get_app_data_by_name;
-use crate::mm;
+use crate::mm::{self, MemStats, MmapFlags, RLimit};
 use crate::plic::{get_context, Plic};
 use crate::task::{
-    add_task, current_task, current_user_token, exit_current_and_run_next, hart_id, mmap, munmap,
-    set_current_priority, suspend_current_and_run_next,
+    add_task, brk, current_task, current_user_token, exit_current_and_run_next, find_task, hart_id,
+    madvise, mlock, mmap, mmap_file, mprotect, mremap, munlock, munmap, set_current_priority,
+    set_task_affinity, set_task_nice, shm_attach, shm_detach, suspend_current_and_run_next,
+    TaskControlBlock, NICE_MAX, NICE_MIN,
 };
 use crate::trap::{push_trap_record, UserTrapRecord};
+use riscv::register::time;
 
-use crate::timer::get_time;
+use crate::timer::{
+    clock_gettime, get_time, TimeSpec, TimeVal, Timex, CLOCK_REALTIME, SOFTWARE_CLOCK,
+};
+use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 
@@ -32,6 +40,111 @@ pub fn sys_set_priority(prio: isize) -> isize {
     }
 }
 
+/// `which` of `sys_setpriority` and `sys_getpriority` for a single process,
+/// the only one there is.
+const PRIO_PROCESS: usize = 0;
+
+/// The process `pid`, 0 for the caller, if the caller may change it: it
+/// belongs to the same user or the caller is root.
+fn target_process(pid: usize) -> Result<(Arc<TaskControlBlock>, u32), isize> {
+    let current = current_task().unwrap();
+    let uid = current.acquire_inner_lock().uid;
+    let task = if pid == 0 {
+        current
+    } else {
+        find_task(pid).ok_or(ESRCH)?
+    };
+    if task.acquire_inner_lock().uid != uid && uid != 0 {
+        return Err(EPERM);
+    }
+    Ok((task, uid))
+}
+
+fn priority_target(which: usize, who: usize) -> Result<(Arc<TaskControlBlock>, u32), isize> {
+    if which != PRIO_PROCESS {
+        return Err(EINVAL);
+    }
+    target_process(who)
+}
+
+/// Set the nice value of process `who`, 0 for the caller, clamped to
+/// `NICE_MIN..=NICE_MAX`. Only root lowers it.
+pub fn sys_setpriority(which: usize, who: usize, nice: isize) -> isize {
+    let (task, uid) = match priority_target(which, who) {
+        Ok(target) => target,
+        Err(errno) => return -errno,
+    };
+    let nice = nice.max(NICE_MIN).min(NICE_MAX);
+    if nice < task.acquire_inner_lock().nice && uid != 0 {
+        return -EPERM;
+    }
+    set_task_nice(&task, nice);
+    0
+}
+
+/// The nice value of process `who` as `20 - nice`, from 1 to 40, so that
+/// it does not look like an error.
+pub fn sys_getpriority(which: usize, who: usize) -> isize {
+    match priority_target(which, who) {
+        Ok((task, _)) => 20 - task.acquire_inner_lock().nice,
+        Err(errno) => -errno,
+    }
+}
+
+/// Bit `i` for each hart `i` the kernel runs on.
+fn online_harts() -> usize {
+    (1 << machine().harts) - 1
+}
+
+/// Let process `pid`, 0 for the caller, run only on the harts in the mask
+/// at `mask`, a word of `cpusetsize` bytes. Harts the kernel does not run
+/// on are dropped from it, EINVAL if that leaves none.
+pub fn sys_sched_setaffinity(pid: usize, cpusetsize: usize, mask: *const usize) -> isize {
+    if cpusetsize < size_of::<usize>() {
+        return -EINVAL;
+    }
+    let cpu_mask = match mm::copy_from_user(current_user_token(), mask) {
+        Ok(cpu_mask) => cpu_mask & online_harts(),
+        Err(_) => return -EFAULT,
+    };
+    if cpu_mask == 0 {
+        return -EINVAL;
+    }
+    let task = match target_process(pid) {
+        Ok((task, _)) => task,
+        Err(errno) => return -errno,
+    };
+    set_task_affinity(&task, cpu_mask);
+    // leave a hart the caller may no longer run on
+    if Arc::ptr_eq(&task, &current_task().unwrap())
+        && !task.acquire_inner_lock().may_run_on(hart_id())
+    {
+        suspend_current_and_run_next();
+    }
+    0
+}
+
+/// Store the harts process `pid`, 0 for the caller, may run on at `mask`,
+/// returning the bytes stored.
+pub fn sys_sched_getaffinity(pid: usize, cpusetsize: usize, mask: *mut usize) -> isize {
+    if cpusetsize < size_of::<usize>() {
+        return -EINVAL;
+    }
+    let task = if pid == 0 {
+        current_task().unwrap()
+    } else {
+        match find_task(pid) {
+            Some(task) => task,
+            None => return -ESRCH,
+        }
+    };
+    let cpu_mask = task.acquire_inner_lock().cpu_mask & online_harts();
+    match mm::copy_to_user(current_user_token(), mask, &cpu_mask) {
+        Ok(()) => size_of::<usize>() as isize,
+        Err(_) => -EFAULT,
+    }
+}
+
 pub fn sys_get_time(time: usize, tz: usize) -> isize {
     let token = current_user_token();
     let mut pas: Vec<*mut usize> = Vec::new();
@@ -46,22 +159,317 @@ pub fn sys_get_time(time: usize, tz: usize) -> isize {
     get_time(pas, tz)
 }
 
-pub fn sys_mmap(start: usize, len: usize, port: usize) -> isize {
-    mmap(start, len, port).unwrap_or(-1)
+pub fn sys_clock_gettime(clock_id: usize, tp: *mut TimeSpec) -> isize {
+    let ts = match clock_gettime(clock_id) {
+        Some(ts) => ts,
+        None => return -EINVAL,
+    };
+    match mm::copy_to_user(current_user_token(), tp, &ts) {
+        Ok(()) => 0,
+        Err(_) => -EFAULT,
+    }
+}
+
+/// Read and, for root, adjust `CLOCK_REALTIME`. No other clock can be
+/// adjusted.
+pub fn sys_clock_adjtime(clock_id: usize, timex: *mut Timex) -> isize {
+    if clock_id != CLOCK_REALTIME {
+        return -EINVAL;
+    }
+    let token = current_user_token();
+    let mut buf = match mm::copy_from_user(token, timex) {
+        Ok(buf) => buf,
+        Err(_) => return -EFAULT,
+    };
+    if buf.modes != 0 && current_task().unwrap().acquire_inner_lock().uid != 0 {
+        return -EPERM;
+    }
+    SOFTWARE_CLOCK.adjtime(&mut buf, time::read());
+    match mm::copy_to_user(token, timex, &buf) {
+        // TIME_OK
+        Ok(()) => 0,
+        Err(_) => -EFAULT,
+    }
+}
+
+/// `adjtimex`, which `ntp_adjtime` is built on.
+pub fn sys_adjtimex(timex: *mut Timex) -> isize {
+    sys_clock_adjtime(CLOCK_REALTIME, timex)
+}
+
+/// Write the live tagged heap allocations as text, one `tag count bytes`
+/// line per tag after a `live bytes` line. Returns the length written.
+pub fn sys_heap_stats(buf: *mut u8, len: usize) -> isize {
+    use core::fmt::Write;
+    let stats = mm::heap_stats();
+    let mut text = String::new();
+    writeln!(text, "live {}", stats.live_bytes).unwrap();
+    for (tag, (count, bytes)) in stats.per_tag.iter() {
+        writeln!(text, "{} {} {}", tag, count, bytes).unwrap();
+    }
+    copy_text_to_user(&text, buf, len)
+}
+
+/// Write the areas of the caller's address space as text, see
+/// `MemorySet::dump`.
+pub fn sys_vmmap(buf: *mut u8, len: usize) -> isize {
+    let text = current_task()
+        .unwrap()
+        .acquire_inner_lock()
+        .memory_set
+        .dump();
+    copy_text_to_user(&text, buf, len)
+}
+
+/// Copy as much of `text` as fits in the `len` bytes at `buf`, returning
+/// the number of bytes copied.
+fn copy_text_to_user(text: &str, buf: *mut u8, len: usize) -> isize {
+    let len = len.min(text.len());
+    let mut buf =
+        match mm::UserBuffer::from_user(current_user_token(), buf, len, mm::AccessType::Write) {
+            Ok(buf) => buf,
+            Err(errno) => return -errno,
+        };
+    let mut written = 0;
+    for chunk in buf.chunks_mut() {
+        chunk.copy_from_slice(&text.as_bytes()[written..written + chunk.len()]);
+        written += chunk.len();
+    }
+    written as isize
+}
+
+/// Write the system's memory usage and the resident pages of the caller.
+pub fn sys_meminfo(info: *mut MemStats) -> isize {
+    let mut stats = mm::stats();
+    stats.resident_pages = current_task()
+        .unwrap()
+        .acquire_inner_lock()
+        .memory_set
+        .resident_set_size();
+    match mm::copy_to_user(current_user_token(), info, &stats) {
+        Ok(()) => 0,
+        Err(_) => -EFAULT,
+    }
+}
+
+/// `who` of `sys_getrusage`
+const RUSAGE_SELF: isize = 0;
+const RUSAGE_CHILDREN: isize = -1;
+
+/// `struct rusage` of Linux, of which only the CPU time is kept.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct RUsage {
+    pub utime: TimeVal,
+    pub stime: TimeVal,
+    /// maxrss and the other counters, all 0
+    pub counters: [isize; 14],
+}
+
+/// The CPU time of the caller, or of its children it waited for and
+/// theirs. Ticks are only charged in user mode, so all of it is user time.
+pub fn sys_getrusage(who: isize, usage: *mut RUsage) -> isize {
+    let task = current_task().unwrap();
+    let inner = task.acquire_inner_lock();
+    let ticks = match who {
+        RUSAGE_SELF => inner.ticks,
+        RUSAGE_CHILDREN => inner.children_ticks,
+        _ => return -EINVAL,
+    };
+    let token = inner.memory_set.token();
+    // the page may have to be faulted in
+    drop(inner);
+    let usage_value = RUsage {
+        utime: TimeVal::from_ticks(ticks),
+        stime: TimeVal::new(),
+        counters: [0; 14],
+    };
+    match mm::copy_to_user(token, usage, &usage_value) {
+        Ok(()) => 0,
+        Err(_) => -EFAULT,
+    }
+}
+
+/// Set the memory limit on `resource` of process `pid`, the caller for 0,
+/// to `*new_limit` and return the old one in `*old_limit`, either pointer
+/// null to skip it. Other processes must have the caller's uid, and only
+/// root raises the hard limit.
+pub fn sys_prlimit(
+    pid: usize,
+    resource: usize,
+    new_limit: *const RLimit,
+    old_limit: *mut RLimit,
+) -> isize {
+    let token = current_user_token();
+    // read before taking any lock, it may fault pages of the caller in
+    let new_limit = if new_limit.is_null() {
+        None
+    } else {
+        match mm::copy_from_user(token, new_limit) {
+            Ok(limit) => Some(limit),
+            Err(_) => return -EFAULT,
+        }
+    };
+    let current = current_task().unwrap();
+    let uid = current.acquire_inner_lock().uid;
+    let task = if pid == 0 {
+        current
+    } else {
+        match find_task(pid) {
+            Some(task) => task,
+            None => return -ESRCH,
+        }
+    };
+    let old = {
+        let mut inner = task.acquire_inner_lock();
+        if inner.uid != uid && uid != 0 {
+            return -EPERM;
+        }
+        let mut limits = inner.memory_set.limits();
+        let old = match limits.get(resource) {
+            Some(old) => old,
+            None => return -EINVAL,
+        };
+        if let Some(new_limit) = new_limit {
+            if let Err(errno) = limits.set(resource, new_limit, uid == 0) {
+                return -errno;
+            }
+            inner.memory_set.set_limits(limits);
+        }
+        old
+    };
+    if !old_limit.is_null() && mm::copy_to_user(token, old_limit, &old).is_err() {
+        return -EFAULT;
+    }
+    0
+}
+
+pub fn sys_mmap(
+    start: usize,
+    len: usize,
+    port: usize,
+    flags: usize,
+    fd: usize,
+    offset: usize,
+) -> isize {
+    match MmapFlags::from_bits(flags) {
+        Some(flags) if flags.contains(MmapFlags::ANONYMOUS) => {
+            mapping_result(mmap(start, len, port, flags))
+        }
+        Some(flags) => mapping_result(mmap_file(start, len, port, flags, fd, offset)),
+        None => -1,
+    }
+}
+
+/// The negated errno of a failed mapping call, -1 for the failures that
+/// carry none.
+fn mapping_result(result: Result<isize, isize>) -> isize {
+    match result {
+        Ok(ret) => ret,
+        Err(errno) if errno > 0 => -errno,
+        Err(_) => -1,
+    }
+}
+
+pub fn sys_brk(addr: usize) -> isize {
+    mapping_result(brk(addr))
 }
 
 pub fn sys_munmap(start: usize, len: usize) -> isize {
-    munmap(start, len).unwrap_or(-1)
+    mapping_result(munmap(start, len))
+}
+
+pub fn sys_mprotect(start: usize, len: usize, prot: usize) -> isize {
+    mapping_result(mprotect(start, len, prot))
+}
+
+pub fn sys_mlock(start: usize, len: usize) -> isize {
+    mapping_result(mlock(start, len))
+}
+
+pub fn sys_munlock(start: usize, len: usize) -> isize {
+    munlock(start, len).unwrap_or(-1)
+}
+
+pub fn sys_madvise(start: usize, len: usize, advice: usize) -> isize {
+    mapping_result(madvise(start, len, advice))
+}
+
+pub fn sys_mremap(old_start: usize, old_len: usize, new_len: usize) -> isize {
+    mapping_result(mremap(old_start, old_len, new_len))
+}
+
+pub fn sys_shm_create(len: usize) -> isize {
+    mm::shm_create(len).map_or(-1, |id| id as isize)
+}
+
+pub fn sys_shm_attach(id: usize, start: usize, prot: usize) -> isize {
+    shm_attach(id, start, prot).unwrap_or(-1)
+}
+
+pub fn sys_shm_detach(start: usize) -> isize {
+    shm_detach(start).unwrap_or(-1)
 }
 
 pub fn sys_getpid() -> isize {
     current_task().unwrap().pid.0 as isize
 }
 
+pub fn sys_getppid() -> isize {
+    current_task()
+        .unwrap()
+        .acquire_inner_lock()
+        .parent
+        .as_ref()
+        .and_then(|parent| parent.upgrade())
+        .map_or(0, |parent| parent.getpid() as isize)
+}
+
+pub fn sys_getuid() -> isize {
+    current_task().unwrap().acquire_inner_lock().uid as isize
+}
+
+/// There is no setuid binary, so the effective uid is always the real one.
+pub fn sys_geteuid() -> isize {
+    sys_getuid()
+}
+
+pub fn sys_getgid() -> isize {
+    current_task().unwrap().acquire_inner_lock().gid as isize
+}
+
+pub fn sys_getegid() -> isize {
+    sys_getgid()
+}
+
+/// Only root may change its identity.
+pub fn sys_setuid(uid: u32) -> isize {
+    let task = current_task().unwrap();
+    let mut inner = task.acquire_inner_lock();
+    if inner.uid != 0 {
+        return -EPERM;
+    }
+    inner.uid = uid;
+    0
+}
+
+pub fn sys_setgid(gid: u32) -> isize {
+    let task = current_task().unwrap();
+    let mut inner = task.acquire_inner_lock();
+    if inner.uid != 0 {
+        return -EPERM;
+    }
+    inner.gid = gid;
+    0
+}
+
 pub fn sys_fork() -> isize {
     debug!("Fork start");
     let current_task = current_task().unwrap();
-    let new_task = current_task.fork();
+    let new_task = match current_task.fork() {
+        Ok(new_task) => new_task,
+        Err(_) => return -1,
+    };
     let new_pid = new_task.pid.0;
     // modify trap context of new_task, because it returns immediately after switching
     let trap_cx = new_task.acquire_inner_lock().get_trap_cx();
@@ -74,14 +482,49 @@ pub fn sys_fork() -> isize {
     new_pid as isize
 }
 
+/// Save the address space and registers of the caller to the file open as
+/// `fd`. Returns 0, and 1 again once `sys_restore` resumes from it.
+pub fn sys_checkpoint(fd: usize) -> isize {
+    let task = current_task().unwrap();
+    let inner = task.acquire_inner_lock();
+    match inner.checkpoint(fd) {
+        Ok(()) => 0,
+        Err(errno) => -errno,
+    }
+}
+
+/// Resume from the checkpoint in the file open as `fd` in place of the
+/// caller, keeping its pid and files. Does not return on success.
+pub fn sys_restore(fd: usize) -> isize {
+    match current_task().unwrap().restore(fd) {
+        Ok(()) => 0,
+        Err(errno) => -errno,
+    }
+}
+
 pub fn sys_exec(path: *const u8) -> isize {
     let token = current_user_token();
-    let path = mm::translated_str(token, path);
+    let path = match mm::strncpy_from_user(token, path, mm::PATH_MAX) {
+        Ok(path) => path,
+        Err(errno) => return -errno,
+    };
     debug!("EXEC {}", &path);
     if let Some(data) = get_app_data_by_name(path.as_str()) {
         let task = current_task().unwrap();
-        task.exec(data);
-        0
+        match task.exec(path.as_str(), data) {
+            Ok(()) => 0,
+            Err(ENOMEM) => {
+                // the old address space has been torn down, nothing to return to
+                drop(task);
+                warn!("exec {} failed!", path);
+                exit_current_and_run_next(-1);
+                0
+            }
+            Err(errno) => {
+                warn!("exec {} failed!", path);
+                -errno
+            }
+        }
     } else {
         warn!("exec failed!");
         -1
@@ -117,10 +560,22 @@ pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
         assert_eq!(Arc::strong_count(&child), 1);
         let found_pid = child.getpid();
         // ++++ temporarily hold child lock
-        let exit_code = child.acquire_inner_lock().exit_code;
+        let child_inner = child.acquire_inner_lock();
+        let exit_code = child_inner.exit_code;
+        inner.children_ticks += child_inner.ticks + child_inner.children_ticks;
+        drop(child_inner);
         // ++++ release child PCB lock
-        *mm::translated_refmut(inner.memory_set.token(), exit_code_ptr) = exit_code;
-        found_pid as isize
+        let token = inner.memory_set.token();
+        // ---- release current PCB lock, the page may have to be faulted in
+        drop(inner);
+        let exit_code_ptr = mm::UserPtr::new(token, exit_code_ptr);
+        if exit_code_ptr.is_null() {
+            return found_pid as isize;
+        }
+        match exit_code_ptr.write(exit_code) {
+            Ok(()) => found_pid as isize,
+            Err(errno) => -errno,
+        }
     } else {
         -2
     }
@@ -1,33 +1,305 @@
+use super::processor::current_tasks;
+use super::sched::{
+    new_scheduler, PriorityScheduler, SchedPolicy, Scheduler, StrideScheduler, BIG_STRIDE,
+    NICE_MAX, NICE_MIN, TIME_SLICE,
+};
 use super::{current_task, TaskControlBlock};
-use alloc::collections::VecDeque;
+use crate::errno::ESRCH;
+use crate::loader::get_app_data_by_name;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// A queued or running task, as recorded by `TaskManager::snapshot_state`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SchedEntry {
+    pub pid: usize,
+    pub priority: isize,
+    pub nice: isize,
+    pub pass: u64,
+}
+
+impl SchedEntry {
+    fn of(task: &Arc<TaskControlBlock>) -> Self {
+        let inner = task.acquire_inner_lock();
+        Self {
+            pid: task.getpid(),
+            priority: inner.priority,
+            nice: inner.nice,
+            pass: inner.pass,
+        }
+    }
+}
+
+/// Scheduler state by pid, so it holds no references to the tasks.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SchedSnapshot {
+    /// The ready queue, front first
+    pub ready: Vec<SchedEntry>,
+    /// What each hart was running, not part of any queue
+    pub running: Vec<Option<SchedEntry>>,
+}
 
 pub struct TaskManager {
-    ready_queue: VecDeque<Arc<TaskControlBlock>>,
+    ready_queue: Box<dyn Scheduler>,
+    make_queue: fn() -> Box<dyn Scheduler>,
 }
 
-/// A simple FIFO scheduler.
+/// Ready tasks in the order of a `Scheduler` policy.
 impl TaskManager {
+    /// A queue of the policy the kernel is built with.
     pub fn new() -> Self {
+        Self::with_scheduler(new_scheduler)
+    }
+    pub fn with_scheduler(make_queue: fn() -> Box<dyn Scheduler>) -> Self {
         Self {
-            ready_queue: VecDeque::new(),
+            ready_queue: make_queue(),
+            make_queue,
         }
     }
+    /// Number of ready tasks.
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
     pub fn add(&mut self, task: Arc<TaskControlBlock>) {
-        self.ready_queue.push_back(task);
+        self.ready_queue.add(task);
     }
     pub fn remove(&mut self, task: &Arc<TaskControlBlock>) {
-        for (idx, task_item) in self.ready_queue.iter().enumerate() {
-            if *task_item == *task {
-                self.ready_queue.remove(idx);
-                break;
-            }
+        self.ready_queue.remove(task);
+    }
+    /// Give `task` the nice value `nice`, moving it to that level of the
+    /// queue if it is queued.
+    pub fn renice(&mut self, task: &Arc<TaskControlBlock>, nice: isize) {
+        let queued = self.ready_queue.remove(task);
+        task.acquire_inner_lock().nice = nice;
+        if queued {
+            self.ready_queue.add(task.clone());
+        }
+    }
+    /// Whether `task` running on the hart of this queue should give way at
+    /// a tick: some queued task ranks no higher. Tasks that rank higher wait
+    /// until it blocks or exits, or falls behind them.
+    pub fn should_preempt(&self, task: &Arc<TaskControlBlock>) -> bool {
+        let rank = self.ready_queue.rank(task);
+        self.ready_queue.peek().map_or(false, |best| best <= rank)
+    }
+    /// Charge a tick to `task` running on the hart of this queue. Whether it
+    /// should give way now: its policy says it may, and `should_preempt`.
+    pub fn tick(&mut self, task: &Arc<TaskControlBlock>) -> bool {
+        self.ready_queue.tick(task) && self.should_preempt(task)
+    }
+    /// The next task that may run on `cpu_id`, see `cpu_mask`. Tasks that
+    /// may not are passed over and keep their place.
+    pub fn fetch(&mut self, cpu_id: usize) -> Option<Arc<TaskControlBlock>> {
+        let task = self
+            .ready_queue
+            .iter()
+            .find(|task| task.acquire_inner_lock().may_run_on(cpu_id))
+            .cloned()?;
+        Some(take(&mut self.ready_queue, task))
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<TaskControlBlock>> {
+        self.ready_queue.iter()
+    }
+    /// Record which tasks are queued, by pid, in queue order.
+    pub fn snapshot_state(&self) -> SchedSnapshot {
+        SchedSnapshot {
+            ready: self.ready_queue.iter().map(SchedEntry::of).collect(),
+            running: current_tasks()
+                .iter()
+                .map(|task| task.as_ref().map(SchedEntry::of))
+                .collect(),
+        }
+    }
+    /// Replace the queue with that of `snap`, looking tasks up in `tasks`
+    /// and giving them back their priorities, nice values and passes.
+    /// Running tasks are left alone. Nothing changes if a pid is not in
+    /// `tasks` (ESRCH).
+    pub fn restore_state(
+        &mut self,
+        snap: &SchedSnapshot,
+        tasks: &BTreeMap<usize, Arc<TaskControlBlock>>,
+    ) -> Result<(), isize> {
+        let ready = snap
+            .ready
+            .iter()
+            .map(|entry| {
+                let task = tasks.get(&entry.pid).ok_or(ESRCH)?;
+                Ok((task.clone(), *entry))
+            })
+            .collect::<Result<Vec<_>, isize>>()?;
+        let mut requeued = (self.make_queue)();
+        for (task, entry) in ready {
+            let mut inner = task.acquire_inner_lock();
+            inner.priority = entry.priority;
+            inner.nice = entry.nice;
+            inner.pass = entry.pass;
+            drop(inner);
+            requeued.add(task);
+        }
+        self.ready_queue = requeued;
+        Ok(())
+    }
+    /// Count ready tasks at each priority level.
+    pub fn priority_histogram(&self) -> BTreeMap<usize, usize> {
+        let mut histogram = BTreeMap::new();
+        for task in self.iter() {
+            let priority = task.acquire_inner_lock().priority as usize;
+            *histogram.entry(priority).or_insert(0) += 1;
         }
+        histogram
+    }
+}
+
+/// Take `task` out of `queue`, by `fetch` if it is up front so that the
+/// queue sees it run.
+fn take(queue: &mut Box<dyn Scheduler>, task: Arc<TaskControlBlock>) -> Arc<TaskControlBlock> {
+    if queue.iter().next() == Some(&task) {
+        queue.fetch().unwrap()
+    } else {
+        queue.remove(&task);
+        task
     }
-    pub fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
-        // May need to concern affinity
-        self.ready_queue.pop_front()
+}
+
+#[allow(unused)]
+pub fn priority_histogram_test() {
+    let mut manager = TaskManager::new();
+    let elf_data = get_app_data_by_name("initproc").unwrap();
+    for &priority in [1, 1, 5].iter() {
+        let task = TaskControlBlock::new("initproc", elf_data);
+        task.acquire_inner_lock().priority = priority;
+        manager.add(task);
     }
+    let histogram = manager.priority_histogram();
+    assert_eq!(histogram.len(), 2);
+    assert_eq!(histogram.get(&1), Some(&2));
+    assert_eq!(histogram.get(&5), Some(&1));
+    debug!("priority_histogram_test passed!");
+}
+
+#[allow(unused)]
+pub fn nice_levels_test() {
+    let mut manager = TaskManager::with_scheduler(|| Box::new(PriorityScheduler::default()));
+    let elf_data = get_app_data_by_name("initproc").unwrap();
+    let probe = TaskControlBlock::new("initproc", elf_data);
+    let tasks: Vec<Arc<TaskControlBlock>> = [0, -5, 10, 0]
+        .iter()
+        .map(|&nice| {
+            let task = TaskControlBlock::new("initproc", elf_data);
+            task.acquire_inner_lock().nice = nice;
+            manager.add(task.clone());
+            task
+        })
+        .collect();
+    // the most favoured level first, in the order queued within a level
+    assert!(manager.should_preempt(&probe));
+    probe.acquire_inner_lock().nice = -10;
+    assert!(!manager.should_preempt(&probe));
+    for &expected in [1, 0, 3, 2].iter() {
+        assert!(Arc::ptr_eq(&manager.fetch(0).unwrap(), &tasks[expected]));
+    }
+    probe.acquire_inner_lock().nice = NICE_MAX;
+    assert!(!manager.should_preempt(&probe));
+    // a reniced task moves to its new level
+    manager.add(tasks[0].clone());
+    manager.add(tasks[2].clone());
+    manager.renice(&tasks[2], NICE_MIN);
+    assert_eq!(tasks[2].acquire_inner_lock().nice, NICE_MIN);
+    assert!(Arc::ptr_eq(&manager.fetch(1).unwrap(), &tasks[2]));
+    assert!(Arc::ptr_eq(&manager.fetch(1).unwrap(), &tasks[0]));
+    assert!(manager.fetch(1).is_none());
+    debug!("nice_levels_test passed!");
+}
+
+#[allow(unused)]
+pub fn stride_test() {
+    let mut manager = TaskManager::with_scheduler(|| Box::new(StrideScheduler::default()));
+    let elf_data = get_app_data_by_name("initproc").unwrap();
+    let tasks: Vec<Arc<TaskControlBlock>> = [1, 2, 4]
+        .iter()
+        .map(|&priority| {
+            let task = TaskControlBlock::new("initproc", elf_data);
+            task.acquire_inner_lock().priority = priority;
+            manager.add(task.clone());
+            task
+        })
+        .collect();
+    // run each task fetched for a tick, as the timer would charge it
+    let mut ticks = [0; 3];
+    for _ in 0..70 {
+        let task = manager.fetch(0).unwrap();
+        let mut inner = task.acquire_inner_lock();
+        inner.pass += BIG_STRIDE / inner.priority as u64;
+        drop(inner);
+        ticks[tasks
+            .iter()
+            .position(|task_item| *task_item == task)
+            .unwrap()] += 1;
+        manager.add(task);
+    }
+    assert_eq!(ticks, [10, 20, 40]);
+    // a task that slept does not get to catch up on what it missed
+    let sleeper = tasks[0].clone();
+    manager.remove(&sleeper);
+    for _ in 0..70 {
+        let task = manager.fetch(0).unwrap();
+        task.acquire_inner_lock().pass += BIG_STRIDE;
+        manager.add(task);
+    }
+    let slept_pass = sleeper.acquire_inner_lock().pass;
+    manager.add(sleeper.clone());
+    let pass = sleeper.acquire_inner_lock().pass;
+    assert!(pass > slept_pass);
+    assert!(tasks[1..]
+        .iter()
+        .all(|task| task.acquire_inner_lock().pass >= pass));
+    assert!(Arc::ptr_eq(&manager.fetch(0).unwrap(), &sleeper));
+    debug!("stride_test passed!");
+}
+
+#[allow(unused)]
+pub fn sched_policies_test() {
+    assert_eq!(SchedPolicy::from_name("rr"), Some(SchedPolicy::RoundRobin));
+    assert_eq!(SchedPolicy::from_name("lottery"), None);
+    let elf_data = get_app_data_by_name("initproc").unwrap();
+    let running = TaskControlBlock::new("initproc", elf_data);
+    let waiting = TaskControlBlock::new("initproc", elf_data);
+    // first in first out, running until it blocks
+    let mut manager = TaskManager::with_scheduler(|| SchedPolicy::Fifo.new_scheduler());
+    manager.add(waiting.clone());
+    assert!((0..100).all(|_| !manager.tick(&running)));
+    // a slice each, then the next in line
+    let mut manager = TaskManager::with_scheduler(|| SchedPolicy::RoundRobin.new_scheduler());
+    assert!((0..TIME_SLICE).all(|_| !manager.tick(&running)));
+    manager.add(waiting.clone());
+    assert!((1..TIME_SLICE).all(|_| !manager.tick(&running)));
+    assert!(manager.tick(&running));
+    // a new slice for whichever task runs next
+    assert!(Arc::ptr_eq(&manager.fetch(0).unwrap(), &waiting));
+    manager.add(running.clone());
+    assert!((1..TIME_SLICE).all(|_| !manager.tick(&waiting)));
+    assert!(manager.tick(&waiting));
+    debug!("sched_policies_test passed!");
+}
+
+#[allow(unused)]
+pub fn affinity_fetch_test() {
+    let mut manager = TaskManager::new();
+    let elf_data = get_app_data_by_name("initproc").unwrap();
+    let first = TaskControlBlock::new("initproc", elf_data);
+    let second = TaskControlBlock::new("initproc", elf_data);
+    first.acquire_inner_lock().cpu_mask = 1 << 0;
+    manager.add(first.clone());
+    manager.add(second.clone());
+    // passed over by a hart it may not run on, keeping its place
+    assert!(Arc::ptr_eq(&manager.fetch(1).unwrap(), &second));
+    assert!(manager.fetch(1).is_none());
+    manager.add(second.clone());
+    assert!(Arc::ptr_eq(&manager.fetch(0).unwrap(), &first));
+    assert!(Arc::ptr_eq(&manager.fetch(0).unwrap(), &second));
+    debug!("affinity_fetch_test passed!");
 }
 
 // lazy_static! {
@@ -49,3 +321,37 @@ impl TaskManager {
 //     }
 //     TASK_MANAGER.lock().find(pid)
 // }
+
+#[allow(unused)]
+pub fn sched_snapshot_test() {
+    let mut manager = TaskManager::new();
+    let elf_data = get_app_data_by_name("initproc").unwrap();
+    let mut tasks = BTreeMap::new();
+    for &priority in [3, 1, 2].iter() {
+        let task = TaskControlBlock::new("initproc", elf_data);
+        task.acquire_inner_lock().priority = priority;
+        tasks.insert(task.getpid(), task.clone());
+        manager.add(task);
+    }
+    let snap = manager.snapshot_state();
+    assert_eq!(snap.ready.len(), 3);
+    let order = |manager: &mut TaskManager| {
+        let mut pids = Vec::new();
+        while let Some(task) = manager.fetch(0) {
+            pids.push((task.getpid(), task.acquire_inner_lock().priority));
+        }
+        pids
+    };
+    let expected = order(&mut manager);
+    assert!(manager.snapshot_state().ready.is_empty());
+    let changed = tasks.values().nth(1).unwrap().clone();
+    changed.acquire_inner_lock().priority = 9;
+    manager.restore_state(&snap, &tasks).unwrap();
+    assert_eq!(manager.snapshot_state(), snap);
+    assert_eq!(order(&mut manager), expected);
+    // a snapshot naming a task that is gone is refused as a whole
+    tasks.remove(&changed.getpid());
+    assert_eq!(manager.restore_state(&snap, &tasks).err(), Some(ESRCH));
+    assert!(manager.iter().next().is_none());
+    debug!("sched_snapshot_test passed!");
+}
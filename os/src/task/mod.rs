@@ -2,29 +2,43 @@ mod context;
 mod manager;
 mod pid;
 mod pool;
+mod preempt;
 mod processor;
+mod sched;
 mod switch;
 mod task;
+mod trace;
 
+use crate::config::CPU_NUM;
 use crate::loader::get_app_data_by_name;
+use crate::mm::VirtAddr;
+use crate::perf::{count_event, PERF_COUNT_SW_CONTEXT_SWITCHES};
 use alloc::sync::Arc;
 use lazy_static::*;
 
+use pool::TASK_POOL;
 use switch::__switch;
-use task::{TaskControlBlock, TaskStatus};
 
 pub use context::TaskContext;
-pub use pid::{find_task, pid_alloc, KernelStack, PidHandle};
-pub use pool::{add_task, fetch_task};
+pub use pid::{
+    find_task, kernel_stack_guard_owner, kernel_stack_position, pid_alloc, task_pids, KernelStack,
+    PidHandle,
+};
+pub use pool::{add_task, fetch_task, scheduler_tick, set_task_affinity, set_task_nice, wake_task};
+pub use preempt::preempt_tick;
 pub use processor::{
-    current_task, current_trap_cx, current_user_token, hart_id, mmap, munmap, run_tasks, schedule,
-    set_current_priority, take_current_task,
+    brk, current_on_hart, current_task, current_trap_cx, current_user_token, hart_id, madvise,
+    mlock, mmap, mmap_file, mprotect, mremap, munlock, munmap, run_tasks, schedule,
+    set_current_priority, shm_attach, shm_detach, take_current_task,
 };
+pub use sched::{NICE_MAX, NICE_MIN};
+pub use task::{BlockReason, TaskControlBlock, TaskStatus};
 
 pub fn suspend_current_and_run_next() {
     // There must be an application running.
     let task = current_task().unwrap();
     let task_inner = task.acquire_inner_lock();
+    count_event(&task_inner.perf_counters, PERF_COUNT_SW_CONTEXT_SWITCHES);
     let task_cx_ptr2 = task_inner.get_task_cx_ptr2();
     drop(task_inner);
 
@@ -32,6 +46,26 @@ pub fn suspend_current_and_run_next() {
     schedule(task_cx_ptr2);
 }
 
+/// Switch away from the current task until it is woken by `wake_task`.
+/// The caller must have marked it Blocked while holding whatever lock the
+/// waker takes, otherwise the wakeup may come first and be lost. If it
+/// came in between already, the task does not switch out at all.
+#[allow(dead_code)]
+pub fn block_current_and_run_next() {
+    let task = current_task().unwrap();
+    let mut task_inner = task.acquire_inner_lock();
+    if !task_inner.must_park() {
+        return;
+    }
+    count_event(&task_inner.perf_counters, PERF_COUNT_SW_CONTEXT_SWITCHES);
+    let task_cx_ptr2 = task_inner.get_task_cx_ptr2();
+    drop(task_inner);
+    drop(task);
+
+    // jump to scheduling cycle
+    schedule(task_cx_ptr2);
+}
+
 pub fn exit_current_and_run_next(exit_code: i32) {
     // take from Processor
     let task = take_current_task().unwrap();
@@ -82,12 +116,86 @@ pub fn exit_current_and_run_next(exit_code: i32) {
     // }
 }
 
+/// Print the task on every hart, the ready queue and the memory map of the
+/// task on this hart. Called from the panic handler, so locks are only tried
+/// and nothing is allocated.
+pub fn dump_state() {
+    for hart in 0..CPU_NUM {
+        match current_on_hart(hart) {
+            Some(task) => match task.try_acquire_inner_lock() {
+                Some(inner) => {
+                    println_unlocked!("hart {}: pid {} {}", hart, task.getpid(), inner.name)
+                }
+                None => println_unlocked!("hart {}: pid {} (locked)", hart, task.getpid()),
+            },
+            None => println_unlocked!("hart {}: idle", hart),
+        }
+    }
+    match TASK_POOL.try_lock() {
+        Some(pool) => {
+            for (hart, scheduler) in pool.schedulers.iter().enumerate() {
+                println_unlocked!("ready queue of hart {}:", hart);
+                for task in scheduler.iter() {
+                    println_unlocked!("  pid {}", task.getpid());
+                }
+            }
+            println_unlocked!("blocked:");
+            for task in pool.sleeping_tasks.iter() {
+                match task.try_acquire_inner_lock() {
+                    Some(inner) => {
+                        println_unlocked!("  pid {} on {:?}", task.getpid(), inner.block_reason)
+                    }
+                    None => println_unlocked!("  pid {} (locked)", task.getpid()),
+                }
+            }
+        }
+        None => println_unlocked!("ready queue: (locked)"),
+    }
+    if let Some(task) = current_task() {
+        match task.try_acquire_inner_lock() {
+            Some(inner) => {
+                println_unlocked!("memory map of pid {}:", task.getpid());
+                for area in inner.memory_set.iter_areas() {
+                    let range = area.vpn_range();
+                    println_unlocked!(
+                        "  {:#x}-{:#x} {:?} {:?}",
+                        VirtAddr::from(range.get_start()).0,
+                        VirtAddr::from(range.get_end()).0,
+                        area.map_perm(),
+                        area.map_type()
+                    );
+                }
+            }
+            None => println_unlocked!("memory map of pid {}: (locked)", task.getpid()),
+        }
+    }
+}
+
 lazy_static! {
     pub static ref INITPROC: Arc<TaskControlBlock> =
-        TaskControlBlock::new(get_app_data_by_name("initproc").unwrap());
+        TaskControlBlock::new("initproc", get_app_data_by_name("initproc").unwrap());
 }
 
 pub fn add_initproc() {
     debug!("add_initproc");
     add_task(INITPROC.clone());
 }
+
+/// Run the scheduling tests. They create tasks of their own, so they run
+/// once initproc has taken the first pid.
+#[cfg(debug_assertions)]
+pub fn self_test() {
+    pid::kernel_stack_test();
+    preempt::preempt_test();
+    trace::sched_trace_test();
+    manager::priority_histogram_test();
+    manager::nice_levels_test();
+    manager::stride_test();
+    manager::sched_policies_test();
+    manager::affinity_fetch_test();
+    manager::sched_snapshot_test();
+    pool::blocked_report_test();
+    pool::wake_batch_test();
+    pool::early_wake_test();
+    pool::work_stealing_test();
+}
@@ -1,5 +1,5 @@
 use crate::config::{KERNEL_STACK_SIZE, PAGE_SIZE, TRAMPOLINE};
-use crate::mm::{MapPermission, VirtAddr, KERNEL_SPACE};
+use crate::mm::{frame_alloc_contiguous, MapPermission, VirtAddr, KERNEL_SPACE};
 use alloc::collections::BTreeMap;
 use alloc::sync::{Arc, Weak};
 use alloc::vec::Vec;
@@ -90,6 +90,16 @@ pub fn find_task(pid: usize) -> Option<Arc<TaskControlBlock>> {
         .and_then(|weak| weak.upgrade())
 }
 
+/// Pids of all live tasks, in ascending order.
+pub fn task_pids() -> Vec<usize> {
+    PID_ALLOCATOR.lock().task_table.keys().copied().collect()
+}
+
+// KERNEL_STACK_SIZE must be a whole number of pages, and a power of two
+// so the stack is a single block of the frame allocator
+const _: [(); 0] = [(); KERNEL_STACK_SIZE % PAGE_SIZE];
+const _: [(); 0] = [(); KERNEL_STACK_SIZE & (KERNEL_STACK_SIZE - 1)];
+
 /// Return (bottom, top) of a kernel stack in kernel space.
 pub fn kernel_stack_position(app_id: usize) -> (usize, usize) {
     let top = TRAMPOLINE - app_id * (KERNEL_STACK_SIZE + PAGE_SIZE);
@@ -103,15 +113,39 @@ pub struct KernelStack {
 }
 
 impl KernelStack {
-    pub fn new(pid_handle: &PidHandle) -> Self {
+    /// Fails with ENOMEM.
+    pub fn new(pid_handle: &PidHandle) -> Result<Self, isize> {
         let pid = pid_handle.0;
         let (kernel_stack_bottom, kernel_stack_top) = kernel_stack_position(pid);
-        KERNEL_SPACE.lock().insert_framed_area(
-            kernel_stack_bottom.into(),
-            kernel_stack_top.into(),
-            MapPermission::R | MapPermission::W,
-        );
-        KernelStack { pid: pid_handle.0 }
+        let permission = MapPermission::R | MapPermission::W;
+        // Prefer one physical run for the whole stack. A stack is far smaller
+        // than a megapage, so it is still mapped page by page. The guard page
+        // below it is left unmapped either way.
+        let order = (KERNEL_STACK_SIZE / PAGE_SIZE).trailing_zeros() as usize;
+        let mut kernel_space = KERNEL_SPACE.lock();
+        kernel_space.reserve_range(kernel_stack_bottom - PAGE_SIZE, PAGE_SIZE)?;
+        let mapped = match frame_alloc_contiguous(order) {
+            Some(frames) => {
+                kernel_space.insert_framed_area_with_frames(
+                    kernel_stack_bottom.into(),
+                    kernel_stack_top.into(),
+                    permission,
+                    frames,
+                );
+                Ok(())
+            }
+            None => kernel_space.insert_framed_area(
+                kernel_stack_bottom.into(),
+                kernel_stack_top.into(),
+                permission,
+            ),
+        };
+        if let Err(err) = mapped {
+            let guard_va: VirtAddr = (kernel_stack_bottom - PAGE_SIZE).into();
+            kernel_space.remove_area_with_start_vpn(guard_va.into());
+            return Err(err);
+        }
+        Ok(KernelStack { pid: pid_handle.0 })
     }
     pub fn push_on_top<T>(&self, value: T) -> *mut T
     where
@@ -134,8 +168,65 @@ impl Drop for KernelStack {
     fn drop(&mut self) {
         let (kernel_stack_bottom, _) = kernel_stack_position(self.pid);
         let kernel_stack_bottom_va: VirtAddr = kernel_stack_bottom.into();
-        KERNEL_SPACE
-            .lock()
-            .remove_area_with_start_vpn(kernel_stack_bottom_va.into());
+        let guard_va: VirtAddr = (kernel_stack_bottom - PAGE_SIZE).into();
+        let mut kernel_space = KERNEL_SPACE.lock();
+        kernel_space.remove_area_with_start_vpn(kernel_stack_bottom_va.into());
+        kernel_space.remove_area_with_start_vpn(guard_va.into());
     }
 }
+
+/// The pid whose kernel stack has its guard page at `va`. A stack that
+/// runs off its bottom faults there.
+///
+/// Called from the kernel trap handler, which may have interrupted a holder
+/// of the kernel space lock; the guard is then taken as still reserved.
+pub fn kernel_stack_guard_owner(va: usize) -> Option<usize> {
+    if va >= TRAMPOLINE {
+        return None;
+    }
+    let pid = (TRAMPOLINE - 1 - va) / (KERNEL_STACK_SIZE + PAGE_SIZE);
+    let (kernel_stack_bottom, _) = kernel_stack_position(pid);
+    if va < kernel_stack_bottom - PAGE_SIZE || va >= kernel_stack_bottom {
+        return None;
+    }
+    let reserved = KERNEL_SPACE.try_lock().map_or(true, |kernel_space| {
+        kernel_space.is_reserved(VirtAddr::from(va).floor())
+    });
+    if reserved {
+        Some(pid)
+    } else {
+        None
+    }
+}
+
+#[allow(unused)]
+pub fn kernel_stack_test() {
+    let pid = pid_alloc();
+    // no task owns the pid, the entry only lets it be handed back
+    PID_ALLOCATOR.lock().task_table.insert(pid.0, Weak::new());
+    let kernel_stack = KernelStack::new(&pid).unwrap();
+    let (bottom, top) = kernel_stack_position(pid.0);
+    let kernel_space = KERNEL_SPACE.lock();
+    let ppns: Vec<usize> = (bottom..top)
+        .step_by(PAGE_SIZE)
+        .map(|va| {
+            let pte = kernel_space.translate(VirtAddr::from(va).floor()).unwrap();
+            assert!(pte.is_valid() && pte.writable());
+            pte.ppn().0
+        })
+        .collect();
+    assert!(ppns.windows(2).all(|pair| pair[1] == pair[0] + 1));
+    // the guard page stays unmapped
+    assert!(kernel_space
+        .translate(VirtAddr::from(bottom - PAGE_SIZE).floor())
+        .map_or(true, |pte| !pte.is_valid()));
+    drop(kernel_space);
+    assert_eq!(kernel_stack_guard_owner(bottom - 8), Some(pid.0));
+    assert_eq!(kernel_stack_guard_owner(bottom - PAGE_SIZE), Some(pid.0));
+    assert_eq!(kernel_stack_guard_owner(bottom), None);
+    assert_eq!(kernel_stack_guard_owner(bottom - PAGE_SIZE - 8), None);
+    drop(kernel_stack);
+    // the guard goes with the stack
+    assert_eq!(kernel_stack_guard_owner(bottom - 8), None);
+    debug!("kernel_stack_test passed!");
+}
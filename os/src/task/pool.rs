@@ -1,11 +1,18 @@
-use alloc::{collections::BTreeSet, sync::Arc};
+use crate::boot::machine;
+use alloc::{collections::BTreeSet, sync::Arc, vec::Vec};
+use core::cmp::Reverse;
 use lazy_static::*;
 use spin::Mutex;
 
-use super::{manager::TaskManager, task::TaskControlBlock};
+use super::preempt::{preempt_disable, preempt_enable};
+use super::processor::{current_task, hart_id};
+use super::trace::{SchedEventKind, SCHED_TRACE};
+use super::{manager::TaskManager, task::TaskControlBlock, BlockReason, TaskStatus};
 
 pub struct TaskPool {
-    pub scheduler: TaskManager,
+    /// The ready tasks of each hart, in the queue of its manager. A task is queued on the hart with the fewest of those it may run on,
+    /// and harts with none steal from the others.
+    pub schedulers: Vec<TaskManager>,
     pub sleeping_tasks: BTreeSet<Arc<TaskControlBlock>>,
 }
 
@@ -16,31 +23,140 @@ lazy_static! {
 impl TaskPool {
     pub fn new() -> Self {
         Self {
-            scheduler: TaskManager::new(),
+            schedulers: (0..machine().harts).map(|_| TaskManager::new()).collect(),
             sleeping_tasks: BTreeSet::new(),
         }
     }
 
+    /// The hart with the fewest ready tasks among those `task` may run on,
+    /// the first of them on a tie.
+    fn least_loaded(&self, task: &Arc<TaskControlBlock>) -> usize {
+        let inner = task.acquire_inner_lock();
+        (0..self.schedulers.len())
+            .filter(|&hart| inner.may_run_on(hart))
+            .min_by_key(|&hart| self.schedulers[hart].len())
+            .unwrap_or(0)
+    }
+
     pub fn add(&mut self, task: Arc<TaskControlBlock>) {
-        self.scheduler.add(task);
+        SCHED_TRACE.record(task.getpid(), SchedEventKind::Add);
+        let hart = self.least_loaded(&task);
+        self.schedulers[hart].add(task);
     }
 
     pub fn remove(&mut self, task: Arc<TaskControlBlock>) {
-        self.scheduler.remove(&task);
+        for scheduler in self.schedulers.iter_mut() {
+            scheduler.remove(&task);
+        }
     }
 
     pub fn wake(&mut self, task: Arc<TaskControlBlock>) {
+        SCHED_TRACE.record(task.getpid(), SchedEventKind::Wake);
         self.sleeping_tasks.remove(&task);
-        self.scheduler.add(task);
+        let hart = self.least_loaded(&task);
+        self.schedulers[hart].add(task);
     }
 
     pub fn sleep(&mut self, task: Arc<TaskControlBlock>) {
-        self.scheduler.remove(&task);
+        SCHED_TRACE.record(task.getpid(), SchedEventKind::Block);
+        self.remove(task.clone());
         self.sleeping_tasks.insert(task);
     }
 
+    /// The next task of this hart, or one stolen from the hart with the
+    /// most ready tasks if this one has none.
     pub fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
-        self.scheduler.fetch()
+        let hart = hart_id();
+        let task = match self.schedulers[hart].fetch(hart) {
+            Some(task) => task,
+            None => self.steal(hart)?,
+        };
+        SCHED_TRACE.record(task.getpid(), SchedEventKind::Fetch);
+        Some(task)
+    }
+
+    /// Only tasks that may run on `hart` are taken, from the busiest hart
+    /// that has one.
+    fn steal(&mut self, hart: usize) -> Option<Arc<TaskControlBlock>> {
+        let mut victims: Vec<usize> = (0..self.schedulers.len())
+            .filter(|&other| other != hart)
+            .collect();
+        victims.sort_by_key(|&other| Reverse(self.schedulers[other].len()));
+        let task = victims
+            .into_iter()
+            .find_map(|victim| self.schedulers[victim].fetch(hart))?;
+        SCHED_TRACE.record(task.getpid(), SchedEventKind::Migrate);
+        Some(task)
+    }
+
+    /// The hart `task` is queued on, if it is ready.
+    fn queued_on(&self, task: &Arc<TaskControlBlock>) -> Option<usize> {
+        (0..self.schedulers.len()).find(|&hart| {
+            self.schedulers[hart]
+                .iter()
+                .any(|task_item| task_item == task)
+        })
+    }
+
+    /// Give `task` the nice value `nice`, requeueing it if it is ready.
+    pub fn renice(&mut self, task: &Arc<TaskControlBlock>, nice: isize) {
+        let hart = self.queued_on(task).unwrap_or(0);
+        self.schedulers[hart].renice(task, nice);
+    }
+
+    /// Let `task` run only on the harts in `cpu_mask`. A ready task moves to
+    /// one of them, a running one when it next switches out.
+    pub fn set_affinity(&mut self, task: &Arc<TaskControlBlock>, cpu_mask: usize) {
+        let queued = self.queued_on(task);
+        if let Some(hart) = queued {
+            self.schedulers[hart].remove(task);
+        }
+        task.acquire_inner_lock().cpu_mask = cpu_mask;
+        if queued.is_some() {
+            let hart = self.least_loaded(task);
+            self.schedulers[hart].add(task.clone());
+        }
+    }
+
+    /// Charge a tick to `task` running on `hart`, see `TaskManager::tick`.
+    pub fn tick(&mut self, hart: usize, task: &Arc<TaskControlBlock>) -> bool {
+        match self.schedulers.get_mut(hart) {
+            Some(scheduler) => scheduler.tick(task),
+            None => false,
+        }
+    }
+
+    /// Wake every task of `tasks` that is still blocked. A task that has not
+    /// switched out yet is only marked Ready. The others are all queued
+    /// before the caller lets go of the pool, so a fetch sees either none or
+    /// all of them.
+    pub fn wake_batch(&mut self, tasks: impl IntoIterator<Item = Arc<TaskControlBlock>>) {
+        for task in tasks {
+            let mut task_inner = task.acquire_inner_lock();
+            if task_inner.task_status != TaskStatus::Blocked {
+                continue;
+            }
+            task_inner.task_status = TaskStatus::Ready;
+            task_inner.block_reason = None;
+            drop(task_inner);
+            if self.sleeping_tasks.contains(&task) {
+                self.wake(task);
+            }
+        }
+    }
+
+    /// Pid and reason of every parked task, by pid.
+    pub fn blocked_report(&self) -> Vec<(usize, BlockReason)> {
+        let mut report: Vec<(usize, BlockReason)> = self
+            .sleeping_tasks
+            .iter()
+            .filter_map(|task| {
+                let reason = task.acquire_inner_lock().block_reason?;
+                Some((task.getpid(), reason))
+            })
+            .collect();
+        report.sort_by_key(|(pid, _)| *pid);
+        report
     }
 }
 
@@ -53,3 +169,163 @@ pub fn add_task(task: Arc<TaskControlBlock>) {
 pub fn fetch_task() -> Option<Arc<TaskControlBlock>> {
     TASK_POOL.lock().fetch()
 }
+
+/// Charge a tick to the task on this hart, to its CPU time and to its
+/// policy. Whether it should be switched out, see `TaskManager::tick`.
+pub fn scheduler_tick() -> bool {
+    match current_task() {
+        Some(task) => {
+            task.acquire_inner_lock().ticks += 1;
+            TASK_POOL.lock().tick(hart_id(), &task)
+        }
+        None => false,
+    }
+}
+
+/// Set the nice value of `task`, requeueing it if it is ready.
+pub fn set_task_nice(task: &Arc<TaskControlBlock>, nice: isize) {
+    TASK_POOL.lock().renice(task, nice);
+}
+
+/// Set the harts `task` may run on, see `TaskPool::set_affinity`.
+pub fn set_task_affinity(task: &Arc<TaskControlBlock>, cpu_mask: usize) {
+    TASK_POOL.lock().set_affinity(task, cpu_mask);
+}
+
+/// `wake_task` for many tasks at once, e.g. all readers of a device that
+/// got data. A tick during the batch does not switch away until every task
+/// is queued.
+#[allow(dead_code)]
+pub fn wake_tasks(tasks: impl IntoIterator<Item = Arc<TaskControlBlock>>) {
+    preempt_disable();
+    TASK_POOL.lock().wake_batch(tasks);
+    preempt_enable();
+}
+
+/// Make a blocked task runnable again. A task that has not switched out yet
+/// is only marked Ready here, and is requeued by its processor instead.
+pub fn wake_task(task: Arc<TaskControlBlock>) {
+    TASK_POOL.lock().wake_batch(Some(task));
+}
+
+#[allow(unused)]
+pub fn blocked_report_test() {
+    let elf_data = crate::loader::get_app_data_by_name("initproc").unwrap();
+    let mut pool = TaskPool::new();
+    let reader = TaskControlBlock::new("initproc", elf_data);
+    let sleeper = TaskControlBlock::new("initproc", elf_data);
+    let runner = TaskControlBlock::new("initproc", elf_data);
+    reader.acquire_inner_lock().block(BlockReason::StdinRead);
+    sleeper.acquire_inner_lock().block(BlockReason::Sleep(1000));
+    pool.sleep(sleeper.clone());
+    pool.sleep(reader.clone());
+    pool.add(runner);
+    assert_eq!(
+        pool.blocked_report(),
+        [
+            (reader.getpid(), BlockReason::StdinRead),
+            (sleeper.getpid(), BlockReason::Sleep(1000)),
+        ]
+    );
+    pool.wake(reader);
+    assert_eq!(
+        pool.blocked_report(),
+        [(sleeper.getpid(), BlockReason::Sleep(1000))]
+    );
+    debug!("blocked_report_test passed!");
+}
+
+#[allow(unused)]
+pub fn wake_batch_test() {
+    let elf_data = crate::loader::get_app_data_by_name("initproc").unwrap();
+    let mut pool = TaskPool::new();
+    let tasks: Vec<Arc<TaskControlBlock>> = (0..3)
+        .map(|_| TaskControlBlock::new("initproc", elf_data))
+        .collect();
+    for task in tasks.iter() {
+        task.acquire_inner_lock().block(BlockReason::StdinRead);
+        pool.sleep(task.clone());
+    }
+    assert!(pool.fetch().is_none());
+    pool.wake_batch(tasks.iter().cloned());
+    assert!(pool.blocked_report().is_empty());
+    // all of them are queued, in the order they were woken
+    for task in tasks.iter() {
+        let fetched = pool.fetch().unwrap();
+        assert!(Arc::ptr_eq(&fetched, task));
+        assert!(fetched.acquire_inner_lock().task_status == TaskStatus::Ready);
+    }
+    assert!(pool.fetch().is_none());
+    // a task that is not blocked anymore is left alone
+    pool.wake_batch(tasks.iter().take(1).cloned());
+    assert!(pool.fetch().is_none());
+    debug!("wake_batch_test passed!");
+}
+
+#[allow(unused)]
+pub fn early_wake_test() {
+    let elf_data = crate::loader::get_app_data_by_name("initproc").unwrap();
+    let mut pool = TaskPool::new();
+    let task = TaskControlBlock::new("initproc", elf_data);
+    // woken by another hart between blocking and switching out
+    task.acquire_inner_lock().block(BlockReason::Mutex(0));
+    pool.wake_batch(Some(task.clone()));
+    let mut task_inner = task.acquire_inner_lock();
+    assert!(task_inner.task_status == TaskStatus::Ready);
+    assert!(!task_inner.must_park());
+    assert!(task_inner.task_status == TaskStatus::Running);
+    assert!(task_inner.block_reason.is_none());
+    // it was never parked, so nothing got queued for it either
+    drop(task_inner);
+    assert!(pool.fetch().is_none());
+    assert!(pool.blocked_report().is_empty());
+    // not woken yet, it parks
+    task.acquire_inner_lock().block(BlockReason::Mutex(0));
+    assert!(task.acquire_inner_lock().must_park());
+    debug!("early_wake_test passed!");
+}
+
+#[allow(unused)]
+pub fn work_stealing_test() {
+    let elf_data = crate::loader::get_app_data_by_name("initproc").unwrap();
+    let mut pool = TaskPool {
+        schedulers: (0..2).map(|_| TaskManager::new()).collect(),
+        sleeping_tasks: BTreeSet::new(),
+    };
+    let tasks: Vec<Arc<TaskControlBlock>> = (0..3)
+        .map(|_| TaskControlBlock::new("initproc", elf_data))
+        .collect();
+    // spread over the harts, the first of the least loaded taking each
+    for task in tasks.iter() {
+        pool.add(task.clone());
+    }
+    assert_eq!(pool.schedulers[0].len(), 2);
+    assert_eq!(pool.schedulers[1].len(), 1);
+    let own = pool.schedulers[1].fetch(1).unwrap();
+    assert!(Arc::ptr_eq(&own, &tasks[1]));
+    // an idle hart takes the next task of the busiest one
+    assert!(Arc::ptr_eq(&pool.steal(1).unwrap(), &tasks[0]));
+    assert!(Arc::ptr_eq(&pool.steal(1).unwrap(), &tasks[2]));
+    assert!(pool.steal(1).is_none());
+    // and a woken task goes where the fewest wait
+    pool.add(tasks[0].clone());
+    tasks[1].acquire_inner_lock().block(BlockReason::StdinRead);
+    pool.sleep(tasks[1].clone());
+    pool.wake(tasks[1].clone());
+    assert_eq!(pool.schedulers[1].len(), 1);
+    assert!(Arc::ptr_eq(
+        &pool.schedulers[1].fetch(1).unwrap(),
+        &tasks[1]
+    ));
+    // a task is only queued on and stolen by the harts it may run on
+    tasks[2].acquire_inner_lock().cpu_mask = 1 << 0;
+    pool.add(tasks[2].clone());
+    assert_eq!(pool.schedulers[0].len(), 2);
+    assert!(Arc::ptr_eq(&pool.steal(1).unwrap(), &tasks[0]));
+    assert!(pool.steal(1).is_none());
+    // and moves when its mask changes while it is queued
+    pool.set_affinity(&tasks[2], 1 << 1);
+    assert_eq!(pool.schedulers[0].len(), 0);
+    assert_eq!(pool.schedulers[1].len(), 1);
+    debug!("work_stealing_test passed!");
+}
@@ -0,0 +1,60 @@
+use super::{current_task, hart_id, suspend_current_and_run_next};
+use crate::config::CPU_NUM;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+const ZERO: AtomicUsize = AtomicUsize::new(0);
+const CLEAR: AtomicBool = AtomicBool::new(false);
+/// Nesting depth of `preempt_disable` on each hart.
+static PREEMPT_COUNT: [AtomicUsize; CPU_NUM] = [ZERO; CPU_NUM];
+/// A tick arrived on this hart while preemption was disabled.
+static RESCHED_PENDING: [AtomicBool; CPU_NUM] = [CLEAR; CPU_NUM];
+
+/// Keep the current task on this hart until the matching `preempt_enable`.
+/// Calls nest.
+pub fn preempt_disable() {
+    PREEMPT_COUNT[hart_id()].fetch_add(1, Ordering::Acquire);
+}
+
+/// Undo one `preempt_disable`. Returns whether a reschedule was deferred
+/// meanwhile and is due now.
+fn preempt_enable_no_resched() -> bool {
+    let hart = hart_id();
+    let count = PREEMPT_COUNT[hart].fetch_sub(1, Ordering::Release);
+    assert!(count > 0, "unbalanced preempt_enable");
+    count == 1 && RESCHED_PENDING[hart].swap(false, Ordering::AcqRel)
+}
+
+/// Undo one `preempt_disable`, then run the reschedule it held back, if any.
+pub fn preempt_enable() {
+    if preempt_enable_no_resched() && current_task().is_some() {
+        suspend_current_and_run_next();
+    }
+}
+
+/// Called on a scheduler tick. Returns whether the current task may be
+/// switched out now; otherwise the switch is left to `preempt_enable`.
+pub fn preempt_tick() -> bool {
+    let hart = hart_id();
+    if PREEMPT_COUNT[hart].load(Ordering::Acquire) == 0 {
+        true
+    } else {
+        RESCHED_PENDING[hart].store(true, Ordering::Release);
+        false
+    }
+}
+
+#[allow(unused)]
+pub fn preempt_test() {
+    assert!(preempt_tick());
+    preempt_disable();
+    preempt_disable();
+    assert!(!preempt_tick());
+    // still nested
+    assert!(!preempt_enable_no_resched());
+    assert!(preempt_enable_no_resched());
+    assert!(preempt_tick());
+    // nothing deferred, nothing to do
+    preempt_disable();
+    assert!(!preempt_enable_no_resched());
+    debug!("preempt_test passed!");
+}
@@ -1,8 +1,10 @@
-use super::TaskControlBlock;
 use super::__switch;
-use super::add_task;
+use super::pool::TASK_POOL;
+use super::TaskControlBlock;
 use super::{fetch_task, TaskStatus};
 use crate::config::CPU_NUM;
+use crate::errno::ESRCH;
+use crate::mm::{shm_get, shm_release, MmapFlags};
 use crate::trap::TrapContext;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
@@ -100,20 +102,35 @@ impl Processor {
         }
     }
 
-    fn suspend_current(&self) {
-        if let Some(task) = take_current_task() {
+    /// Put the task which has just switched back to the idle flow where it belongs.
+    /// An exited task has been taken away already, so only a live one is requeued.
+    /// A blocked task is parked until `wake_task`; the pool lock is held while
+    /// checking its status so that a concurrent wakeup is not lost.
+    fn put_back_current(&self) {
+        if let Some(task) = self.take_current() {
+            let mut pool = TASK_POOL.lock();
             // ---- hold current PCB lock
             let mut task_inner = task.acquire_inner_lock();
-            // Change status to Ready
-            task_inner.task_status = TaskStatus::Ready;
+            if task_inner.task_status == TaskStatus::Zombie {
+                return;
+            }
             if let Some(trap_info) = &task_inner.user_trap_info {
                 trap_info.disable_user_ext_int();
             }
+            let blocked = task_inner.task_status == TaskStatus::Blocked;
+            if !blocked {
+                // Change status to Ready
+                task_inner.task_status = TaskStatus::Ready;
+            }
             drop(task_inner);
             // ---- release current PCB lock
 
-            // push back to ready queue.
-            add_task(task);
+            if blocked {
+                pool.sleep(task);
+            } else {
+                // push back to ready queue.
+                pool.add(task);
+            }
         }
     }
 
@@ -122,7 +139,7 @@ impl Processor {
             if let Some(task) = fetch_task() {
                 self.run_next(task);
                 // __switch inside run_next
-                self.suspend_current();
+                self.put_back_current();
             }
         }
     }
@@ -163,11 +180,16 @@ pub fn current_task() -> Option<Arc<TaskControlBlock>> {
     PROCESSORS[hart_id()].current()
 }
 
+/// The task running on hart `hart_id`, if any. `None` is also returned while
+/// that hart is switching tasks.
+pub fn current_on_hart(hart_id: usize) -> Option<Arc<TaskControlBlock>> {
+    let processor = PROCESSORS.get(hart_id)?;
+    let inner = processor.inner.try_borrow().ok()?;
+    inner.current.as_ref().map(Arc::clone)
+}
+
 pub fn current_tasks() -> Vec<Option<Arc<TaskControlBlock>>> {
-    PROCESSORS
-        .iter()
-        .map(|processor| processor.current())
-        .collect()
+    (0..PROCESSORS.len()).map(current_on_hart).collect()
 }
 
 pub fn current_user_token() -> usize {
@@ -196,10 +218,26 @@ pub fn set_current_priority(priority: isize) -> Result<isize, isize> {
     }
 }
 
-pub fn mmap(start: usize, len: usize, port: usize) -> Result<isize, isize> {
+pub fn mmap(start: usize, len: usize, port: usize, flags: MmapFlags) -> Result<isize, isize> {
     if let Some(current) = current_task() {
         let mut current = current.acquire_inner_lock();
-        current.mmap(start, len, port)
+        current.mmap(start, len, port, flags)
+    } else {
+        Err(-1)
+    }
+}
+
+pub fn mmap_file(
+    start: usize,
+    len: usize,
+    port: usize,
+    flags: MmapFlags,
+    fd: usize,
+    offset: usize,
+) -> Result<isize, isize> {
+    if let Some(current) = current_task() {
+        let mut current = current.acquire_inner_lock();
+        current.mmap_file(start, len, port, flags, fd, offset)
     } else {
         Err(-1)
     }
@@ -213,3 +251,77 @@ pub fn munmap(start: usize, len: usize) -> Result<isize, isize> {
         Err(-1)
     }
 }
+
+pub fn mprotect(start: usize, len: usize, port: usize) -> Result<isize, isize> {
+    if let Some(current) = current_task() {
+        let mut current = current.acquire_inner_lock();
+        current.mprotect(start, len, port)
+    } else {
+        Err(-1)
+    }
+}
+
+pub fn mlock(start: usize, len: usize) -> Result<isize, isize> {
+    if let Some(current) = current_task() {
+        let mut current = current.acquire_inner_lock();
+        current.mlock(start, len)
+    } else {
+        Err(-1)
+    }
+}
+
+pub fn munlock(start: usize, len: usize) -> Result<isize, isize> {
+    if let Some(current) = current_task() {
+        let mut current = current.acquire_inner_lock();
+        current.munlock(start, len)
+    } else {
+        Err(-1)
+    }
+}
+
+pub fn madvise(start: usize, len: usize, advice: usize) -> Result<isize, isize> {
+    if let Some(current) = current_task() {
+        let mut current = current.acquire_inner_lock();
+        current.madvise(start, len, advice)
+    } else {
+        Err(ESRCH)
+    }
+}
+
+pub fn shm_attach(id: usize, start: usize, port: usize) -> Result<isize, isize> {
+    let segment = shm_get(id).ok_or(-1isize)?;
+    if let Some(current) = current_task() {
+        let mut current = current.acquire_inner_lock();
+        current.shm_attach(&segment, start, port)
+    } else {
+        Err(-1)
+    }
+}
+
+pub fn shm_detach(start: usize) -> Result<isize, isize> {
+    if let Some(current) = current_task() {
+        let segment = current.acquire_inner_lock().shm_detach(start)?;
+        shm_release(segment);
+        Ok(0)
+    } else {
+        Err(-1)
+    }
+}
+
+pub fn brk(addr: usize) -> Result<isize, isize> {
+    if let Some(current) = current_task() {
+        let mut current = current.acquire_inner_lock();
+        current.brk(addr)
+    } else {
+        Err(-1)
+    }
+}
+
+pub fn mremap(old_start: usize, old_len: usize, new_len: usize) -> Result<isize, isize> {
+    if let Some(current) = current_task() {
+        let mut current = current.acquire_inner_lock();
+        current.mremap(old_start, old_len, new_len)
+    } else {
+        Err(-1)
+    }
+}
@@ -0,0 +1,287 @@
+//! Policies for the order ready tasks run in, see `TaskManager`.
+
+use super::TaskControlBlock;
+use crate::boot::machine;
+use crate::config::SCHED_POLICY;
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::Arc;
+use spin::Once;
+
+/// Nice values as on Linux, the lowest being the most favoured.
+pub const NICE_MIN: isize = -20;
+pub const NICE_MAX: isize = 19;
+
+/// The pass of a task advances by this over its `priority` for every tick
+/// it runs, see `StrideScheduler`.
+pub const BIG_STRIDE: u64 = 1 << 20;
+
+/// Ticks a task runs under `RoundRobinScheduler` before the next one gets
+/// its turn.
+pub const TIME_SLICE: usize = 4;
+
+/// The order a ready queue hands out its tasks in. The `TaskManager` of
+/// each hart keeps one queue of the policy the kernel runs.
+pub trait Scheduler: Send {
+    fn add(&mut self, task: Arc<TaskControlBlock>);
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>>;
+    /// Whether `task` was queued here.
+    fn remove(&mut self, task: &Arc<TaskControlBlock>) -> bool;
+    /// `task`, which is not queued, ran for a tick on the hart this queue
+    /// belongs to. Whether it may have to give way, if a ready task ranks
+    /// no higher.
+    fn tick(&mut self, task: &Arc<TaskControlBlock>) -> bool;
+    /// Where `task` ranks among queued tasks, the lowest being fetched
+    /// first.
+    fn rank(&self, task: &Arc<TaskControlBlock>) -> u64;
+    /// Rank of the task `fetch` returns next.
+    fn peek(&self) -> Option<u64>;
+    /// The tasks in the order `fetch` returns them.
+    fn iter(&self) -> Box<dyn Iterator<Item = &Arc<TaskControlBlock>> + '_>;
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SchedPolicy {
+    Fifo,
+    RoundRobin,
+    Priority,
+    Stride,
+}
+
+impl SchedPolicy {
+    /// The policy called `name` in `SCHED_POLICY` and the `sched=` boot
+    /// argument.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "fifo" => Some(Self::Fifo),
+            "rr" => Some(Self::RoundRobin),
+            "priority" => Some(Self::Priority),
+            "stride" => Some(Self::Stride),
+            _ => None,
+        }
+    }
+    pub fn new_scheduler(self) -> Box<dyn Scheduler> {
+        match self {
+            Self::Fifo => Box::new(FifoScheduler::default()),
+            Self::RoundRobin => Box::new(RoundRobinScheduler::default()),
+            Self::Priority => Box::new(PriorityScheduler::default()),
+            Self::Stride => Box::new(StrideScheduler::default()),
+        }
+    }
+}
+
+static POLICY: Once<SchedPolicy> = Once::new();
+
+/// The policy the `sched=` boot argument names, `SCHED_POLICY` if there is
+/// none or it names no policy.
+pub fn sched_policy() -> SchedPolicy {
+    *POLICY.call_once(|| {
+        let configured = SchedPolicy::from_name(SCHED_POLICY).expect("bad SCHED_POLICY");
+        match machine().bootarg("sched") {
+            Some(name) => SchedPolicy::from_name(name).unwrap_or_else(|| {
+                warn!("no scheduling policy {:?}, using {:?}", name, configured);
+                configured
+            }),
+            None => configured,
+        }
+    })
+}
+
+/// A queue of the policy the kernel runs with, see `sched_policy`.
+pub fn new_scheduler() -> Box<dyn Scheduler> {
+    sched_policy().new_scheduler()
+}
+
+/// Tasks in the order they became ready, each running until it blocks,
+/// yields or exits.
+#[derive(Default)]
+pub struct FifoScheduler {
+    queue: VecDeque<Arc<TaskControlBlock>>,
+}
+
+impl Scheduler for FifoScheduler {
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        self.queue.push_back(task);
+    }
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        self.queue.pop_front()
+    }
+    fn remove(&mut self, task: &Arc<TaskControlBlock>) -> bool {
+        match self.queue.iter().position(|task_item| *task_item == *task) {
+            Some(idx) => {
+                self.queue.remove(idx);
+                true
+            }
+            None => false,
+        }
+    }
+    fn tick(&mut self, _task: &Arc<TaskControlBlock>) -> bool {
+        false
+    }
+    fn rank(&self, _task: &Arc<TaskControlBlock>) -> u64 {
+        0
+    }
+    fn peek(&self) -> Option<u64> {
+        self.queue.front().map(|_| 0)
+    }
+    fn iter(&self) -> Box<dyn Iterator<Item = &Arc<TaskControlBlock>> + '_> {
+        Box::new(self.queue.iter())
+    }
+}
+
+/// Tasks first in first out, each giving way after `TIME_SLICE` ticks.
+#[derive(Default)]
+pub struct RoundRobinScheduler {
+    queue: FifoScheduler,
+    /// Pid of the task last ticked on this hart and the ticks it has used
+    /// of its slice
+    running: usize,
+    used: usize,
+}
+
+impl Scheduler for RoundRobinScheduler {
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        self.queue.add(task);
+    }
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        self.queue.fetch()
+    }
+    fn remove(&mut self, task: &Arc<TaskControlBlock>) -> bool {
+        self.queue.remove(task)
+    }
+    fn tick(&mut self, task: &Arc<TaskControlBlock>) -> bool {
+        if task.getpid() != self.running {
+            self.running = task.getpid();
+            self.used = 0;
+        }
+        self.used += 1;
+        if self.used < TIME_SLICE {
+            return false;
+        }
+        self.used = 0;
+        true
+    }
+    fn rank(&self, task: &Arc<TaskControlBlock>) -> u64 {
+        self.queue.rank(task)
+    }
+    fn peek(&self) -> Option<u64> {
+        self.queue.peek()
+    }
+    fn iter(&self) -> Box<dyn Iterator<Item = &Arc<TaskControlBlock>> + '_> {
+        self.queue.iter()
+    }
+}
+
+/// Tasks by nice value, first in first out within a level: the most
+/// favoured level with ready tasks runs, and the others wait until it has
+/// none. A task stays at the level of the nice value it had when queued.
+#[derive(Default)]
+pub struct PriorityScheduler {
+    levels: BTreeMap<isize, VecDeque<Arc<TaskControlBlock>>>,
+}
+
+impl PriorityScheduler {
+    fn level_of(&mut self, task: &Arc<TaskControlBlock>) -> &mut VecDeque<Arc<TaskControlBlock>> {
+        let nice = task.acquire_inner_lock().nice;
+        self.levels.entry(nice).or_insert_with(VecDeque::new)
+    }
+}
+
+impl Scheduler for PriorityScheduler {
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        self.level_of(&task).push_back(task);
+    }
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        let nice = *self.levels.keys().next()?;
+        let level = self.levels.get_mut(&nice).unwrap();
+        let task = level.pop_front();
+        if level.is_empty() {
+            self.levels.remove(&nice);
+        }
+        task
+    }
+    fn remove(&mut self, task: &Arc<TaskControlBlock>) -> bool {
+        for (&nice, level) in self.levels.iter_mut() {
+            if let Some(idx) = level.iter().position(|task_item| *task_item == *task) {
+                level.remove(idx);
+                if level.is_empty() {
+                    self.levels.remove(&nice);
+                }
+                return true;
+            }
+        }
+        false
+    }
+    fn tick(&mut self, _task: &Arc<TaskControlBlock>) -> bool {
+        true
+    }
+    fn rank(&self, task: &Arc<TaskControlBlock>) -> u64 {
+        (task.acquire_inner_lock().nice - NICE_MIN) as u64
+    }
+    fn peek(&self) -> Option<u64> {
+        self.levels
+            .keys()
+            .next()
+            .map(|&nice| (nice - NICE_MIN) as u64)
+    }
+    fn iter(&self) -> Box<dyn Iterator<Item = &Arc<TaskControlBlock>> + '_> {
+        Box::new(self.levels.values().flat_map(|level| level.iter()))
+    }
+}
+
+/// Tasks by pass, the virtual runtime that grows by `BIG_STRIDE /
+/// priority` for each tick a task runs, so tasks get CPU time in
+/// proportion to their priorities. Ties go first in first out.
+#[derive(Default)]
+pub struct StrideScheduler {
+    /// By pass, then by when queued
+    queue: BTreeMap<(u64, i64), Arc<TaskControlBlock>>,
+    /// Order of the next task queued, counting up
+    back: i64,
+    /// Pass of the task fetched last. A task that slept is queued no lower,
+    /// instead of taking the CPU until it made up for the time it slept.
+    min_pass: u64,
+}
+
+impl StrideScheduler {
+    fn pass_of(&self, task: &Arc<TaskControlBlock>) -> u64 {
+        let mut inner = task.acquire_inner_lock();
+        inner.pass = inner.pass.max(self.min_pass);
+        inner.pass
+    }
+}
+
+impl Scheduler for StrideScheduler {
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        let key = (self.pass_of(&task), self.back);
+        self.back += 1;
+        self.queue.insert(key, task);
+    }
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        let key = *self.queue.keys().next()?;
+        self.min_pass = key.0;
+        self.queue.remove(&key)
+    }
+    fn remove(&mut self, task: &Arc<TaskControlBlock>) -> bool {
+        let key = match self.queue.iter().find(|(_, task_item)| *task_item == task) {
+            Some((key, _)) => *key,
+            None => return false,
+        };
+        self.queue.remove(&key);
+        true
+    }
+    fn tick(&mut self, task: &Arc<TaskControlBlock>) -> bool {
+        let mut inner = task.acquire_inner_lock();
+        inner.pass += BIG_STRIDE / inner.priority.max(1) as u64;
+        true
+    }
+    fn rank(&self, task: &Arc<TaskControlBlock>) -> u64 {
+        task.acquire_inner_lock().pass
+    }
+    fn peek(&self) -> Option<u64> {
+        self.queue.keys().next().map(|&(pass, _)| pass)
+    }
+    fn iter(&self) -> Box<dyn Iterator<Item = &Arc<TaskControlBlock>> + '_> {
+        Box::new(self.queue.values())
+    }
+}
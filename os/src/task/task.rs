@@ -1,14 +1,22 @@
 use super::TaskContext;
 use super::{pid_alloc, KernelStack, PidHandle};
 use crate::fs::{File, MailBox, Socket, Stdin, Stdout};
-use crate::mm::{translate_writable_va, MemorySet, PhysAddr, PhysPageNum, VirtAddr, KERNEL_SPACE};
+use crate::mm::{
+    translate_writable_va, InodeStream, MemorySet, MmapFlags, PhysAddr, PhysPageNum, Placement,
+    SharedFrame, VirtAddr, KERNEL_SPACE,
+};
+use crate::perf::PerfCounters;
 use crate::task::pid::add_task_2_map;
 use crate::trap::{trap_handler, TrapContext, UserTrapInfo};
 use crate::{
-    config::{PAGE_SIZE, TRAP_CONTEXT, USER_TRAP_BUFFER},
+    config::{CPU_NUM, PAGE_SIZE, TRAP_CONTEXT, USER_TRAP_BUFFER},
+    errno::{EACCES, EBADF, ENOMEM},
     loader::get_app_data_by_name,
-    mm::translated_str,
+    mm::{strncpy_from_user, PATH_MAX},
+    track_alloc,
 };
+use alloc::collections::BTreeSet;
+use alloc::string::String;
 use alloc::sync::{Arc, Weak};
 use alloc::vec;
 use alloc::vec::Vec;
@@ -25,17 +33,36 @@ pub struct TaskControlBlock {
 }
 
 pub struct TaskControlBlockInner {
+    pub name: String,
+    pub uid: u32,
+    pub gid: u32,
     pub trap_cx_ppn: PhysPageNum,
     pub base_size: usize,
     pub task_cx_ptr: usize,
     pub user_trap_info: Option<UserTrapInfo>,
     pub task_status: TaskStatus,
+    /// What a Blocked task waits for
+    pub block_reason: Option<BlockReason>,
     pub priority: isize,
+    /// From `NICE_MIN`, the most favoured, to `NICE_MAX`, see `TaskManager`
+    pub nice: isize,
+    /// Virtual runtime, see `StrideScheduler`
+    pub pass: u64,
+    /// Scheduler ticks spent running
+    pub ticks: usize,
+    /// `ticks` of the children reaped by `waitpid`, theirs included
+    pub children_ticks: usize,
+    /// Bit `i` set if the task may run on hart `i`
+    pub cpu_mask: usize,
     pub memory_set: MemorySet,
     pub parent: Option<Weak<TaskControlBlock>>,
     pub children: Vec<Arc<TaskControlBlock>>,
     pub exit_code: i32,
     pub fd_table: Vec<Option<Arc<dyn File + Send + Sync>>>,
+    /// fds that are closed on exec (`FD_CLOEXEC`)
+    pub fd_cloexec: BTreeSet<usize>,
+    /// perf events counted for this task
+    pub perf_counters: PerfCounters,
     pub mail_box: Arc<MailBox>,
 }
 
@@ -55,6 +82,24 @@ impl TaskControlBlockInner {
     pub fn get_trap_cx(&self) -> &'static mut TrapContext {
         self.trap_cx_ppn.get_mut()
     }
+    /// Mark the task Blocked on `reason`. It parks on the next switch.
+    pub fn block(&mut self, reason: BlockReason) {
+        self.task_status = TaskStatus::Blocked;
+        self.block_reason = Some(reason);
+    }
+    /// Whether the running task, marked Blocked by `block`, still has to
+    /// switch out. A waker on another hart may have made it Ready before it
+    /// got to, in which case it just keeps running.
+    pub fn must_park(&mut self) -> bool {
+        match self.task_status {
+            TaskStatus::Blocked => true,
+            TaskStatus::Ready => {
+                self.task_status = TaskStatus::Running;
+                false
+            }
+            _ => panic!("only a blocked task parks"),
+        }
+    }
     pub fn get_user_token(&self) -> usize {
         self.memory_set.token()
     }
@@ -64,6 +109,9 @@ impl TaskControlBlockInner {
     pub fn is_zombie(&self) -> bool {
         self.get_status() == TaskStatus::Zombie
     }
+    pub fn may_run_on(&self, hart: usize) -> bool {
+        self.cpu_mask & (1 << hart) != 0
+    }
 
     pub fn set_priority(&mut self, priority: isize) -> Result<isize, isize> {
         if priority < 2 {
@@ -73,23 +121,120 @@ impl TaskControlBlockInner {
         Ok(priority)
     }
 
-    pub fn mmap(&mut self, start: usize, len: usize, port: usize) -> Result<isize, isize> {
-        self.memory_set.mmap(start, len, port)
+    pub fn mmap(
+        &mut self,
+        start: usize,
+        len: usize,
+        port: usize,
+        flags: MmapFlags,
+    ) -> Result<isize, isize> {
+        self.memory_set.mmap(start, len, port, flags)
+    }
+
+    /// Map the file open as `fd`, which has to be open for reading, and
+    /// for writing as well to be mapped shared and writable.
+    pub fn mmap_file(
+        &mut self,
+        start: usize,
+        len: usize,
+        port: usize,
+        flags: MmapFlags,
+        fd: usize,
+        offset: usize,
+    ) -> Result<isize, isize> {
+        let file = self.fd_table.get(fd).cloned().flatten().ok_or(EBADF)?;
+        let inode = file.inode().ok_or(EACCES)?;
+        let (readable, writable) = file.status_flags().read_write();
+        if !readable || (flags.contains(MmapFlags::SHARED) && port & 0b10 != 0 && !writable) {
+            return Err(EACCES);
+        }
+        self.memory_set
+            .mmap_file(start, len, port, flags, inode, offset)
+    }
+
+    /// Save the address space, registers in the trap context included, to
+    /// the file open as `fd` for writing, replacing what it held.
+    pub fn checkpoint(&self, fd: usize) -> Result<(), isize> {
+        let file = self.fd_table.get(fd).cloned().flatten().ok_or(EBADF)?;
+        let inode = file.inode().ok_or(EACCES)?;
+        if !file.status_flags().read_write().1 {
+            return Err(EBADF);
+        }
+        self.memory_set.serialize(&mut InodeStream::writer(inode)?)
     }
 
     pub fn munmap(&mut self, start: usize, len: usize) -> Result<isize, isize> {
         self.memory_set.munmap(start, len)
     }
 
+    pub fn mprotect(&mut self, start: usize, len: usize, port: usize) -> Result<isize, isize> {
+        self.memory_set.mprotect(start, len, port)
+    }
+
+    pub fn mlock(&mut self, start: usize, len: usize) -> Result<isize, isize> {
+        self.memory_set.pin_range(start, len)
+    }
+
+    pub fn munlock(&mut self, start: usize, len: usize) -> Result<isize, isize> {
+        self.memory_set.unpin_range(start, len)
+    }
+
+    pub fn madvise(&mut self, start: usize, len: usize, advice: usize) -> Result<isize, isize> {
+        self.memory_set.madvise(start, len, advice)
+    }
+
+    pub fn shm_attach(
+        &mut self,
+        segment: &SharedFrame,
+        start: usize,
+        port: usize,
+    ) -> Result<isize, isize> {
+        self.memory_set.attach_shared(segment, start, port)
+    }
+
+    pub fn shm_detach(&mut self, start: usize) -> Result<SharedFrame, isize> {
+        self.memory_set.detach_shared(start)
+    }
+
+    /// Move the program break to `addr`, or just report it for 0. A move
+    /// that fails leaves the break alone and reports it, as on Linux.
+    pub fn brk(&mut self, addr: usize) -> Result<isize, isize> {
+        let current = self.memory_set.brk().ok_or(-1isize)?;
+        if addr == 0 {
+            return Ok(current as isize);
+        }
+        Ok(self.memory_set.change_brk(addr).unwrap_or(current) as isize)
+    }
+
+    pub fn mremap(
+        &mut self,
+        old_start: usize,
+        old_len: usize,
+        new_len: usize,
+    ) -> Result<isize, isize> {
+        self.memory_set.mremap(old_start, old_len, new_len)
+    }
+
     pub fn alloc_fd(&mut self) -> usize {
-        if let Some(fd) = (0..self.fd_table.len()).find(|fd| self.fd_table[*fd].is_none()) {
+        self.alloc_fd_from(0)
+    }
+
+    /// Allocate the lowest free fd that is not below `min_fd`.
+    pub fn alloc_fd_from(&mut self, min_fd: usize) -> usize {
+        if let Some(fd) = (min_fd..self.fd_table.len()).find(|fd| self.fd_table[*fd].is_none()) {
             fd
         } else {
-            self.fd_table.push(None);
-            self.fd_table.len() - 1
+            let fd = self.fd_table.len().max(min_fd);
+            self.fd_table.resize(fd + 1, None);
+            fd
         }
     }
 
+    pub fn close_fd(&mut self, fd: usize) {
+        self.fd_table[fd].take();
+        self.fd_cloexec.remove(&fd);
+    }
+
     pub fn is_mailbox_full(&self) -> bool {
         self.mail_box.is_full()
     }
@@ -106,7 +251,18 @@ impl TaskControlBlockInner {
         use riscv::register::sstatus;
         if self.user_trap_info.is_none() {
             // R | W
-            if self.mmap(USER_TRAP_BUFFER, PAGE_SIZE, 0b11).is_ok() {
+            // the kernel writes the buffer through its frame, keep it there
+            let flags = MmapFlags::PRIVATE | MmapFlags::ANONYMOUS | MmapFlags::FIXED_NOREPLACE;
+            if self.mmap(USER_TRAP_BUFFER, PAGE_SIZE, 0b11, flags).is_ok()
+                && self
+                    .memory_set
+                    .set_placement(USER_TRAP_BUFFER, PAGE_SIZE, Placement::Fast)
+                    .is_ok()
+                && self
+                    .memory_set
+                    .pin_range(USER_TRAP_BUFFER, PAGE_SIZE)
+                    .is_ok()
+            {
                 let phys_addr =
                     translate_writable_va(self.get_user_token(), USER_TRAP_BUFFER).unwrap();
                 self.user_trap_info = Some(UserTrapInfo {
@@ -148,45 +304,63 @@ impl TaskControlBlock {
     pub fn acquire_inner_lock(&self) -> MutexGuard<TaskControlBlockInner> {
         self.inner.lock()
     }
-    pub fn new(elf_data: &[u8]) -> Arc<TaskControlBlock> {
+    pub fn try_acquire_inner_lock(&self) -> Option<MutexGuard<TaskControlBlockInner>> {
+        self.inner.try_lock()
+    }
+    pub fn new(name: &str, elf_data: &'static [u8]) -> Arc<TaskControlBlock> {
         // memory_set with elf program headers/trampoline/trap context/user stack
-        let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data);
+        let (memory_set, user_sp, entry_point) =
+            MemorySet::from_elf_demand(elf_data).expect("invalid elf!");
         let trap_cx_ppn = memory_set
             .translate(VirtAddr::from(TRAP_CONTEXT).into())
             .unwrap()
             .ppn();
         // alloc a pid and a kernel stack in kernel space
         let pid_handle = pid_alloc();
-        let kernel_stack = KernelStack::new(&pid_handle);
+        let kernel_stack = KernelStack::new(&pid_handle).expect("out of memory!");
         let kernel_stack_top = kernel_stack.get_top();
         // push a task context which goes to trap_return to the top of kernel stack
         let task_cx_ptr = kernel_stack.push_on_top(TaskContext::goto_trap_return());
         debug!("new task cx ptr: {:#x?}", task_cx_ptr as usize);
-        let task_control_block = Arc::new(TaskControlBlock {
-            pid: pid_handle,
-            kernel_stack,
-            inner: Mutex::new(TaskControlBlockInner {
-                trap_cx_ppn,
-                base_size: user_sp,
-                task_cx_ptr: task_cx_ptr as usize,
-                user_trap_info: None,
-                task_status: TaskStatus::Ready,
-                memory_set,
-                parent: None,
-                children: Vec::new(),
-                exit_code: 0,
-                priority: 16,
-                fd_table: vec![
-                    // 0 -> stdin
-                    Some(Arc::new(Stdin)),
-                    // 1 -> stdout
-                    Some(Arc::new(Stdout)),
-                    // 2 -> stderr
-                    Some(Arc::new(Stdout)),
-                ],
-                mail_box: Arc::new(MailBox::new()),
-            }),
-        });
+        let task_control_block = track_alloc!(
+            "task",
+            Arc::new(TaskControlBlock {
+                pid: pid_handle,
+                kernel_stack,
+                inner: Mutex::new(TaskControlBlockInner {
+                    name: String::from(name),
+                    uid: 0,
+                    gid: 0,
+                    trap_cx_ppn,
+                    base_size: user_sp,
+                    task_cx_ptr: task_cx_ptr as usize,
+                    user_trap_info: None,
+                    task_status: TaskStatus::Ready,
+                    block_reason: None,
+                    memory_set,
+                    parent: None,
+                    children: Vec::new(),
+                    exit_code: 0,
+                    priority: 16,
+                    nice: 0,
+                    pass: 0,
+                    ticks: 0,
+                    children_ticks: 0,
+                    cpu_mask: (1 << CPU_NUM) - 1,
+                    fd_table: vec![
+                        // 0 -> stdin
+                        Some(Arc::new(Stdin)),
+                        // 1 -> stdout
+                        Some(Arc::new(Stdout)),
+                        // 2 -> stderr
+                        Some(Arc::new(Stdout)),
+                    ],
+                    fd_cloexec: BTreeSet::new(),
+                    perf_counters: PerfCounters::default(),
+                    mail_box: Arc::new(MailBox::new()),
+                }),
+            })
+        );
         add_task_2_map(task_control_block.getpid(), task_control_block.clone());
         // prepare TrapContext in user space
         let trap_cx = task_control_block.acquire_inner_lock().get_trap_cx();
@@ -200,21 +374,25 @@ impl TaskControlBlock {
         task_control_block
     }
 
-    pub fn exec(&self, elf_data: &[u8]) {
-        // memory_set with elf program headers/trampoline/trap context/user stack
-        let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data);
-        let trap_cx_ppn = memory_set
+    /// Replace the user space of this task with `elf_data`. The old space
+    /// is kept on any error but ENOMEM, after which it is already gone and
+    /// the caller must not return to user mode.
+    pub fn exec(&self, name: &str, elf_data: &'static [u8]) -> Result<(), isize> {
+        // **** hold current PCB lock
+        let mut inner = self.acquire_inner_lock();
+        // reuse the page table, map elf program headers/trap context/user stack
+        let (user_sp, entry_point) = inner.memory_set.exec_replace_demand(elf_data)?;
+        // update trap_cx ppn
+        inner.trap_cx_ppn = inner
+            .memory_set
             .translate(VirtAddr::from(TRAP_CONTEXT).into())
             .unwrap()
             .ppn();
-
-        // **** hold current PCB lock
-        let mut inner = self.acquire_inner_lock();
+        inner.name = String::from(name);
         inner.user_trap_info = None;
-        // substitute memory_set
-        inner.memory_set = memory_set;
-        // update trap_cx ppn
-        inner.trap_cx_ppn = trap_cx_ppn;
+        for fd in core::mem::take(&mut inner.fd_cloexec) {
+            inner.fd_table[fd].take();
+        }
         // initialize trap_cx
         let trap_cx = inner.get_trap_cx();
         *trap_cx = TrapContext::app_init_context(
@@ -224,21 +402,62 @@ impl TaskControlBlock {
             self.kernel_stack.get_top(),
             trap_handler as usize,
         );
+        Ok(())
         // **** release current PCB lock
     }
 
-    pub fn fork(self: &Arc<TaskControlBlock>) -> Arc<TaskControlBlock> {
+    /// Replace the address space with the one checkpointed to the file open
+    /// as `fd`, to resume right after the `checkpoint` call, which returns
+    /// 1 there. Files and limits stay those of this task. Fails leaving the
+    /// task as it was.
+    pub fn restore(&self, fd: usize) -> Result<(), isize> {
+        let mut inner = self.acquire_inner_lock();
+        let file = inner.fd_table.get(fd).cloned().flatten().ok_or(EBADF)?;
+        let inode = file.inode().ok_or(EACCES)?;
+        if !file.status_flags().read_write().0 {
+            return Err(EBADF);
+        }
+        let mut memory_set = MemorySet::restore(&mut InodeStream::reader(inode))?;
+        let limits = inner.memory_set.limits();
+        if limits.address_space.exceeded_by(memory_set.mapped_bytes())
+            || limits
+                .resident
+                .exceeded_by(memory_set.resident_set_size() * PAGE_SIZE)
+        {
+            return Err(ENOMEM);
+        }
+        memory_set.set_limits(limits);
+        inner.memory_set = memory_set;
+        inner.trap_cx_ppn = inner
+            .memory_set
+            .translate(VirtAddr::from(TRAP_CONTEXT).into())
+            .unwrap()
+            .ppn();
+        inner.user_trap_info = None;
+        let trap_cx = inner.get_trap_cx();
+        *trap_cx = TrapContext::from_checkpoint(
+            trap_cx,
+            KERNEL_SPACE.lock().token(),
+            self.kernel_stack.get_top(),
+            trap_handler as usize,
+        );
+        trap_cx.x[10] = 1;
+        Ok(())
+    }
+
+    /// Fails with ENOMEM, leaving the parent as it was.
+    pub fn fork(self: &Arc<TaskControlBlock>) -> Result<Arc<TaskControlBlock>, isize> {
         // ---- hold parent PCB lock
         let mut parent_inner = self.acquire_inner_lock();
         // copy user space(include trap context)
-        let memory_set = MemorySet::from_existed_user(&parent_inner.memory_set);
+        let memory_set = parent_inner.memory_set.deep_clone()?;
         let trap_cx_ppn = memory_set
             .translate(VirtAddr::from(TRAP_CONTEXT).into())
             .unwrap()
             .ppn();
         // alloc a pid and a kernel stack in kernel space
         let pid_handle = pid_alloc();
-        let kernel_stack = KernelStack::new(&pid_handle);
+        let kernel_stack = KernelStack::new(&pid_handle)?;
         let kernel_stack_top = kernel_stack.get_top();
         // push a goto_trap_return task_cx on the top of kernel stack
         let task_cx_ptr = kernel_stack.push_on_top(TaskContext::goto_trap_return());
@@ -261,24 +480,38 @@ impl TaskControlBlock {
                 .ppn();
             user_trap_info = Some(trap_info);
         }
-        let task_control_block = Arc::new(TaskControlBlock {
-            pid: pid_handle,
-            kernel_stack,
-            inner: Mutex::new(TaskControlBlockInner {
-                trap_cx_ppn,
-                base_size: parent_inner.base_size,
-                task_cx_ptr: task_cx_ptr as usize,
-                user_trap_info,
-                task_status: TaskStatus::Ready,
-                memory_set,
-                parent: Some(Arc::downgrade(self)),
-                children: Vec::new(),
-                exit_code: 0,
-                priority: 16,
-                fd_table: new_fd_table,
-                mail_box: Arc::new(MailBox::new()),
-            }),
-        });
+        let task_control_block = track_alloc!(
+            "task",
+            Arc::new(TaskControlBlock {
+                pid: pid_handle,
+                kernel_stack,
+                inner: Mutex::new(TaskControlBlockInner {
+                    name: parent_inner.name.clone(),
+                    uid: parent_inner.uid,
+                    gid: parent_inner.gid,
+                    trap_cx_ppn,
+                    base_size: parent_inner.base_size,
+                    task_cx_ptr: task_cx_ptr as usize,
+                    user_trap_info,
+                    task_status: TaskStatus::Ready,
+                    block_reason: None,
+                    memory_set,
+                    parent: Some(Arc::downgrade(self)),
+                    children: Vec::new(),
+                    exit_code: 0,
+                    priority: 16,
+                    nice: parent_inner.nice,
+                    pass: parent_inner.pass,
+                    ticks: 0,
+                    children_ticks: 0,
+                    cpu_mask: parent_inner.cpu_mask,
+                    fd_table: new_fd_table,
+                    fd_cloexec: parent_inner.fd_cloexec.clone(),
+                    perf_counters: PerfCounters::default(),
+                    mail_box: Arc::new(MailBox::new()),
+                }),
+            })
+        );
         add_task_2_map(task_control_block.getpid(), task_control_block.clone());
         // add child
         parent_inner.children.push(task_control_block.clone());
@@ -288,7 +521,7 @@ impl TaskControlBlock {
         // **** release child PCB lock
         trap_cx.kernel_sp = kernel_stack_top;
         // return
-        task_control_block
+        Ok(task_control_block)
         // ---- release parent PCB lock
     }
     pub fn getpid(&self) -> usize {
@@ -299,48 +532,62 @@ impl TaskControlBlock {
         self: &Arc<TaskControlBlock>,
         file: *const u8,
     ) -> Result<Arc<TaskControlBlock>, isize> {
+        let parent_token = self.acquire_inner_lock().get_user_token();
+        let f = strncpy_from_user(parent_token, file, PATH_MAX)?;
         let mut parent_inner = self.acquire_inner_lock();
-        let parent_token = parent_inner.get_user_token();
-        let f = translated_str(parent_token, file);
         debug!("SPAWN exec {:?}", &f);
 
         if let Some(elf_data) = get_app_data_by_name(f.as_str()) {
-            let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data);
+            let (memory_set, user_sp, entry_point) = MemorySet::from_elf_demand(elf_data)?;
             let trap_cx_ppn = memory_set
                 .translate(VirtAddr::from(TRAP_CONTEXT).into())
                 .unwrap()
                 .ppn();
             let pid_handle = pid_alloc();
-            let kernel_stack = KernelStack::new(&pid_handle);
+            let kernel_stack = KernelStack::new(&pid_handle)?;
             let kernel_stack_top = kernel_stack.get_top();
             let task_cx_ptr = kernel_stack.push_on_top(TaskContext::goto_trap_return());
             debug!("spawned task cx ptr: {:#x?}", task_cx_ptr as usize);
 
-            let task_control_block = Arc::new(TaskControlBlock {
-                pid: pid_handle,
-                kernel_stack,
-                inner: Mutex::new(TaskControlBlockInner {
-                    trap_cx_ppn,
-                    base_size: user_sp,
-                    task_cx_ptr: task_cx_ptr as usize,
-                    user_trap_info: None,
-                    task_status: TaskStatus::Ready,
-                    memory_set,
-                    parent: Some(Arc::downgrade(self)),
-                    children: Vec::new(),
-                    exit_code: 0,
-                    priority: 16,
-                    fd_table: vec![
-                        // 0 -> stdin
-                        Some(Arc::new(Stdin)),
-                        // 1 -> stdout
-                        Some(Arc::new(Stdout)),
-                        // 2 -> stderr
-                        Some(Arc::new(Stdout)),
-                    ],
-                    mail_box: Arc::new(MailBox::new()),
-                }),
-            });
+            let task_control_block = track_alloc!(
+                "task",
+                Arc::new(TaskControlBlock {
+                    pid: pid_handle,
+                    kernel_stack,
+                    inner: Mutex::new(TaskControlBlockInner {
+                        name: f.clone(),
+                        uid: parent_inner.uid,
+                        gid: parent_inner.gid,
+                        trap_cx_ppn,
+                        base_size: user_sp,
+                        task_cx_ptr: task_cx_ptr as usize,
+                        user_trap_info: None,
+                        task_status: TaskStatus::Ready,
+                        block_reason: None,
+                        memory_set,
+                        parent: Some(Arc::downgrade(self)),
+                        children: Vec::new(),
+                        exit_code: 0,
+                        priority: 16,
+                        nice: parent_inner.nice,
+                        pass: parent_inner.pass,
+                        ticks: 0,
+                        children_ticks: 0,
+                        cpu_mask: parent_inner.cpu_mask,
+                        fd_table: vec![
+                            // 0 -> stdin
+                            Some(Arc::new(Stdin)),
+                            // 1 -> stdout
+                            Some(Arc::new(Stdout)),
+                            // 2 -> stderr
+                            Some(Arc::new(Stdout)),
+                        ],
+                        fd_cloexec: BTreeSet::new(),
+                        perf_counters: PerfCounters::default(),
+                        mail_box: Arc::new(MailBox::new()),
+                    }),
+                })
+            );
             add_task_2_map(task_control_block.getpid(), task_control_block.clone());
             parent_inner.children.push(task_control_block.clone());
             let trap_cx = task_control_block.acquire_inner_lock().get_trap_cx();
@@ -385,5 +632,19 @@ impl Ord for TaskControlBlock {
 pub enum TaskStatus {
     Ready,
     Running,
+    /// Waiting to be woken by `wake_task`.
+    Blocked,
     Zombie,
 }
+
+/// Why a task is Blocked, for diagnosing hangs.
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BlockReason {
+    StdinRead,
+    /// Until the given tick
+    Sleep(usize),
+    WaitChild(usize),
+    /// Identified by its address
+    Mutex(usize),
+}
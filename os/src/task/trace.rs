@@ -0,0 +1,107 @@
+use super::hart_id;
+use crate::config::CPU_NUM;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use lazy_static::*;
+use spin::Mutex;
+
+/// Events kept per hart, older ones are dropped.
+pub const SCHED_TRACE_SIZE: usize = 256;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SchedEventKind {
+    Add,
+    Fetch,
+    Migrate,
+    Block,
+    Wake,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct SchedEvent {
+    /// Position in the global order of events
+    pub seq: u64,
+    pub hart: usize,
+    pub pid: usize,
+    pub kind: SchedEventKind,
+}
+
+/// A ring buffer of scheduler events per hart. Every event is stamped from
+/// one global counter, so the buffers can be merged into a single log.
+pub struct SchedTrace {
+    seq: AtomicU64,
+    harts: Vec<Mutex<VecDeque<SchedEvent>>>,
+}
+
+lazy_static! {
+    pub static ref SCHED_TRACE: SchedTrace = SchedTrace::new();
+}
+
+impl SchedTrace {
+    pub fn new() -> Self {
+        Self {
+            seq: AtomicU64::new(0),
+            harts: (0..CPU_NUM)
+                .map(|_| Mutex::new(VecDeque::with_capacity(SCHED_TRACE_SIZE)))
+                .collect(),
+        }
+    }
+
+    /// Record an event on the calling hart.
+    pub fn record(&self, pid: usize, kind: SchedEventKind) {
+        self.record_on(hart_id(), pid, kind);
+    }
+
+    pub fn record_on(&self, hart: usize, pid: usize, kind: SchedEventKind) {
+        let mut ring = self.harts[hart].lock();
+        // stamped under the ring lock, so every ring is in sequence order
+        let seq = self.seq.fetch_add(1, Ordering::Relaxed);
+        if ring.len() == SCHED_TRACE_SIZE {
+            ring.pop_front();
+        }
+        ring.push_back(SchedEvent {
+            seq,
+            hart,
+            pid,
+            kind,
+        });
+    }
+
+    /// All buffered events of all harts in the order they happened.
+    #[allow(dead_code)]
+    pub fn merge_traces(&self) -> Vec<SchedEvent> {
+        let mut events: Vec<SchedEvent> = Vec::new();
+        for ring in self.harts.iter() {
+            events.extend(ring.lock().iter());
+        }
+        events.sort_unstable_by_key(|event| event.seq);
+        events
+    }
+}
+
+#[allow(unused)]
+pub fn sched_trace_test() {
+    let trace = SchedTrace::new();
+    let recorded = [
+        (0, 1, SchedEventKind::Add),
+        (1, 2, SchedEventKind::Add),
+        (1, 1, SchedEventKind::Fetch),
+        (0, 2, SchedEventKind::Fetch),
+        (0, 2, SchedEventKind::Block),
+        (1, 2, SchedEventKind::Wake),
+        (1, 2, SchedEventKind::Migrate),
+    ];
+    for &(hart, pid, kind) in recorded.iter() {
+        trace.record_on(hart, pid, kind);
+    }
+    let merged = trace.merge_traces();
+    assert_eq!(merged.len(), recorded.len());
+    for pair in merged.windows(2) {
+        assert!(pair[0].seq < pair[1].seq);
+    }
+    for (event, &(hart, pid, kind)) in merged.iter().zip(recorded.iter()) {
+        assert_eq!((event.hart, event.pid, event.kind), (hart, pid, kind));
+    }
+    debug!("sched_trace_test passed!");
+}
@@ -8,9 +8,13 @@ use spin::Mutex;
 const TICKS_PER_SEC: usize = 100;
 const MSEC_PER_SEC: usize = 1000;
 pub const USEC_PER_SEC: usize = 1_000_000;
+pub const NSEC_PER_SEC: usize = 1_000_000_000;
+
+pub const CLOCK_REALTIME: usize = 0;
+pub const CLOCK_MONOTONIC: usize = 1;
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone)]
 pub struct TimeVal {
     pub sec: usize,
     pub usec: usize,
@@ -21,6 +25,111 @@ impl TimeVal {
     pub fn new() -> Self {
         TimeVal { sec: 0, usec: 0 }
     }
+    /// The length of `ticks` scheduler ticks.
+    pub fn from_ticks(ticks: usize) -> Self {
+        TimeVal {
+            sec: ticks / TICKS_PER_SEC,
+            usec: ticks % TICKS_PER_SEC * (USEC_PER_SEC / TICKS_PER_SEC),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct TimeSpec {
+    pub sec: usize,
+    pub nsec: usize,
+}
+
+pub const ADJ_OFFSET: u32 = 0x0001;
+pub const ADJ_FREQUENCY: u32 = 0x0002;
+
+/// Our own take on `struct timex`, not the Linux layout, which is why the
+/// calls taking it have numbers of their own. `offset` is in nanoseconds
+/// and steps the clock at once, and `freq` is in parts per billion.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Timex {
+    pub modes: u32,
+    pub offset: i64,
+    pub freq: i64,
+}
+
+/// Corrections applied on top of `mtime` to get `CLOCK_REALTIME`.
+pub struct SoftwareClock {
+    inner: Mutex<ClockCorrection>,
+}
+
+struct ClockCorrection {
+    /// Nanoseconds added to `mtime`, including what earlier frequencies
+    /// made up before `since`
+    offset_ns: i64,
+    freq_ppb: i64,
+    /// `mtime` ticks from which `freq_ppb` applies
+    since: usize,
+}
+
+impl ClockCorrection {
+    /// What `freq_ppb` made up from `since` to `ticks`.
+    fn drift(&self, ticks: usize) -> i64 {
+        let elapsed = ticks_to_ns(ticks.saturating_sub(self.since)) as i128;
+        (elapsed * self.freq_ppb as i128 / NSEC_PER_SEC as i128) as i64
+    }
+}
+
+impl SoftwareClock {
+    pub const fn new() -> Self {
+        Self {
+            inner: Mutex::new(ClockCorrection {
+                offset_ns: 0,
+                freq_ppb: 0,
+                since: 0,
+            }),
+        }
+    }
+    /// Apply the adjustments selected by `timex.modes` at `ticks` of
+    /// `mtime`, then fill in the current ones. A new frequency only applies
+    /// from `ticks` on.
+    pub fn adjtime(&self, timex: &mut Timex, ticks: usize) {
+        let mut inner = self.inner.lock();
+        if timex.modes & ADJ_OFFSET != 0 {
+            inner.offset_ns = inner.offset_ns.saturating_add(timex.offset);
+        }
+        if timex.modes & ADJ_FREQUENCY != 0 {
+            let drift = inner.drift(ticks);
+            inner.offset_ns = inner.offset_ns.saturating_add(drift);
+            inner.since = ticks;
+            inner.freq_ppb = timex.freq;
+        }
+        timex.offset = inner.offset_ns;
+        timex.freq = inner.freq_ppb;
+    }
+    /// Corrected time in nanoseconds after `ticks` of `mtime`.
+    pub fn realtime_ns(&self, ticks: usize) -> i64 {
+        let inner = self.inner.lock();
+        (ticks_to_ns(ticks) as i64)
+            .saturating_add(inner.drift(ticks))
+            .saturating_add(inner.offset_ns)
+    }
+}
+
+pub static SOFTWARE_CLOCK: SoftwareClock = SoftwareClock::new();
+
+fn ticks_to_ns(ticks: usize) -> usize {
+    (ticks as u128 * NSEC_PER_SEC as u128 / CLOCK_FREQ as u128) as usize
+}
+
+/// Read `clock_id`. Only `CLOCK_REALTIME` sees `SOFTWARE_CLOCK` corrections.
+pub fn clock_gettime(clock_id: usize) -> Option<TimeSpec> {
+    let ns = match clock_id {
+        CLOCK_REALTIME => SOFTWARE_CLOCK.realtime_ns(time::read()).max(0) as usize,
+        CLOCK_MONOTONIC => ticks_to_ns(time::read()),
+        _ => return None,
+    };
+    Some(TimeSpec {
+        sec: ns / NSEC_PER_SEC,
+        nsec: ns % NSEC_PER_SEC,
+    })
 }
 
 #[allow(unused_variables)]
@@ -68,3 +177,37 @@ pub fn set_virtual_timer(time: usize, pid: usize) {
         }
     }
 }
+
+#[allow(unused)]
+pub fn software_clock_test() {
+    let clock = SoftwareClock::new();
+    let mut timex = Timex {
+        modes: ADJ_FREQUENCY,
+        freq: 500_000,
+        ..Default::default()
+    };
+    clock.adjtime(&mut timex, 0);
+    let raw = ticks_to_ns(CLOCK_FREQ) as i64;
+    assert_eq!(raw, NSEC_PER_SEC as i64);
+    // 500 ppm is 500 us per second
+    assert_eq!(clock.realtime_ns(CLOCK_FREQ) - raw, 500_000);
+    let mut timex = Timex {
+        modes: ADJ_OFFSET,
+        offset: -1_000,
+        ..Default::default()
+    };
+    clock.adjtime(&mut timex, CLOCK_FREQ);
+    assert_eq!((timex.offset, timex.freq), (-1_000, 500_000));
+    assert_eq!(clock.realtime_ns(CLOCK_FREQ) - raw, 499_000);
+    // a new frequency keeps what the old one made up so far
+    let mut timex = Timex {
+        modes: ADJ_FREQUENCY,
+        freq: -1_000_000,
+        ..Default::default()
+    };
+    clock.adjtime(&mut timex, CLOCK_FREQ);
+    assert_eq!((timex.offset, timex.freq), (499_000, -1_000_000));
+    assert_eq!(clock.realtime_ns(CLOCK_FREQ) - raw, 499_000);
+    assert_eq!(clock.realtime_ns(3 * CLOCK_FREQ) - 3 * raw, -1_501_000);
+    debug!("software_clock_test passed!");
+}
@@ -37,4 +37,16 @@ impl TrapContext {
         cx.set_sp(sp);
         cx
     }
+    /// The registers of a context read back from a checkpoint, made to
+    /// return to user mode and to trap into this kernel.
+    pub fn from_checkpoint(
+        saved: &TrapContext,
+        kernel_satp: usize,
+        kernel_sp: usize,
+        trap_handler: usize,
+    ) -> Self {
+        let mut cx = Self::app_init_context(saved.sepc, 0, kernel_satp, kernel_sp, trap_handler);
+        cx.x = saved.x;
+        cx
+    }
 }
@@ -2,11 +2,15 @@ mod context;
 mod usertrap;
 
 use crate::config::{TRAMPOLINE, TRAP_CONTEXT};
+use crate::console_blog::refill_output_rate;
+use crate::mm::{reclaim, AccessType};
+use crate::perf::{count_event, PERF_COUNT_SW_PAGE_FAULTS};
 use crate::plic;
 use crate::sbi::set_timer;
 use crate::syscall::syscall;
 use crate::task::{
     current_task, current_trap_cx, current_user_token, exit_current_and_run_next, hart_id,
+    kernel_stack_guard_owner, kernel_stack_position, preempt_tick, scheduler_tick,
     suspend_current_and_run_next,
 };
 use crate::timer::{set_next_trigger, TIMER_MAP};
@@ -59,10 +63,14 @@ pub fn trap_handler() -> ! {
             cx.sepc += 4;
             let id = cx.x[17];
             // get system call return value
-            let result = syscall(cx.x[17], [cx.x[10], cx.x[11], cx.x[12]]);
+            let result = syscall(
+                cx.x[17],
+                [cx.x[10], cx.x[11], cx.x[12], cx.x[13], cx.x[14], cx.x[15]],
+            );
             // cx is changed during sys_exec, so we have to call it again
             // cx = current_trap_cx();
-            if id != 221 || result != 0 {
+            // a successful sys_restore has freed it and set up another
+            if (id != 221 && id != 612) || result != 0 {
                 cx.x[10] = result as usize;
             }
         }
@@ -72,14 +80,36 @@ pub fn trap_handler() -> ! {
         | Trap::Exception(Exception::InstructionPageFault)
         | Trap::Exception(Exception::LoadFault)
         | Trap::Exception(Exception::LoadPageFault) => {
-            error!(
-                "[kernel] {:?} in application, bad addr = {:#x}, bad instruction = {:#x}, core dumped.",
-                scause.cause(),
-                stval,
-                current_trap_cx().sepc,
-            );
-            // page fault exit code
-            exit_current_and_run_next(-2);
+            let access = AccessType::from_trap(scause.cause()).unwrap();
+            let resolved = {
+                let task = current_task().unwrap();
+                count_event(
+                    &task.acquire_inner_lock().perf_counters,
+                    PERF_COUNT_SW_PAGE_FAULTS,
+                );
+                let handle_fault = || {
+                    let mut inner = task.acquire_inner_lock();
+                    inner.memory_set.handle_fault(stval.into(), access)
+                };
+                // out of frames, try again once some are swapped out
+                handle_fault().or_else(|report| {
+                    if reclaim() > 0 {
+                        handle_fault()
+                    } else {
+                        Err(report)
+                    }
+                })
+            };
+            if let Err(report) = resolved {
+                error!(
+                    "[kernel] {:?} in application, {}, bad instruction = {:#x}, core dumped.",
+                    scause.cause(),
+                    report,
+                    current_trap_cx().sepc,
+                );
+                // page fault exit code
+                exit_current_and_run_next(-2);
+            }
         }
         Trap::Exception(Exception::IllegalInstruction) => {
             error!("[kernel] IllegalInstruction in application, core dumped.");
@@ -100,8 +130,11 @@ pub fn trap_handler() -> ! {
                 drop(timer_map);
                 if pid == 0 {
                     set_next_trigger();
+                    refill_output_rate();
                     trace!("kernel tick");
-                    suspend_current_and_run_next();
+                    if scheduler_tick() && preempt_tick() {
+                        suspend_current_and_run_next();
+                    }
                 } else if pid == current_task().unwrap().pid.0 {
                     unsafe {
                         sip::set_utimer();
@@ -160,8 +193,50 @@ pub fn trap_return() -> ! {
     panic!("Unreachable in back_to_user!");
 }
 
+/// Registers saved by `kernelvec`, each at its number times 8 except sp.
+const FRAME_RA: usize = 0;
+const FRAME_SP: usize = 1;
+const FRAME_S0: usize = 7;
+
+/// Print the return addresses up the frame pointer chain from `fp`, while
+/// the frames lie within `[bottom, top)`. Relies on `-Cforce-frame-pointers`,
+/// which keeps ra at fp - 8 and the caller's fp at fp - 16.
+fn print_backtrace(mut fp: usize, bottom: usize, top: usize) {
+    for depth in 0..64 {
+        if fp < bottom + 16 || fp > top || fp % 8 != 0 {
+            return;
+        }
+        let (ra, prev_fp) = unsafe { (*((fp - 8) as *const usize), *((fp - 16) as *const usize)) };
+        println!("  #{} {:#x}", depth + 1, ra);
+        if prev_fp <= fp {
+            return;
+        }
+        fp = prev_fp;
+    }
+}
+
+/// Report a fault in the guard page below a kernel stack as an overflow
+/// of it, with a backtrace, rather than as an unsupported trap.
+fn check_kernel_stack_overflow(frame: &[usize; 32], stval: usize, sepc: usize) {
+    let sp = frame[FRAME_SP];
+    let pid = match kernel_stack_guard_owner(stval).or_else(|| kernel_stack_guard_owner(sp)) {
+        Some(pid) => pid,
+        None => return,
+    };
+    error!(
+        "kernel stack overflow in task {}, stval = {:#x}, sepc = {:#x}, sp = {:#x}",
+        pid, stval, sepc, sp
+    );
+    let (bottom, top) = kernel_stack_position(pid);
+    println!("backtrace:");
+    println!("  #0 {:#x}", sepc);
+    println!("  ra {:#x}", frame[FRAME_RA]);
+    print_backtrace(frame[FRAME_S0], bottom, top);
+    panic!("kernel stack overflow in task {}", pid);
+}
+
 #[no_mangle]
-pub extern "C" fn trap_from_kernel() {
+pub extern "C" fn trap_from_kernel(frame: &[usize; 32]) {
     let scause = scause::read();
     let stval = stval::read();
     let sepc = sepc::read();
@@ -185,6 +260,9 @@ pub extern "C" fn trap_from_kernel() {
             debug!("SupervisorSoft");
         }
         _ => {
+            if let Trap::Exception(_) = scause.cause() {
+                check_kernel_stack_overflow(frame, stval, sepc);
+            }
             error!(
                 "Unsupported trap {:?}, stval = {:#x}, sepc = {:#x}, sstatus = {:#x?}!",
                 scause.cause(),
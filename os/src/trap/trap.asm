@@ -39,17 +39,30 @@ __alltraps:
     ld t1, 36*8(sp)
     # move to kernel_sp
     ld sp, 35*8(sp)
-    # switch to kernel space
+    # switch to kernel space; a user space sharing its ASID leaves entries
+    # behind that have to be fenced, the others are kept apart by their ASID
+    csrr t2, satp
     csrw satp, t0
-    sfence.vma
+    srli t2, t2, 44
+    slli t2, t2, 48
+    srli t2, t2, 48
+    bnez t2, 1f
+    sfence.vma zero, t2
+1:
     # jump to trap_handler
     jr t1
 
 __restore:
     # a0: *TrapContext in user space(Constant); a1: user space token
-    # switch to user space
+    # switch to user space, fencing the kernel entries only if it shares
+    # their ASID
     csrw satp, a1
-    sfence.vma
+    srli t0, a1, 44
+    slli t0, t0, 48
+    srli t0, t0, 48
+    bnez t0, 1f
+    sfence.vma zero, t0
+1:
     csrw sscratch, a0
     mv sp, a0
     # now sp points to TrapContext in user space, start restoring based on it
@@ -83,11 +96,26 @@ __restore:
 .globl kernelvec
 .align 4
 kernelvec:
+        csrw sscratch, sp
+        csrr sp, scause
+        bltz sp, 1f
+        // an exception in the kernel is fatal, and may come from running
+        // off the bottom of a kernel stack, where nothing can be pushed.
+        // take it on this hart's trap stack, tp being borrowed to index it.
+        la sp, trap_stack
+        addi tp, tp, 1
+        slli tp, tp, 14
+        add sp, sp, tp
+        srli tp, tp, 14
+        addi tp, tp, -1
+        j 2f
+1:
+        csrr sp, sscratch
+2:
         // make room to save registers.
         addi sp, sp, -256
-        // save the registers.
+        // save the registers, sp as it was before the trap.
         sd ra, 0(sp)
-        sd sp, 8(sp)
         sd gp, 16(sp)
         sd tp, 24(sp)
         sd t0, 32(sp)
@@ -117,14 +145,18 @@ kernelvec:
         sd t4, 224(sp)
         sd t5, 232(sp)
         sd t6, 240(sp)
+        // sscratch is only used by traps from user space, where it is
+        // set again before returning
+        csrr t0, sscratch
+        sd t0, 8(sp)
 
 	// call the C trap handler in trap.c
+        mv a0, sp
         call trap_from_kernel
 
 kernelret:
         // restore registers.
         ld ra, 0(sp)
-        ld sp, 8(sp)
         ld gp, 16(sp)
         // not this, in case we moved CPUs: ld tp, 24(sp)
         ld t0, 32(sp)
@@ -155,7 +187,7 @@ kernelret:
         ld t5, 232(sp)
         ld t6, 240(sp)
 
-        addi sp, sp, 256
+        ld sp, 8(sp)
 
         // return to whatever we were doing in the kernel.
         sret
\ No newline at end of file
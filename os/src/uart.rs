@@ -1,4 +1,7 @@
-use crate::console_blog::{IN_BUFFER, OUT_BUFFER};
+use crate::boot::machine;
+use crate::config::PAGE_SIZE;
+use crate::console_blog::{receive_stdin, OUT_BUFFER};
+use crate::mm::{kernel_ioremap, MapPermission};
 use alloc::sync::Arc;
 use lazy_static::*;
 use spin::Mutex;
@@ -9,16 +12,30 @@ use uart8250::{InterruptType, MmioUart8250};
 #[cfg(feature = "board_lrv")]
 use uart_xilinx::uart_16550::{InterruptType, MmioUartAxi16550};
 
+/// The interrupt of the UART the kernel drives, which tells it apart from
+/// the others in the device tree.
+#[cfg(feature = "board_qemu")]
+const UART_IRQ: usize = 12;
+
+#[cfg(any(feature = "board_lrv", feature = "board_lrv_uartlite"))]
+const UART_IRQ: usize = 4;
+
+fn uart_base() -> usize {
+    machine()
+        .uart(UART_IRQ)
+        .expect("no UART for the kernel in the device tree")
+}
+
 #[cfg(feature = "board_qemu")]
 lazy_static! {
     pub static ref UART: Arc<Mutex<MmioUart8250<'static>>> =
-        Arc::new(Mutex::new(MmioUart8250::new(0x1000_0200)));
+        Arc::new(Mutex::new(MmioUart8250::new(uart_base())));
 }
 
 #[cfg(feature = "board_lrv")]
 lazy_static! {
     pub static ref UART: Arc<Mutex<MmioUartAxi16550<'static>>> =
-        Arc::new(Mutex::new(MmioUartAxi16550::new(0x6000_1000)));
+        Arc::new(Mutex::new(MmioUartAxi16550::new(uart_base())));
 }
 
 #[cfg(feature = "board_lrv_uartlite")]
@@ -27,11 +44,17 @@ use uart_xilinx::MmioUartAxiLite;
 #[cfg(feature = "board_lrv_uartlite")]
 lazy_static! {
     pub static ref UART: Arc<Mutex<MmioUartAxiLite<'static>>> =
-        Arc::new(Mutex::new(MmioUartAxiLite::new(0x6000_1000)));
+        Arc::new(Mutex::new(MmioUartAxiLite::new(uart_base())));
+}
+
+/// Map the registers of the UART, before anything touches them.
+fn map_registers() {
+    kernel_ioremap(uart_base(), PAGE_SIZE, MapPermission::R | MapPermission::W).unwrap();
 }
 
 #[cfg(any(feature = "board_qemu", feature = "board_lrv"))]
 pub fn init() {
+    map_registers();
     let uart = UART.lock();
     uart.write_ier(0);
     let _ = uart.read_msr();
@@ -39,10 +62,13 @@ pub fn init() {
     uart.init(100_000_000, 115200);
     // Rx FIFO trigger level=14, reset Rx & Tx FIFO, enable FIFO
     uart.write_fcr(0b11_000_11_1);
+    // input is only ever read from the Rx interrupt
+    uart.enable_received_data_available_interrupt();
 }
 
 #[cfg(feature = "board_lrv_uartlite")]
 pub fn init() {
+    map_registers();
     UART.lock().enable_interrupt();
 }
 
@@ -56,10 +82,7 @@ pub fn handle_interrupt() {
         match int_type {
             InterruptType::ReceivedDataAvailable | InterruptType::Timeout => {
                 trace!("Received data available");
-                let mut stdin = IN_BUFFER.lock();
-                while let Some(ch) = uart.read_byte() {
-                    stdin.push_back(ch);
-                }
+                receive_stdin(|| uart.read_byte());
             }
             InterruptType::TransmitterHoldingRegisterEmpty => {
                 trace!("TransmitterHoldingRegisterEmpty");
@@ -104,13 +127,13 @@ pub fn handle_interrupt() {
         }
     }
     if status.contains(Status::RX_FIFO_FULL) {
-        let mut stdin = IN_BUFFER.lock();
-        for _ in 0..FIFO_DEPTH {
-            if let Some(ch) = uart.read_byte() {
-                stdin.push_back(ch);
-            } else {
-                break;
+        let mut budget = FIFO_DEPTH;
+        receive_stdin(|| {
+            if budget == 0 {
+                return None;
             }
-        }
+            budget -= 1;
+            uart.read_byte()
+        });
     }
 }
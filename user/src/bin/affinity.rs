@@ -0,0 +1,44 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    exit, fork, sched_getaffinity, sched_setaffinity, setuid, waitpid, yield_, EINVAL, EPERM, ESRCH,
+};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut online = 0;
+    assert_eq!(sched_getaffinity(0, &mut online), 8);
+    assert_ne!(online & 1, 0);
+    // harts that are not there are dropped, and some hart must be left
+    assert_eq!(sched_setaffinity(0, 1 | 1 << 40), 0);
+    let mut mask = 0;
+    sched_getaffinity(0, &mut mask);
+    assert_eq!(mask, 1);
+    assert_eq!(sched_setaffinity(0, 1 << 40), -EINVAL);
+    assert_eq!(sched_setaffinity(1 << 20, 1), -ESRCH);
+    for _ in 0..10 {
+        yield_();
+    }
+
+    // children inherit the mask, and leave other users alone
+    let child = fork();
+    if child == 0 {
+        let mut mask = 0;
+        sched_getaffinity(0, &mut mask);
+        assert_eq!(mask, 1);
+        assert_eq!(setuid(1000), 0);
+        assert_eq!(sched_setaffinity(1, 1), -EPERM);
+        assert_eq!(sched_setaffinity(0, online), 0);
+        exit(0);
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(child as usize, &mut exit_code), child);
+    assert_eq!(exit_code, 0);
+    assert_eq!(sched_setaffinity(0, online), 0);
+    println!("affinity passed!");
+    0
+}
@@ -0,0 +1,32 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::slice;
+use user_lib::{close, pipe, read, write};
+
+const EFAULT: isize = 14;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut pipe_fd = [0usize; 2];
+    assert_eq!(pipe(&mut pipe_fd), 0);
+    // kernel memory, and the unmapped null page
+    let kernel = unsafe { slice::from_raw_parts(0x8020_0000 as *const u8, 16) };
+    assert_eq!(write(pipe_fd[1], kernel), -EFAULT);
+    let null = unsafe { slice::from_raw_parts(0 as *const u8, 16) };
+    assert_eq!(write(pipe_fd[1], null), -EFAULT);
+    // reading into our own code is refused before any data is taken
+    assert_eq!(write(pipe_fd[1], b"x"), 1);
+    let text = unsafe { slice::from_raw_parts_mut(main as usize as *mut u8, 1) };
+    assert_eq!(read(pipe_fd[0], text), -EFAULT);
+    let mut buf = [0u8; 1];
+    assert_eq!(read(pipe_fd[0], &mut buf), 1);
+    assert_eq!(&buf, b"x");
+    close(pipe_fd[0]);
+    close(pipe_fd[1]);
+    println!("bad_buffer passed!");
+    0
+}
@@ -0,0 +1,29 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{brk, sbrk};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let base = brk(0);
+    assert!(base > 0);
+    // grow by a bit more than two pages and use all of it
+    let len = 2 * 4096 + 100;
+    assert_eq!(sbrk(len), base);
+    assert_eq!(brk(0), base + len);
+    let heap = unsafe { core::slice::from_raw_parts_mut(base as *mut u8, len as usize) };
+    for (i, b) in heap.iter_mut().enumerate() {
+        *b = i as u8;
+    }
+    assert!(heap.iter().enumerate().all(|(i, b)| *b == i as u8));
+    // give it back, then fail to go below the start
+    assert_eq!(sbrk(-len), base + len);
+    assert_eq!(brk(0), base);
+    assert_eq!(sbrk(-1), -1);
+    assert_eq!(brk(0), base);
+    println!("brk passed!");
+    0
+}
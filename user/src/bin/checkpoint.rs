@@ -0,0 +1,43 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{checkpoint, close, open, restore, OpenFlags};
+
+static mut STAGE: usize = 0;
+
+fn stage() -> usize {
+    unsafe { core::ptr::read_volatile(&STAGE) }
+}
+
+fn set_stage(stage: usize) {
+    unsafe { core::ptr::write_volatile(&mut STAGE, stage) }
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let fd = open(
+        "/tmp/checkpoint\0",
+        OpenFlags::CREATE | OpenFlags::RDWR | OpenFlags::TRUNC,
+    );
+    assert!(fd >= 0);
+    let fd = fd as usize;
+    // a file without a checkpoint in it is refused
+    assert!(restore(fd) < 0);
+    set_stage(1);
+    match checkpoint(fd) {
+        0 => {
+            // memory changed after the checkpoint is rolled back
+            set_stage(2);
+            restore(fd);
+            panic!("restore returned");
+        }
+        1 => assert_eq!(stage(), 1),
+        err => panic!("checkpoint failed: {}", err),
+    }
+    close(fd);
+    println!("checkpoint passed!");
+    0
+}
@@ -0,0 +1,65 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    clock_adjtime, clock_gettime, exit, fork, ntp_adjtime, setuid, waitpid, TimeSpec, Timex,
+    ADJ_FREQUENCY, ADJ_OFFSET, CLOCK_MONOTONIC, CLOCK_REALTIME, EPERM,
+};
+
+/// CLOCK_REALTIME minus CLOCK_MONOTONIC, in nanoseconds.
+fn realtime_lead() -> i64 {
+    let (mut real, mut mono) = (TimeSpec::default(), TimeSpec::default());
+    assert_eq!(clock_gettime(CLOCK_MONOTONIC, &mut mono), 0);
+    assert_eq!(clock_gettime(CLOCK_REALTIME, &mut real), 0);
+    real.as_nanos() - mono.as_nanos()
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut timex = Timex::default();
+    assert_eq!(ntp_adjtime(&mut timex), 0);
+    let (old_offset, old_freq) = (timex.offset, timex.freq);
+
+    // step the clock one second ahead
+    let before = realtime_lead();
+    let mut timex = Timex {
+        modes: ADJ_OFFSET,
+        offset: 1_000_000_000,
+        ..Default::default()
+    };
+    assert_eq!(clock_adjtime(CLOCK_REALTIME, &mut timex), 0);
+    assert_eq!(timex.offset, old_offset + 1_000_000_000);
+    let after = realtime_lead();
+    assert!(after - before >= 1_000_000_000);
+    assert!(after - before < 1_001_000_000);
+
+    // the monotonic clock can not be adjusted
+    assert!(clock_adjtime(CLOCK_MONOTONIC, &mut Timex::default()) < 0);
+
+    // only root may adjust the clock, anyone may read it
+    let child = fork();
+    if child == 0 {
+        assert_eq!(setuid(1000), 0);
+        let mut timex = Timex::default();
+        assert_eq!(ntp_adjtime(&mut timex), 0);
+        timex.modes = ADJ_FREQUENCY;
+        assert_eq!(ntp_adjtime(&mut timex), -EPERM);
+        exit(0);
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(child as usize, &mut exit_code), child);
+    assert_eq!(exit_code, 0);
+
+    let mut timex = Timex {
+        modes: ADJ_OFFSET | ADJ_FREQUENCY,
+        offset: -1_000_000_000,
+        freq: old_freq,
+    };
+    assert_eq!(ntp_adjtime(&mut timex), 0);
+    assert_eq!(timex.offset, old_offset);
+    println!("clock_adjtime passed!");
+    0
+}
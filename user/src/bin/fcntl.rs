@@ -0,0 +1,87 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, exec, fcntl, fork, open, pipe, read, waitpid, write, OpenFlags, EAGAIN, EBADF,
+    FD_CLOEXEC, F_DUPFD, F_DUPFD_CLOEXEC, F_GETFD, F_GETFL, F_SETFD, F_SETFL,
+};
+
+/// Survives the exec, tells the new image it is the child.
+const KEPT_FD: usize = 20;
+/// Marked close-on-exec.
+const CLOEXEC_FD: usize = 21;
+
+/// Runs after `exec`: only the fd without `FD_CLOEXEC` is left.
+fn after_exec() -> i32 {
+    let mut buf = [0u8; 4];
+    assert_eq!(read(CLOEXEC_FD, &mut buf), -EBADF);
+    assert_eq!(fcntl(CLOEXEC_FD, F_GETFD, 0), -EBADF);
+    assert_eq!(fcntl(KEPT_FD, F_GETFD, 0), 0);
+    close(KEPT_FD);
+    0
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    if fcntl(KEPT_FD, F_GETFD, 0) >= 0 {
+        return after_exec();
+    }
+
+    // O_NONBLOCK on an empty pipe
+    let mut pipe_fd = [0usize; 2];
+    assert_eq!(pipe(&mut pipe_fd), 0);
+    let (read_fd, write_fd) = (pipe_fd[0], pipe_fd[1]);
+    assert_eq!(
+        fcntl(read_fd, F_SETFL, OpenFlags::NONBLOCK.bits() as usize),
+        0
+    );
+    assert!(fcntl(read_fd, F_GETFL, 0) as u32 & OpenFlags::NONBLOCK.bits() != 0);
+    let mut buf = [0u8; 8];
+    assert_eq!(read(read_fd, &mut buf), -EAGAIN);
+    assert_eq!(write(write_fd, b"abc"), 3);
+    assert_eq!(read(read_fd, &mut buf), 3);
+    assert_eq!(&buf[..3], b"abc");
+
+    // F_DUPFD picks the lowest free fd not below arg, sharing the file
+    let dup_fd = fcntl(write_fd, F_DUPFD, 10);
+    assert_eq!(dup_fd, 10);
+    assert_eq!(fcntl(write_fd, F_DUPFD, 10), 11);
+    assert_eq!(write(dup_fd as usize, b"d"), 1);
+    assert_eq!(read(read_fd, &mut buf), 1);
+    assert_eq!(buf[0], b'd');
+    assert_eq!(fcntl(dup_fd as usize, F_GETFD, 0), 0);
+    let cloexec_dup_fd = fcntl(write_fd, F_DUPFD_CLOEXEC, 0);
+    assert!(cloexec_dup_fd >= 0);
+    assert_eq!(
+        fcntl(cloexec_dup_fd as usize, F_GETFD, 0),
+        FD_CLOEXEC as isize
+    );
+    for fd in [read_fd, write_fd, 10, 11, cloexec_dup_fd as usize].iter() {
+        close(*fd);
+    }
+    assert_eq!(fcntl(read_fd, F_GETFL, 0), -EBADF);
+
+    // FD_CLOEXEC is honoured across exec
+    let fd = open("/tmp/fcntl\0", OpenFlags::CREATE | OpenFlags::RDWR);
+    assert!(fd >= 0);
+    assert_eq!(fcntl(fd as usize, F_DUPFD, KEPT_FD), KEPT_FD as isize);
+    assert_eq!(fcntl(fd as usize, F_DUPFD, CLOEXEC_FD), CLOEXEC_FD as isize);
+    close(fd as usize);
+    assert_eq!(fcntl(CLOEXEC_FD, F_SETFD, FD_CLOEXEC), 0);
+    assert_eq!(fcntl(CLOEXEC_FD, F_GETFD, 0), FD_CLOEXEC as isize);
+    let pid = fork();
+    if pid == 0 {
+        exec("fcntl\0", &[core::ptr::null::<u8>()]);
+        unreachable!();
+    }
+    close(KEPT_FD);
+    close(CLOEXEC_FD);
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    println!("fcntl passed!");
+    0
+}
@@ -0,0 +1,46 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    exit, fork, get_time, getrusage, waitpid, RUsage, EINVAL, RUSAGE_CHILDREN, RUSAGE_SELF,
+};
+
+fn user_time(who: isize) -> usize {
+    let mut usage = RUsage::default();
+    assert_eq!(getrusage(who, &mut usage), 0);
+    assert_eq!(usage.stime.sec + usage.stime.usec, 0);
+    usage.utime.sec * 1_000_000 + usage.utime.usec
+}
+
+fn spin(ms: isize) {
+    let start = get_time();
+    while get_time() - start < ms {}
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut usage = RUsage::default();
+    assert_eq!(getrusage(1, &mut usage), -EINVAL);
+    assert_eq!(user_time(RUSAGE_CHILDREN), 0);
+
+    spin(200);
+    let own = user_time(RUSAGE_SELF);
+    assert!(own > 0);
+
+    // a child's time counts once it has been waited for
+    let child = fork();
+    if child == 0 {
+        assert_eq!(user_time(RUSAGE_SELF), 0);
+        spin(200);
+        exit(0);
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(child as usize, &mut exit_code), child);
+    assert!(user_time(RUSAGE_CHILDREN) > 0);
+    assert!(user_time(RUSAGE_SELF) >= own);
+    println!("getrusage passed!");
+    0
+}
@@ -0,0 +1,34 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::heap_stats;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut buf = [0u8; 512];
+    let len = heap_stats(&mut buf);
+    assert!(len > 0);
+    let stats = core::str::from_utf8(&buf[..len as usize]).unwrap();
+    print!("{}", stats);
+    let mut lines = stats.lines();
+    let live: usize = lines
+        .next()
+        .and_then(|line| line.strip_prefix("live "))
+        .unwrap()
+        .parse()
+        .unwrap();
+    // per-tag sizes add up to the total
+    let tagged: usize = lines
+        .map(|line| line.rsplit(' ').next().unwrap().parse::<usize>().unwrap())
+        .sum();
+    assert_eq!(tagged, live);
+    // a short buffer is filled up to its end
+    let mut short = [0u8; 3];
+    assert_eq!(heap_stats(&mut short), 3);
+    assert_eq!(&short, b"liv");
+    println!("heap_stats passed!");
+    0
+}
@@ -0,0 +1,45 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    exit, fork, getegid, geteuid, getgid, getpid, getppid, getuid, setgid, setuid, waitpid, EPERM,
+};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let pid = getpid();
+    assert_eq!(getuid(), 0);
+    assert_eq!(getgid(), 0);
+
+    // the child reports its own pid, which must match what fork returned
+    let child = fork();
+    if child == 0 {
+        assert_eq!(getppid(), pid);
+        exit(getpid() as i32);
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(child as usize, &mut exit_code), child);
+    assert_eq!(exit_code as isize, child);
+
+    // a process that dropped root can not get it back
+    let child = fork();
+    if child == 0 {
+        assert_eq!(setgid(100), 0);
+        assert_eq!(setuid(1000), 0);
+        assert_eq!(getuid(), 1000);
+        assert_eq!(geteuid(), 1000);
+        assert_eq!(getgid(), 100);
+        assert_eq!(getegid(), 100);
+        assert_eq!(setuid(0), -EPERM);
+        assert_eq!(setgid(0), -EPERM);
+        assert_eq!(getuid(), 1000);
+        exit(0);
+    }
+    assert_eq!(waitpid(child as usize, &mut exit_code), child);
+    assert_eq!(exit_code, 0);
+    println!("identity passed!");
+    0
+}
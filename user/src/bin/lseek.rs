@@ -0,0 +1,38 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, lseek, open, read, write, OpenFlags, EINVAL, ENXIO, EOVERFLOW, SEEK_CUR, SEEK_DATA,
+    SEEK_END, SEEK_HOLE, SEEK_SET,
+};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let fd = open("/tmp/lseek\0", OpenFlags::CREATE | OpenFlags::RDWR);
+    assert!(fd >= 0);
+    let fd = fd as usize;
+    assert_eq!(write(fd, b"0123456789"), 10);
+
+    assert_eq!(lseek(fd, 0, SEEK_END), 10);
+    assert_eq!(lseek(fd, 0, SEEK_DATA), 0);
+    assert_eq!(lseek(fd, 3, SEEK_HOLE), 10);
+    assert_eq!(lseek(fd, 10, SEEK_DATA), -ENXIO);
+    assert_eq!(lseek(fd, 4, SEEK_SET), 4);
+    assert_eq!(lseek(fd, 2, SEEK_CUR), 6);
+    let mut buf = [0u8; 4];
+    assert_eq!(read(fd, &mut buf), 4);
+    assert_eq!(&buf, b"6789");
+    assert!(lseek(fd, -1, SEEK_SET) < 0);
+    // before the start, or past what an offset can hold
+    assert_eq!(lseek(fd, -11, SEEK_END), -EINVAL);
+    assert_eq!(lseek(fd, -11, SEEK_CUR), -EINVAL);
+    assert_eq!(lseek(fd, isize::MAX, SEEK_CUR), -EOVERFLOW);
+    assert_eq!(lseek(fd, isize::MAX, SEEK_END), -EOVERFLOW);
+    assert_eq!(lseek(fd, 0, SEEK_CUR), 10);
+    close(fd);
+    println!("lseek passed!");
+    0
+}
@@ -0,0 +1,47 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    brk, madvise, meminfo, sbrk, MemInfo, EINVAL, ENOMEM, MADV_DONTNEED, MADV_WILLNEED,
+};
+
+const PAGE_SIZE: usize = 4096;
+
+fn resident_pages() -> usize {
+    let mut info = MemInfo::default();
+    assert_eq!(meminfo(&mut info), 0);
+    info.resident_pages
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // a few fresh heap pages, not touched yet
+    let base = brk(0) as usize;
+    let start = (base + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+    let len = 4 * PAGE_SIZE;
+    assert!(sbrk((start + len - base) as isize) >= 0);
+    let resident = resident_pages();
+    assert_eq!(madvise(start, len, MADV_WILLNEED), 0);
+    assert_eq!(resident_pages(), resident + 4);
+    let buffer = unsafe { core::slice::from_raw_parts_mut(start as *mut u8, len) };
+    buffer.fill(0x5a);
+    // the middle pages go back, the range stays mapped
+    assert_eq!(madvise(start + PAGE_SIZE, 2 * PAGE_SIZE, MADV_DONTNEED), 0);
+    assert_eq!(resident_pages(), resident + 2);
+    assert!(buffer[PAGE_SIZE..3 * PAGE_SIZE].iter().all(|b| *b == 0));
+    assert!(buffer[..PAGE_SIZE].iter().all(|b| *b == 0x5a));
+    assert!(buffer[3 * PAGE_SIZE..].iter().all(|b| *b == 0x5a));
+    buffer[PAGE_SIZE] = 1;
+    assert_eq!(buffer[PAGE_SIZE], 1);
+    // unaligned, unknown advice and unmapped ranges
+    assert_eq!(madvise(start + 1, PAGE_SIZE, MADV_DONTNEED), -EINVAL);
+    assert_eq!(madvise(start, PAGE_SIZE, 100), -EINVAL);
+    assert_eq!(madvise(start, 64 * PAGE_SIZE, MADV_WILLNEED), -ENOMEM);
+    assert_eq!(madvise(start, 0, MADV_DONTNEED), 0);
+    sbrk(-((start + len - base) as isize));
+    println!("madvise passed!");
+    0
+}
@@ -0,0 +1,30 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{meminfo, sbrk, MemInfo};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut before = MemInfo::default();
+    assert_eq!(meminfo(&mut before), 0);
+    println!("{:?}", before);
+    assert!(before.free_frames > 0 && before.free_frames <= before.total_frames);
+    assert!(before.heap_used <= before.heap_total);
+    assert!(before.resident_pages > 0);
+    // touching fresh heap pages makes them resident
+    let base = sbrk(4 * 4096);
+    assert!(base > 0);
+    for page in 0..4 {
+        unsafe { ((base as usize + page * 4096) as *mut u8).write_volatile(1) };
+    }
+    let mut after = MemInfo::default();
+    assert_eq!(meminfo(&mut after), 0);
+    assert_eq!(after.resident_pages, before.resident_pages + 4);
+    assert!(after.free_frames < before.free_frames);
+    sbrk(-4 * 4096);
+    println!("meminfo passed!");
+    0
+}
@@ -0,0 +1,33 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{brk, mlock, munlock, sbrk};
+
+const PAGE_SIZE: usize = 4096;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // a few fresh heap pages, not touched yet
+    let base = brk(0) as usize;
+    let start = (base + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+    let len = 2 * PAGE_SIZE;
+    assert!(sbrk((start + 3 * PAGE_SIZE - base) as isize) >= 0);
+    assert_eq!(mlock(start, len), 0);
+    let buffer = unsafe { core::slice::from_raw_parts_mut(start as *mut u8, len) };
+    for (i, b) in buffer.iter_mut().enumerate() {
+        *b = i as u8;
+    }
+    assert!(buffer.iter().enumerate().all(|(i, b)| *b == i as u8));
+    assert_eq!(munlock(start, len), 0);
+    // unaligned, empty and unmapped ranges
+    assert_eq!(mlock(start + 1, PAGE_SIZE), -1);
+    assert_eq!(mlock(start, 0), -1);
+    assert_eq!(mlock(0, PAGE_SIZE), -1);
+    assert_eq!(mlock(start, 16 * PAGE_SIZE), -1);
+    sbrk(-((start + 3 * PAGE_SIZE - base) as isize));
+    println!("mlock passed!");
+    0
+}
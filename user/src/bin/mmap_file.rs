@@ -0,0 +1,55 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, mmap, munmap, open, pread, write, OpenFlags, EBADF, MAP_ANONYMOUS, MAP_PRIVATE,
+    MAP_SHARED, PROT_READ, PROT_WRITE,
+};
+
+const PAGE_SIZE: usize = 4096;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let fd = open(
+        "/tmp/mmap_file\0",
+        OpenFlags::CREATE | OpenFlags::RDWR | OpenFlags::TRUNC,
+    );
+    assert!(fd >= 0);
+    let fd = fd as usize;
+    assert_eq!(write(fd, b"hello, mmap"), 11);
+
+    // a private mapping sees the file, but keeps its writes
+    let addr = mmap(0, PAGE_SIZE, PROT_READ | PROT_WRITE, MAP_PRIVATE, fd, 0);
+    assert!(addr > 0);
+    let page = unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, PAGE_SIZE) };
+    assert_eq!(&page[..11], b"hello, mmap");
+    assert!(page[11..].iter().all(|b| *b == 0));
+    page[0] = b'j';
+    assert_eq!(munmap(addr as usize, PAGE_SIZE), PAGE_SIZE as isize);
+    let mut buf = [0u8; 16];
+    assert_eq!(pread(fd, &mut buf, 0), 11);
+    assert_eq!(&buf[..11], b"hello, mmap");
+
+    // a shared one writes back on munmap, without growing the file
+    let addr = mmap(0, PAGE_SIZE, PROT_READ | PROT_WRITE, MAP_SHARED, fd, 0);
+    assert!(addr > 0);
+    let page = unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, PAGE_SIZE) };
+    page[..5].copy_from_slice(b"HELLO");
+    page[20] = b'!';
+    assert_eq!(munmap(addr as usize, PAGE_SIZE), PAGE_SIZE as isize);
+    assert_eq!(pread(fd, &mut buf, 0), 11);
+    assert_eq!(&buf[..11], b"HELLO, mmap");
+
+    // unaligned offsets and bad descriptors
+    assert_eq!(mmap(0, PAGE_SIZE, PROT_READ, MAP_PRIVATE, fd, 1), -1);
+    assert_eq!(mmap(0, PAGE_SIZE, PROT_READ, MAP_PRIVATE, 99, 0), -EBADF);
+    let anon = mmap(0, PAGE_SIZE, PROT_READ, MAP_PRIVATE | MAP_ANONYMOUS, 99, 0);
+    assert!(anon > 0);
+    assert_eq!(munmap(anon as usize, PAGE_SIZE), PAGE_SIZE as isize);
+    close(fd);
+    println!("mmap_file passed!");
+    0
+}
@@ -0,0 +1,42 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::mem::size_of;
+use user_lib::{
+    close, ioctl, perf_event_open, read, yield_, PerfEventAttr, ENOENT,
+    PERF_COUNT_SW_CONTEXT_SWITCHES, PERF_EVENT_IOC_RESET, PERF_TYPE_SOFTWARE,
+};
+
+fn read_count(fd: usize) -> u64 {
+    let mut buf = [0u8; 8];
+    assert_eq!(read(fd, &mut buf), 8);
+    u64::from_ne_bytes(buf)
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut attr = PerfEventAttr {
+        type_: PERF_TYPE_SOFTWARE,
+        size: size_of::<PerfEventAttr>() as u32,
+        config: PERF_COUNT_SW_CONTEXT_SWITCHES,
+    };
+    let fd = perf_event_open(&attr, 0, -1, -1, 0);
+    assert!(fd >= 0);
+    let fd = fd as usize;
+    for _ in 0..10 {
+        yield_();
+    }
+    let count = read_count(fd);
+    assert!(count >= 10, "only {} context switches counted", count);
+    assert_eq!(ioctl(fd, PERF_EVENT_IOC_RESET, 0), 0);
+    assert!(read_count(fd) < count);
+    close(fd);
+
+    attr.config = 42;
+    assert_eq!(perf_event_open(&attr, 0, -1, -1, 0), -ENOENT);
+    println!("perf_event passed!");
+    0
+}
@@ -0,0 +1,32 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, open, pread, pwrite, read, write, OpenFlags, EFBIG, EINVAL};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let fd = open("/tmp/pread\0", OpenFlags::CREATE | OpenFlags::RDWR);
+    assert!(fd >= 0);
+    let fd = fd as usize;
+    assert_eq!(write(fd, b"0123456789"), 10);
+    close(fd);
+
+    let fd = open("/tmp/pread\0", OpenFlags::RDWR) as usize;
+    let mut buf = [0u8; 16];
+    assert_eq!(pread(fd, &mut buf, 5), 5);
+    assert_eq!(&buf[..5], b"56789");
+    assert_eq!(pwrite(fd, b"ab", 2), 2);
+    assert!(pread(fd, &mut buf, -1) < 0);
+    // past the largest offset, or past the largest file
+    assert_eq!(pwrite(fd, b"ab", isize::MAX), -EINVAL);
+    assert_eq!(pwrite(fd, b"ab", 1 << 40), -EFBIG);
+    // neither call moved the file offset
+    assert_eq!(read(fd, &mut buf), 10);
+    assert_eq!(&buf[..10], b"01ab456789");
+    close(fd);
+    println!("pread passed!");
+    0
+}
@@ -0,0 +1,43 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    exit, fork, getpid, getpriority, setpriority, setuid, waitpid, EINVAL, EPERM, ESRCH,
+    PRIO_PROCESS,
+};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(getpriority(PRIO_PROCESS, 0), 20);
+    assert_eq!(setpriority(PRIO_PROCESS, 0, 5), 0);
+    assert_eq!(getpriority(PRIO_PROCESS, getpid() as usize), 15);
+    // out of range values are clamped
+    assert_eq!(setpriority(PRIO_PROCESS, 0, 100), 0);
+    assert_eq!(getpriority(PRIO_PROCESS, 0), 1);
+    assert_eq!(setpriority(PRIO_PROCESS, 0, -100), 0);
+    assert_eq!(getpriority(PRIO_PROCESS, 0), 40);
+    assert_eq!(setpriority(1, 0, 0), -EINVAL);
+    assert_eq!(getpriority(PRIO_PROCESS, 1 << 20), -ESRCH);
+    assert_eq!(setpriority(PRIO_PROCESS, 0, 0), 0);
+
+    // children inherit the nice value, and only root lowers it
+    let child = fork();
+    if child == 0 {
+        assert_eq!(getpriority(PRIO_PROCESS, 0), 20);
+        assert_eq!(setuid(1000), 0);
+        assert_eq!(setpriority(PRIO_PROCESS, 0, 10), 0);
+        assert_eq!(setpriority(PRIO_PROCESS, 0, 5), -EPERM);
+        assert_eq!(getpriority(PRIO_PROCESS, 0), 10);
+        // nor does it touch processes of other users
+        assert_eq!(setpriority(PRIO_PROCESS, 1, 19), -EPERM);
+        exit(0);
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(child as usize, &mut exit_code), child);
+    assert_eq!(exit_code, 0);
+    println!("priority passed!");
+    0
+}
@@ -0,0 +1,45 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    mmap, munmap, prlimit, RLimit, MAP_ANONYMOUS, MAP_PRIVATE, PROT_READ, PROT_WRITE, RLIMIT_AS,
+    RLIMIT_RSS, RLIM_INFINITY,
+};
+
+const PAGE_SIZE: usize = 4096;
+const EINVAL: isize = 22;
+const ENOMEM: isize = 12;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let flags = MAP_PRIVATE | MAP_ANONYMOUS;
+    let mut old = RLimit { cur: 0, max: 0 };
+    assert_eq!(prlimit(0, RLIMIT_RSS, None, Some(&mut old)), 0);
+    assert_eq!(old.cur, RLIM_INFINITY);
+    assert_eq!(prlimit(0, 0, None, Some(&mut old)), -EINVAL);
+    // a soft limit above the hard one
+    let inverted = RLimit {
+        cur: 2 * PAGE_SIZE,
+        max: PAGE_SIZE,
+    };
+    assert_eq!(prlimit(0, RLIMIT_AS, Some(&inverted), None), -EINVAL);
+    // code and stack alone are past a single page
+    let mut limit = RLimit {
+        cur: PAGE_SIZE,
+        max: RLIM_INFINITY,
+    };
+    assert_eq!(prlimit(0, RLIMIT_AS, Some(&limit), Some(&mut old)), 0);
+    assert_eq!(old.cur, RLIM_INFINITY);
+    assert_eq!(mmap(0, PAGE_SIZE, PROT_READ, flags, 0, 0), -ENOMEM);
+    limit.cur = RLIM_INFINITY;
+    assert_eq!(prlimit(0, RLIMIT_AS, Some(&limit), Some(&mut old)), 0);
+    assert_eq!(old.cur, PAGE_SIZE);
+    let start = mmap(0, 16 * PAGE_SIZE, PROT_READ | PROT_WRITE, flags, 0, 0);
+    assert!(start > 0);
+    munmap(start as usize, 16 * PAGE_SIZE);
+    println!("prlimit passed!");
+    0
+}
@@ -0,0 +1,28 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, getpid, open, read, OpenFlags};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let fd = open("/proc/self/status\0", OpenFlags::RDONLY);
+    assert!(fd >= 0, "open /proc/self/status failed");
+    let fd = fd as usize;
+    let mut buf = [0u8; 512];
+    let len = read(fd, &mut buf);
+    close(fd);
+    assert!(len > 0);
+    let status = core::str::from_utf8(&buf[..len as usize]).unwrap();
+    print!("{}", status);
+    let pid = status
+        .lines()
+        .find_map(|line| line.strip_prefix("Pid:"))
+        .map(|pid| pid.trim().parse::<isize>().unwrap())
+        .unwrap();
+    assert_eq!(pid, getpid());
+    println!("proc_status passed!");
+    0
+}
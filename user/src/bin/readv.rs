@@ -0,0 +1,37 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, lseek, open, read, readv, writev, IoVec, OpenFlags, SEEK_SET};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let fd = open("/tmp/readv\0", OpenFlags::CREATE | OpenFlags::RDWR);
+    assert!(fd >= 0);
+    let fd = fd as usize;
+    let (first, second, third) = (b"scatter", [b'-'; 3], "gather\n");
+    let iov = [
+        IoVec::new(first),
+        IoVec::new(&second),
+        IoVec::new(third.as_bytes()),
+    ];
+    assert_eq!(writev(fd, &iov), 17);
+
+    assert_eq!(lseek(fd, 0, SEEK_SET), 0);
+    let mut buf = [0u8; 32];
+    assert_eq!(read(fd, &mut buf), 17);
+    assert_eq!(&buf[..17], b"scatter---gather\n");
+
+    // and back into separate buffers
+    assert_eq!(lseek(fd, 0, SEEK_SET), 0);
+    let (mut head, mut tail) = ([0u8; 4], [0u8; 32]);
+    let iov = [IoVec::new_mut(&mut head), IoVec::new_mut(&mut tail)];
+    assert_eq!(readv(fd, &iov), 17);
+    assert_eq!(&head, b"scat");
+    assert_eq!(&tail[..13], b"ter---gather\n");
+    close(fd);
+    println!("readv passed!");
+    0
+}
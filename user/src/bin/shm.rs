@@ -0,0 +1,34 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exit, fork, shm_attach, shm_create, shm_detach, waitpid, PROT_READ, PROT_WRITE};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let id = shm_create(4096);
+    assert!(id > 0);
+    let addr = shm_attach(id as usize, 0, PROT_READ | PROT_WRITE);
+    assert!(addr > 0);
+    let shared = unsafe { &mut *(addr as *mut u64) };
+    *shared = 1;
+    let pid = fork();
+    if pid == 0 {
+        // the child writes through its own mapping of the segment
+        let other = shm_attach(id as usize, 0, PROT_READ | PROT_WRITE);
+        assert!(other > 0 && other != addr);
+        unsafe { *(other as *mut u64) = 42 };
+        assert_eq!(shm_detach(other as usize), 0);
+        exit(0);
+    }
+    let mut exit_code = 0;
+    waitpid(pid as usize, &mut exit_code);
+    assert_eq!(exit_code, 0);
+    assert_eq!(unsafe { core::ptr::read_volatile(shared) }, 42);
+    assert_eq!(shm_detach(addr as usize), 0);
+    assert_eq!(shm_detach(addr as usize), -1);
+    println!("shm passed!");
+    0
+}
@@ -0,0 +1,23 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{get_time, getpid};
+
+const ROUNDS: usize = 100_000;
+
+/// Time a round trip through the kernel, which runs on the kernel stack.
+#[no_mangle]
+pub fn main() -> i32 {
+    let pid = getpid();
+    let start = get_time();
+    for _ in 0..ROUNDS {
+        assert_eq!(getpid(), pid);
+    }
+    let elapsed = get_time() - start;
+    println!("{} syscalls in {} ms", ROUNDS, elapsed);
+    println!("syscall_bench passed!");
+    0
+}
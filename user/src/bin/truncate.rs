@@ -0,0 +1,48 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, ftruncate, open, read, truncate, write, OpenFlags, EFBIG};
+
+const PATH: &str = "/tmp/truncate\0";
+
+fn read_all(buf: &mut [u8]) -> usize {
+    let fd = open(PATH, OpenFlags::RDONLY);
+    assert!(fd >= 0);
+    let len = read(fd as usize, buf);
+    close(fd as usize);
+    assert!(len >= 0);
+    len as usize
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let fd = open(PATH, OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd >= 0);
+    assert_eq!(write(fd as usize, b"0123456789"), 10);
+    close(fd as usize);
+
+    let mut buf = [0xffu8; 32];
+    assert_eq!(truncate(PATH, 5), 0);
+    assert_eq!(read_all(&mut buf), 5);
+    assert_eq!(&buf[..5], b"01234");
+
+    let mut buf = [0xffu8; 32];
+    assert_eq!(truncate(PATH, 20), 0);
+    assert_eq!(read_all(&mut buf), 20);
+    assert_eq!(&buf[..5], b"01234");
+    assert!(buf[5..20].iter().all(|b| *b == 0));
+
+    let fd = open(PATH, OpenFlags::RDWR);
+    assert!(fd >= 0);
+    // files live in kernel memory, and only grow so far
+    assert_eq!(ftruncate(fd as usize, 1 << 40), -EFBIG);
+    assert_eq!(ftruncate(fd as usize, usize::MAX), -EFBIG);
+    assert_eq!(ftruncate(fd as usize, 0), 0);
+    close(fd as usize);
+    assert_eq!(read_all(&mut buf), 0);
+    println!("truncate passed!");
+    0
+}
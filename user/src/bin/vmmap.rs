@@ -0,0 +1,54 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{shm_attach, shm_create, shm_detach, vmmap, PROT_READ, PROT_WRITE};
+
+/// Whether some area of the map starts at `start`.
+fn has_area_at(map: &str, start: usize) -> bool {
+    map.lines().any(|line| {
+        line.split('-')
+            .next()
+            .and_then(|s| s.strip_prefix("0x"))
+            .and_then(|s| usize::from_str_radix(s, 16).ok())
+            == Some(start)
+    })
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut buf = [0u8; 2048];
+    let len = vmmap(&mut buf);
+    assert!(len > 0);
+    let map = core::str::from_utf8(&buf[..len as usize]).unwrap();
+    print!("{}", map);
+    for line in map.lines() {
+        // range, permissions, map type and resident/all pages
+        let mut fields = line.split(' ');
+        assert_eq!(fields.nth(1).unwrap().len(), 4);
+        let mut pages = fields.nth(1).unwrap().split('/');
+        let resident: usize = pages.next().unwrap().parse().unwrap();
+        let total: usize = pages.next().unwrap().parse().unwrap();
+        assert!(resident <= total);
+    }
+    // a new mapping shows up, and goes again with it
+    let id = shm_create(4096);
+    let addr = shm_attach(id as usize, 0, PROT_READ | PROT_WRITE);
+    assert!(addr > 0);
+    let len = vmmap(&mut buf);
+    let map = core::str::from_utf8(&buf[..len as usize]).unwrap();
+    assert!(has_area_at(map, addr as usize));
+    assert!(map.contains("rw-u Shared"));
+    assert_eq!(shm_detach(addr as usize), 0);
+    let len = vmmap(&mut buf);
+    let map = core::str::from_utf8(&buf[..len as usize]).unwrap();
+    assert!(!has_area_at(map, addr as usize));
+    // a short buffer is filled up to its end
+    let mut short = [0u8; 2];
+    assert_eq!(vmmap(&mut short), 2);
+    assert_eq!(&short, b"0x");
+    println!("vmmap passed!");
+    0
+}
@@ -79,16 +79,79 @@ bitflags! {
         const RDWR = 1 << 1;
         const CREATE = 1 << 9;
         const TRUNC = 1 << 10;
+        const NONBLOCK = 1 << 11;
     }
 }
 
+pub const EPERM: isize = 1;
+pub const ENOENT: isize = 2;
+pub const ESRCH: isize = 3;
+pub const ENXIO: isize = 6;
+pub const EBADF: isize = 9;
+pub const EAGAIN: isize = 11;
+pub const ENOMEM: isize = 12;
+pub const EINVAL: isize = 22;
+pub const EFBIG: isize = 27;
+pub const EOVERFLOW: isize = 75;
+
+pub const F_DUPFD: usize = 0;
+pub const F_GETFD: usize = 1;
+pub const F_SETFD: usize = 2;
+pub const F_GETFL: usize = 3;
+pub const F_SETFL: usize = 4;
+pub const F_DUPFD_CLOEXEC: usize = 1030;
+pub const FD_CLOEXEC: usize = 1;
+
+pub const PERF_TYPE_SOFTWARE: u32 = 1;
+pub const PERF_COUNT_SW_PAGE_FAULTS: u64 = 2;
+pub const PERF_COUNT_SW_CONTEXT_SWITCHES: u64 = 3;
+pub const PERF_EVENT_IOC_RESET: usize = 0x2403;
+
+pub const CLOCK_REALTIME: usize = 0;
+pub const CLOCK_MONOTONIC: usize = 1;
+pub const ADJ_OFFSET: u32 = 0x0001;
+pub const ADJ_FREQUENCY: u32 = 0x0002;
+
+pub const PROT_NONE: usize = 0;
+pub const PROT_READ: usize = 1;
+pub const PROT_WRITE: usize = 2;
+pub const PROT_EXEC: usize = 4;
+
+pub const MAP_SHARED: usize = 0x01;
+pub const MAP_PRIVATE: usize = 0x02;
+pub const MAP_FIXED: usize = 0x10;
+pub const MAP_ANONYMOUS: usize = 0x20;
+
+pub const MADV_WILLNEED: usize = 3;
+pub const MADV_DONTNEED: usize = 4;
+
+pub const SEEK_SET: usize = 0;
+pub const SEEK_CUR: usize = 1;
+pub const SEEK_END: usize = 2;
+pub const SEEK_DATA: usize = 3;
+pub const SEEK_HOLE: usize = 4;
+
 pub fn dup(fd: usize) -> isize {
     sys_dup(fd)
 }
+pub fn ioctl(fd: usize, cmd: usize, arg: usize) -> isize {
+    sys_ioctl(fd, cmd, arg)
+}
+pub fn fcntl(fd: usize, cmd: usize, arg: usize) -> isize {
+    sys_fcntl(fd, cmd, arg)
+}
 pub fn open(path: &str, flags: OpenFlags) -> isize {
     sys_open(path, flags.bits)
 }
 
+pub fn truncate(path: &str, len: usize) -> isize {
+    sys_truncate(path, len)
+}
+
+pub fn ftruncate(fd: usize, len: usize) -> isize {
+    sys_ftruncate(fd, len)
+}
+
 pub fn close(fd: usize) -> isize {
     sys_close(fd)
 }
@@ -97,6 +160,10 @@ pub fn pipe(pipe_fd: &mut [usize]) -> isize {
     sys_pipe(pipe_fd)
 }
 
+pub fn lseek(fd: usize, offset: isize, whence: usize) -> isize {
+    sys_lseek(fd, offset, whence)
+}
+
 pub fn read(fd: usize, buf: &mut [u8]) -> isize {
     sys_read(fd, buf)
 }
@@ -104,14 +171,69 @@ pub fn read(fd: usize, buf: &mut [u8]) -> isize {
 pub fn write(fd: usize, buf: &[u8]) -> isize {
     sys_write(fd, buf)
 }
+pub fn readv(fd: usize, iov: &[IoVec]) -> isize {
+    sys_readv(fd, iov)
+}
+
+pub fn writev(fd: usize, iov: &[IoVec]) -> isize {
+    sys_writev(fd, iov)
+}
+
+pub fn pread(fd: usize, buf: &mut [u8], offset: isize) -> isize {
+    sys_pread64(fd, buf, offset)
+}
+
+pub fn pwrite(fd: usize, buf: &[u8], offset: isize) -> isize {
+    sys_pwrite64(fd, buf, offset)
+}
 pub fn exit(exit_code: i32) -> ! {
     sys_exit(exit_code);
 }
 pub fn yield_() -> isize {
     sys_yield()
 }
+/// The leading fields of `struct perf_event_attr`.
 #[repr(C)]
-#[derive(Debug)]
+pub struct PerfEventAttr {
+    pub type_: u32,
+    pub size: u32,
+    pub config: u64,
+}
+
+pub fn perf_event_open(
+    attr: &PerfEventAttr,
+    pid: isize,
+    cpu: isize,
+    group_fd: isize,
+    flags: usize,
+) -> isize {
+    sys_perf_event_open(attr, pid, cpu, group_fd, flags)
+}
+
+/// One buffer of a `readv`/`writev` call.
+#[repr(C)]
+pub struct IoVec {
+    pub iov_base: *mut u8,
+    pub iov_len: usize,
+}
+
+impl IoVec {
+    pub fn new(buf: &[u8]) -> Self {
+        Self {
+            iov_base: buf.as_ptr() as *mut u8,
+            iov_len: buf.len(),
+        }
+    }
+    pub fn new_mut(buf: &mut [u8]) -> Self {
+        Self {
+            iov_base: buf.as_mut_ptr(),
+            iov_len: buf.len(),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
 pub struct TimeVal {
     pub sec: usize,
     pub usec: usize,
@@ -123,6 +245,57 @@ impl TimeVal {
     }
 }
 
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct TimeSpec {
+    pub sec: usize,
+    pub nsec: usize,
+}
+
+impl TimeSpec {
+    pub fn as_nanos(&self) -> i64 {
+        (self.sec * 1_000_000_000 + self.nsec) as i64
+    }
+}
+
+/// The kernel's own `struct timex`, not the Linux one: `offset` is in
+/// nanoseconds and `freq` in parts per billion.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Timex {
+    pub modes: u32,
+    pub offset: i64,
+    pub freq: i64,
+}
+
+pub fn clock_gettime(clock_id: usize, tp: &mut TimeSpec) -> isize {
+    sys_clock_gettime(clock_id, tp)
+}
+pub fn clock_adjtime(clock_id: usize, timex: &mut Timex) -> isize {
+    sys_clock_adjtime(clock_id, timex)
+}
+pub fn ntp_adjtime(timex: &mut Timex) -> isize {
+    sys_adjtimex(timex)
+}
+
+/// `who` of `getrusage`
+pub const RUSAGE_SELF: isize = 0;
+pub const RUSAGE_CHILDREN: isize = -1;
+
+/// `struct rusage`, of which the kernel only fills in the CPU time.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct RUsage {
+    pub utime: TimeVal,
+    pub stime: TimeVal,
+    pub counters: [isize; 14],
+}
+
+/// The CPU time of this process, or of the children it waited for.
+pub fn getrusage(who: isize, usage: &mut RUsage) -> isize {
+    sys_getrusage(who, usage)
+}
+
 pub fn get_time() -> isize {
     let time = TimeVal::new();
     match sys_get_time(&time, 0) {
@@ -133,6 +306,49 @@ pub fn get_time() -> isize {
 pub fn getpid() -> isize {
     sys_getpid()
 }
+pub fn getppid() -> isize {
+    sys_getppid()
+}
+pub fn getuid() -> isize {
+    sys_getuid()
+}
+pub fn geteuid() -> isize {
+    sys_geteuid()
+}
+pub fn getgid() -> isize {
+    sys_getgid()
+}
+pub fn getegid() -> isize {
+    sys_getegid()
+}
+pub fn setuid(uid: u32) -> isize {
+    sys_setuid(uid)
+}
+pub fn setgid(gid: u32) -> isize {
+    sys_setgid(gid)
+}
+
+/// `which` of `setpriority` and `getpriority`
+pub const PRIO_PROCESS: usize = 0;
+
+/// Set the nice value of process `who`, 0 for this one, from -20, the most
+/// favoured, to 19.
+pub fn setpriority(which: usize, who: usize, nice: isize) -> isize {
+    sys_setpriority(which, who, nice)
+}
+/// The nice value of process `who` as `20 - nice`, or an error below 0.
+pub fn getpriority(which: usize, who: usize) -> isize {
+    sys_getpriority(which, who)
+}
+/// Let process `pid`, 0 for this one, run only on the harts whose bits
+/// are set in `mask`.
+pub fn sched_setaffinity(pid: usize, mask: usize) -> isize {
+    sys_sched_setaffinity(pid, &mask)
+}
+/// Read the harts process `pid` may run on into `mask`.
+pub fn sched_getaffinity(pid: usize, mask: &mut usize) -> isize {
+    sys_sched_getaffinity(pid, mask)
+}
 pub fn fork() -> isize {
     sys_fork()
 }
@@ -142,6 +358,61 @@ pub fn exec(path: &str, args: &[*const u8]) -> isize {
 pub fn spawn(path: &str) -> isize {
     sys_spawn(path)
 }
+/// Map `len` bytes with `PROT_*` bits and `MAP_*` flags, of the file open
+/// as `fd` from `offset` unless `MAP_ANONYMOUS` is given. Returns the
+/// address.
+pub fn mmap(
+    start: usize,
+    len: usize,
+    prot: usize,
+    flags: usize,
+    fd: usize,
+    offset: usize,
+) -> isize {
+    sys_mmap(start, len, prot, flags, fd, offset)
+}
+pub fn munmap(start: usize, len: usize) -> isize {
+    sys_munmap(start, len)
+}
+/// Change the protection of `[start, start + len)` to `PROT_*` bits.
+pub fn mprotect(start: usize, len: usize, prot: usize) -> isize {
+    sys_mprotect(start, len, prot)
+}
+/// Keep the pages of `[start, start + len)` resident, e.g. a buffer a
+/// user interrupt handler touches. `start` must be page aligned.
+pub fn mlock(start: usize, len: usize) -> isize {
+    sys_mlock(start, len)
+}
+pub fn munlock(start: usize, len: usize) -> isize {
+    sys_munlock(start, len)
+}
+/// Hint how `[start, start + len)` will be used with a `MADV_*` value.
+/// After `MADV_DONTNEED` anonymous pages read as zero again.
+pub fn madvise(start: usize, len: usize, advice: usize) -> isize {
+    sys_madvise(start, len, advice)
+}
+/// Set the program break, 0 just returns it. Returns the break after the
+/// call, which is the old one if it could not move.
+pub fn brk(addr: usize) -> isize {
+    sys_brk(addr)
+}
+/// Move the program break by `increment` bytes and return the old break,
+/// or -1 if it could not move.
+pub fn sbrk(increment: isize) -> isize {
+    let old = sys_brk(0);
+    if old < 0 || increment == 0 {
+        return old;
+    }
+    let new = old + increment;
+    if sys_brk(new as usize) != new {
+        return -1;
+    }
+    old
+}
+/// Resize the mapping at `old_start`, returning where it ends up.
+pub fn mremap(old_start: usize, old_len: usize, new_len: usize) -> isize {
+    sys_mremap(old_start, old_len, new_len)
+}
 pub fn wait(exit_code: &mut i32) -> isize {
     loop {
         match sys_waitpid(-1, exit_code as *mut _) {
@@ -191,3 +462,85 @@ pub fn claim_ext_int(device_id: usize) -> isize {
 pub fn set_ext_int_enable(device_id: usize, enable: usize) -> isize {
     sys_set_ext_int_enable(device_id, enable)
 }
+
+/// Live tagged kernel heap allocations as text, see the kernel's
+/// `heap_tracking` feature.
+pub fn heap_stats(buf: &mut [u8]) -> isize {
+    sys_heap_stats(buf)
+}
+
+/// Make a shared memory segment of at least `len` bytes, returning its id.
+pub fn shm_create(len: usize) -> isize {
+    sys_shm_create(len)
+}
+
+/// Map segment `id` with `PROT_*` bits at `start`, or anywhere for 0.
+/// Returns the address.
+pub fn shm_attach(id: usize, start: usize, prot: usize) -> isize {
+    sys_shm_attach(id, start, prot)
+}
+
+pub fn shm_detach(start: usize) -> isize {
+    sys_shm_detach(start)
+}
+
+/// Memory usage: frames and pages are 4 KiB each, the kernel heap is in
+/// bytes.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct MemInfo {
+    pub total_frames: usize,
+    pub free_frames: usize,
+    pub heap_total: usize,
+    pub heap_used: usize,
+    /// Pages backed by frames in this process.
+    pub resident_pages: usize,
+}
+
+pub fn meminfo(info: &mut MemInfo) -> isize {
+    sys_meminfo(info)
+}
+
+pub const RLIM_INFINITY: usize = usize::MAX;
+/// Bytes of pages backed by frames
+pub const RLIMIT_RSS: usize = 5;
+/// Bytes of mappings
+pub const RLIMIT_AS: usize = 9;
+
+/// A limit enforced at `cur`, which may be moved up to `max`. Only root
+/// raises `max`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RLimit {
+    pub cur: usize,
+    pub max: usize,
+}
+
+/// Set the limit on `resource` of process `pid`, 0 for this one, to
+/// `new_limit` if given, and read the old one into `old_limit`.
+pub fn prlimit(
+    pid: usize,
+    resource: usize,
+    new_limit: Option<&RLimit>,
+    old_limit: Option<&mut RLimit>,
+) -> isize {
+    sys_prlimit(pid, resource, new_limit, old_limit)
+}
+
+/// The areas of this process as text, one per line: range, permissions,
+/// map type and resident out of all pages.
+pub fn vmmap(buf: &mut [u8]) -> isize {
+    sys_vmmap(buf)
+}
+
+/// Save this process to the file open as `fd`. Returns 0, then 1 once
+/// more when `restore` resumes from the checkpoint.
+pub fn checkpoint(fd: usize) -> isize {
+    sys_checkpoint(fd)
+}
+
+/// Resume from the checkpoint in the file open as `fd`, in place of this
+/// process. Only returns on failure.
+pub fn restore(fd: usize) -> isize {
+    sys_restore(fd)
+}